@@ -0,0 +1,417 @@
+// Offline analysis of finished recordings
+use crate::media;
+use crate::MousePosition;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+const ANALYSIS_WIDTH: u32 = 64; // Frames are compared as small grayscale thumbnails
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrimOptions {
+    pub threshold: f32, // Mean per-pixel difference (0-1) that counts as a meaningful change
+    pub padding_secs: f64, // Kept before the first and after the last change
+    pub use_mouse_track: bool, // Also treat the first/last real mouse movement as activity
+    pub mouse_threshold_px: f64, // Movement smaller than this is ignored
+}
+
+impl Default for TrimOptions {
+    fn default() -> Self {
+        Self {
+            threshold: 0.02,
+            padding_secs: 0.5,
+            use_mouse_track: true,
+            mouse_threshold_px: 20.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrimPoints {
+    pub start_secs: f64,
+    pub end_secs: f64,
+    pub duration: f64,
+}
+
+fn luma_thumbnail(frame: &media::DecodedFrame) -> Vec<u8> {
+    let (thumb, _, _) =
+        crate::downscale_bgra(frame.data, frame.width, frame.height, ANALYSIS_WIDTH);
+    thumb
+        .chunks_exact(4)
+        .map(|px| ((px[0] as u32 * 29 + px[1] as u32 * 150 + px[2] as u32 * 77) >> 8) as u8)
+        .collect()
+}
+
+fn mean_difference(a: &[u8], b: &[u8]) -> f32 {
+    if a.is_empty() || a.len() != b.len() {
+        return 0.0;
+    }
+    let total: u64 = a
+        .iter()
+        .zip(b)
+        .map(|(&x, &y)| (x as i32 - y as i32).unsigned_abs() as u64)
+        .sum();
+    total as f32 / (a.len() as f32 * 255.0)
+}
+
+// First and last time the cursor really moved, measured from where it started and where it ended
+fn mouse_activity(positions: &[MousePosition], threshold_px: f64) -> Option<(f64, f64)> {
+    let first = positions.first()?;
+    let last = positions.last()?;
+    let moved_from = |origin: &MousePosition, p: &MousePosition| {
+        (((p.x - origin.x) as f64).powi(2) + ((p.y - origin.y) as f64).powi(2)).sqrt()
+            > threshold_px
+    };
+
+    let start = positions.iter().find(|p| moved_from(first, p))?.timestamp;
+    let end = positions
+        .iter()
+        .rev()
+        .find(|p| moved_from(last, p))?
+        .timestamp;
+    Some((start, end))
+}
+
+// Suggest trim points by finding the first frame that differs from the opening (static) frame
+// and the last frame that differs from the one before it.
+pub fn detect_trim_points(
+    path: &str,
+    options: &TrimOptions,
+    mouse_positions: &[MousePosition],
+) -> Result<TrimPoints, String> {
+    let mut opening: Option<Vec<u8>> = None;
+    let mut previous: Option<Vec<u8>> = None;
+    let mut first_change: Option<f64> = None;
+    let mut last_change: Option<f64> = None;
+    let mut duration = 0.0;
+
+    media::for_each_video_frame(path, |frame| {
+        let luma = luma_thumbnail(&frame);
+        duration = frame.timestamp;
+
+        if first_change.is_none() {
+            if let Some(opening) = &opening {
+                if mean_difference(opening, &luma) > options.threshold {
+                    first_change = Some(frame.timestamp);
+                }
+            }
+        }
+        if let Some(previous) = &previous {
+            if mean_difference(previous, &luma) > options.threshold {
+                last_change = Some(frame.timestamp);
+            }
+        }
+
+        opening.get_or_insert_with(|| luma.clone());
+        previous = Some(luma);
+        true
+    })?;
+
+    let mut start = first_change;
+    let mut end = last_change;
+    if options.use_mouse_track {
+        if let Some((mouse_start, mouse_end)) =
+            mouse_activity(mouse_positions, options.mouse_threshold_px)
+        {
+            start = Some(start.map_or(mouse_start, |s| s.min(mouse_start)));
+            end = Some(end.map_or(mouse_end, |e| e.max(mouse_end)));
+        }
+    }
+
+    let start_secs = start
+        .map(|s| (s - options.padding_secs).max(0.0))
+        .unwrap_or(0.0);
+    let end_secs = end
+        .map(|e| (e + options.padding_secs).min(duration))
+        .unwrap_or(duration)
+        .max(start_secs);
+
+    info!(
+        "Suggested trim: {:.2}s - {:.2}s of {:.2}s",
+        start_secs, end_secs, duration
+    );
+    Ok(TrimPoints {
+        start_secs,
+        end_secs,
+        duration,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ZoomOptions {
+    pub radius_px: f64, // Samples within this distance of a cluster's anchor belong to it
+    pub min_dwell_secs: f64, // A cluster without clicks needs the cursor to rest this long
+    pub min_clicks: u32, // A cluster with at least this many presses qualifies regardless of dwell
+    pub merge_gap_secs: f64, // Neighbouring clusters closer than this in time and space are merged
+    pub lead_in_secs: f64, // Start zooming this long before the cluster begins
+    pub max_suggestions: usize, // Keep the highest scoring clusters, 0 for no limit
+}
+
+impl Default for ZoomOptions {
+    fn default() -> Self {
+        Self {
+            radius_px: 120.0,
+            min_dwell_secs: 1.5,
+            min_clicks: 1,
+            merge_gap_secs: 1.0,
+            lead_in_secs: 0.3,
+            max_suggestions: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoomSuggestion {
+    pub x: f64, // Zoom center, 0-1 across the recording
+    pub y: f64,
+    pub timestamp: f64,
+    pub duration: f64,
+    pub clicks: u32,
+    pub score: f64, // Dwell seconds plus one per click, higher is a stronger candidate
+}
+
+struct Cluster {
+    sum_x: f64,
+    sum_y: f64,
+    samples: u32,
+    start: f64,
+    end: f64,
+    clicks: u32,
+}
+
+impl Cluster {
+    fn new(p: &MousePosition) -> Self {
+        Self {
+            sum_x: p.x as f64,
+            sum_y: p.y as f64,
+            samples: 1,
+            start: p.timestamp,
+            end: p.timestamp,
+            clicks: 0,
+        }
+    }
+
+    fn center(&self) -> (f64, f64) {
+        (
+            self.sum_x / self.samples as f64,
+            self.sum_y / self.samples as f64,
+        )
+    }
+
+    fn absorb(&mut self, other: Cluster) {
+        self.sum_x += other.sum_x;
+        self.sum_y += other.sum_y;
+        self.samples += other.samples;
+        self.end = other.end;
+        self.clicks += other.clicks;
+    }
+}
+
+// Group the track into places where the cursor rested or clicked. Samples join the current cluster
+// while they stay within radius_px of its center; a press is counted on the sample it first shows up.
+pub fn suggest_zoom_points(
+    positions: &[MousePosition],
+    width: u32,
+    height: u32,
+    options: &ZoomOptions,
+) -> Vec<ZoomSuggestion> {
+    let mut clusters: Vec<Cluster> = Vec::new();
+    let mut current: Option<Cluster> = None;
+    let mut was_clicked = false;
+
+    for p in positions {
+        let pressed = p.isClicked && (!was_clicked || p.click_type.is_some());
+        was_clicked = p.isClicked;
+
+        let joins = current.as_ref().is_some_and(|cluster| {
+            let (cx, cy) = cluster.center();
+            ((p.x as f64 - cx).powi(2) + (p.y as f64 - cy).powi(2)).sqrt() <= options.radius_px
+        });
+        if joins {
+            let cluster = current.as_mut().unwrap();
+            cluster.sum_x += p.x as f64;
+            cluster.sum_y += p.y as f64;
+            cluster.samples += 1;
+            cluster.end = p.timestamp;
+        } else {
+            clusters.extend(current.take());
+            current = Some(Cluster::new(p));
+        }
+        if pressed {
+            current.as_mut().unwrap().clicks += 1;
+        }
+    }
+    clusters.extend(current);
+
+    let qualifies = |c: &Cluster| {
+        (options.min_clicks > 0 && c.clicks >= options.min_clicks)
+            || c.end - c.start >= options.min_dwell_secs
+    };
+
+    // Merge qualifying clusters that are really one activity interrupted by a small movement
+    let mut merged: Vec<Cluster> = Vec::new();
+    for cluster in clusters.into_iter().filter(qualifies) {
+        if let Some(last) = merged.last_mut() {
+            let (lx, ly) = last.center();
+            let (cx, cy) = cluster.center();
+            let close = ((lx - cx).powi(2) + (ly - cy).powi(2)).sqrt() <= options.radius_px;
+            if close && cluster.start - last.end <= options.merge_gap_secs {
+                last.absorb(cluster);
+                continue;
+            }
+        }
+        merged.push(cluster);
+    }
+
+    let (width, height) = (width.max(1) as f64, height.max(1) as f64);
+    let mut suggestions: Vec<ZoomSuggestion> = merged
+        .iter()
+        .map(|c| {
+            let (cx, cy) = c.center();
+            let timestamp = (c.start - options.lead_in_secs).max(0.0);
+            ZoomSuggestion {
+                x: (cx / width).clamp(0.0, 1.0),
+                y: (cy / height).clamp(0.0, 1.0),
+                timestamp,
+                duration: c.end - timestamp,
+                clicks: c.clicks,
+                score: (c.end - c.start) + c.clicks as f64,
+            }
+        })
+        .collect();
+
+    if options.max_suggestions > 0 && suggestions.len() > options.max_suggestions {
+        suggestions.sort_by(|a, b| b.score.total_cmp(&a.score));
+        suggestions.truncate(options.max_suggestions);
+        suggestions.sort_by(|a, b| a.timestamp.total_cmp(&b.timestamp));
+    }
+
+    info!(
+        "Suggested {} zoom points from {} mouse samples",
+        suggestions.len(),
+        positions.len()
+    );
+    suggestions
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrailOptions {
+    pub max_gap_secs: f64, // Consecutive samples further apart than this aren't connected
+    pub min_distance_px: f64, // Shorter moves (including a resting cursor) produce no segment
+}
+
+impl Default for TrailOptions {
+    fn default() -> Self {
+        Self {
+            max_gap_secs: 0.1,
+            min_distance_px: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrailSegment {
+    pub from: (i32, i32),
+    pub to: (i32, i32),
+    pub t_start: f64,
+    pub t_end: f64,
+}
+
+// Line segments between consecutive mouse samples, for drawing a cursor trail
+pub fn build_trail_segments(
+    positions: &[MousePosition],
+    options: &TrailOptions,
+) -> Vec<TrailSegment> {
+    positions
+        .windows(2)
+        .filter_map(|pair| {
+            let (a, b) = (&pair[0], &pair[1]);
+            let gap = b.timestamp - a.timestamp;
+            let distance = (((b.x - a.x) as f64).powi(2) + ((b.y - a.y) as f64).powi(2)).sqrt();
+            if gap <= 0.0 || gap > options.max_gap_secs || distance < options.min_distance_px {
+                return None;
+            }
+            Some(TrailSegment {
+                from: (a.x, a.y),
+                to: (b.x, b.y),
+                t_start: a.timestamp,
+                t_end: b.timestamp,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChapterOptions {
+    pub min_gap_secs: f64, // Inactivity at least this long starts a new chapter when activity resumes
+    pub movement_threshold_px: f64, // Smaller moves (jitter, a nudged mouse) don't count as activity
+    pub min_chapter_secs: f64, // Gaps are ignored until the current chapter is at least this long
+}
+
+impl Default for ChapterOptions {
+    fn default() -> Self {
+        Self {
+            min_gap_secs: 5.0,
+            movement_threshold_px: 10.0,
+            min_chapter_secs: 10.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chapter {
+    pub title_placeholder: String, // "Chapter N", for the user to rename
+    pub start_secs: f64,
+}
+
+// Split a recording into chapters at long stretches without cursor movement or clicks.
+// The first chapter always starts at 0.
+pub fn detect_chapters(positions: &[MousePosition], options: &ChapterOptions) -> Vec<Chapter> {
+    let mut starts = vec![0.0];
+    let mut anchor: Option<(i32, i32)> = None; // Position of the last activity
+    let mut last_activity: Option<f64> = None;
+    let mut was_clicked = false;
+
+    for position in positions {
+        let moved = match anchor {
+            Some((x, y)) => {
+                (((position.x - x) as f64).powi(2) + ((position.y - y) as f64).powi(2)).sqrt()
+                    >= options.movement_threshold_px
+            }
+            None => true,
+        };
+        let pressed = position.isClicked && !was_clicked;
+        was_clicked = position.isClicked;
+        if !moved && !pressed {
+            continue;
+        }
+
+        let chapter_start = starts.last().copied().unwrap_or(0.0);
+        if let Some(last) = last_activity {
+            if position.timestamp - last >= options.min_gap_secs
+                && position.timestamp - chapter_start >= options.min_chapter_secs
+            {
+                starts.push(position.timestamp);
+            }
+        }
+        anchor = Some((position.x, position.y));
+        last_activity = Some(position.timestamp);
+    }
+
+    info!(
+        "Detected {} chapters from {} mouse samples",
+        starts.len(),
+        positions.len()
+    );
+    starts
+        .into_iter()
+        .enumerate()
+        .map(|(index, start_secs)| Chapter {
+            title_placeholder: format!("Chapter {}", index + 1),
+            start_secs,
+        })
+        .collect()
+}
@@ -0,0 +1,92 @@
+// Capture/encode throughput benchmark: records the monitor into a throwaway file for a fixed time
+// and reports how well the pipeline keeps up
+use crate::capture_test::run_throwaway;
+use log::info;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use windows::Win32::System::ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS};
+use windows::Win32::System::Threading::GetCurrentProcess;
+use windows_capture::monitor::Monitor;
+
+pub const MAX_SECONDS: u32 = 60;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BenchmarkResult {
+    pub width: u32,
+    pub height: u32,
+    pub duration: f64, // Seconds actually measured, from the first frame to the end
+    pub frames_captured: u32,
+    pub frames_encoded: u32,
+    pub capture_fps: f64,
+    pub encode_fps: f64,
+    // Gaps longer than DROP_THRESHOLD_FACTOR intervals, as in a recording. Windows only delivers
+    // frames when the screen changes, so an idle screen inflates this.
+    pub dropped_frames: u32,
+    pub avg_frame_ms: f64, // Time spent in the frame callback, encoding included
+    pub peak_memory_mb: f64, // Peak working set of the whole process during the run
+}
+
+pub fn working_set_bytes() -> usize {
+    let mut counters = PROCESS_MEMORY_COUNTERS {
+        cb: std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32,
+        ..Default::default()
+    };
+    unsafe {
+        if GetProcessMemoryInfo(GetCurrentProcess(), &mut counters, counters.cb).as_bool() {
+            counters.WorkingSetSize
+        } else {
+            0
+        }
+    }
+}
+
+pub fn run(monitor: Monitor, seconds: u32) -> Result<BenchmarkResult, String> {
+    let width = monitor.width().map_err(|e| e.to_string())?;
+    let height = monitor.height().map_err(|e| e.to_string())?;
+    info!(
+        "Benchmarking capture at {}x{} for {}s",
+        width, height, seconds
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(seconds as u64);
+    let mut peak_memory = working_set_bytes();
+    let result = run_throwaway(
+        monitor,
+        width,
+        height,
+        "screen_demo_benchmark",
+        None,
+        || {
+            peak_memory = peak_memory.max(working_set_bytes());
+            Instant::now() < deadline
+        },
+    )
+    .map_err(|failure| failure.encoder_error.unwrap_or(failure.capture_error))
+    .and_then(|run| {
+        // Report the first failure: frame submission, stopping the session, then finalization
+        if let Some(e) = run.send_error.or(run.stop_error).or(run.finish_error) {
+            return Err(e);
+        }
+        let stats = run.stats;
+        let first_frame_at = stats
+            .first_frame_at
+            .ok_or("No frames arrived from the monitor")?;
+        let duration = run.ended.duration_since(first_frame_at).as_secs_f64();
+        Ok(BenchmarkResult {
+            width,
+            height,
+            duration,
+            frames_captured: stats.frames_received,
+            frames_encoded: stats.frames_encoded,
+            capture_fps: stats.frames_received as f64 / duration,
+            encode_fps: stats.frames_encoded as f64 / duration,
+            dropped_frames: stats.dropped_frames,
+            avg_frame_ms: stats.processing_time.as_secs_f64() * 1000.0
+                / stats.frames_received as f64,
+            peak_memory_mb: peak_memory as f64 / (1024.0 * 1024.0),
+        })
+    });
+
+    info!("Benchmark result: {:?}", result);
+    result
+}
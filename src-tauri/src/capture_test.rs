@@ -0,0 +1,316 @@
+// Dry-run of the capture pipeline: encoder creation, monitor access and frame delivery, without
+// keeping a recording around
+use log::{info, warn};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use windows_capture::{
+    capture::{Context, GraphicsCaptureApiHandler},
+    encoder::{AudioSettingsBuilder, ContainerSettingsBuilder, VideoEncoder, VideoSettingsBuilder},
+    frame::Frame,
+    graphics_capture_api::{GraphicsCaptureApi, InternalCaptureControl},
+    monitor::Monitor,
+    settings::{ColorFormat, CursorCaptureSettings, DrawBorderSettings, Settings},
+};
+
+const TEST_FRAMES: u32 = 5;
+const ACCESS_DENIED: &str = "0x80070005"; // E_ACCESSDENIED, as it appears in Windows error messages
+const TEST_TIMEOUT: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureTestResult {
+    pub success: bool,
+    pub width: u32,
+    pub height: u32,
+    pub frames_received: u32,
+    pub frames_encoded: u32,
+    pub first_frame_ms: Option<u64>, // Time from session start to the first frame
+    pub encoder_error: Option<String>, // Encoder creation, frame submission or finalization
+    pub capture_error: Option<String>, // Capture session creation or a timeout waiting for frames
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PermissionStatus {
+    Allowed,
+    BlockedByPolicy, // Access denied, e.g. by group policy, MDM or the privacy settings
+    MissingFeature,  // No Windows Graphics Capture (before Windows 10 1903, or a stripped edition)
+    Failed,          // Capture failed for another reason, see the detail
+}
+
+// Result of check_permissions
+#[derive(Debug, Clone, Serialize)]
+pub struct PermissionCheck {
+    pub status: PermissionStatus,
+    pub detail: Option<String>,
+}
+
+// Flags for ThrowawayHandler
+struct ThrowawayFlags {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    max_frames: Option<u32>,
+    encoder_error: Arc<Mutex<Option<String>>>, // Reported back when new() fails
+}
+
+// What a throwaway capture saw of the frames
+#[derive(Debug, Clone, Default)]
+pub struct FrameStats {
+    pub frames_received: u32,
+    pub frames_encoded: u32,
+    pub first_frame_at: Option<Instant>,
+    pub last_frame_at: Option<Instant>,
+    pub dropped_frames: u32, // Gaps longer than DROP_THRESHOLD_FACTOR intervals, as in a recording
+    pub processing_time: Duration, // Spent in the frame callback, encoding included
+}
+
+// Encodes every frame into a file nobody keeps; used by the capture test and the benchmark
+struct ThrowawayHandler {
+    encoder: Option<VideoEncoder>,
+    started: Instant,
+    max_frames: Option<u32>,
+    stats: FrameStats,
+    send_error: Option<String>,
+}
+
+impl GraphicsCaptureApiHandler for ThrowawayHandler {
+    type Flags = ThrowawayFlags;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn new(ctx: Context<Self::Flags>) -> Result<Self, Self::Error> {
+        let flags = ctx.flags;
+        let encoder = VideoEncoder::new(
+            VideoSettingsBuilder::new(flags.width, flags.height)
+                .frame_rate(crate::TARGET_FPS)
+                .bitrate(10_000_000),
+            AudioSettingsBuilder::default().disabled(true),
+            ContainerSettingsBuilder::default(),
+            &flags.path,
+        )
+        .map_err(|e| {
+            if let Ok(mut error) = flags.encoder_error.lock() {
+                *error = Some(e.to_string());
+            }
+            e
+        })?;
+
+        Ok(Self {
+            encoder: Some(encoder),
+            started: Instant::now(),
+            max_frames: flags.max_frames,
+            stats: FrameStats::default(),
+            send_error: None,
+        })
+    }
+
+    fn on_frame_arrived(
+        &mut self,
+        frame: &mut Frame,
+        capture_control: InternalCaptureControl,
+    ) -> Result<(), Self::Error> {
+        let now = Instant::now();
+        let stats = &mut self.stats;
+        if let Some(last) = stats.last_frame_at {
+            let gap = now.duration_since(last).as_secs_f64();
+            if gap > crate::DROP_THRESHOLD_FACTOR / crate::TARGET_FPS as f64 {
+                stats.dropped_frames += 1;
+            }
+        }
+        stats.first_frame_at.get_or_insert(now);
+        stats.last_frame_at = Some(now);
+        stats.frames_received += 1;
+
+        if let Some(encoder) = self.encoder.as_mut() {
+            match encoder.send_frame(frame) {
+                Ok(()) => stats.frames_encoded += 1,
+                Err(e) => self.send_error = Some(e.to_string()),
+            }
+        }
+        stats.processing_time += now.elapsed();
+        if self.send_error.is_some()
+            || self
+                .max_frames
+                .is_some_and(|max| stats.frames_received >= max)
+        {
+            capture_control.stop();
+        }
+        Ok(())
+    }
+}
+
+// How a throwaway capture went; the file is already deleted
+pub struct ThrowawayRun {
+    pub stats: FrameStats,
+    pub started: Instant, // When the handler was created, just before the session started
+    pub ended: Instant,   // When the session was stopped
+    pub send_error: Option<String>,
+    pub stop_error: Option<String>,
+    pub finish_error: Option<String>,
+}
+
+// The capture session couldn't be started
+pub struct StartFailure {
+    pub capture_error: String,
+    pub encoder_error: Option<String>, // Set when creating the encoder was what failed
+}
+
+// Capture `monitor` into a temp file named after `name` until `max_frames` have arrived, the
+// session ends by itself or `keep_going` (polled every POLL_INTERVAL) returns false, then delete
+// the file
+pub fn run_throwaway(
+    monitor: Monitor,
+    width: u32,
+    height: u32,
+    name: &str,
+    max_frames: Option<u32>,
+    mut keep_going: impl FnMut() -> bool,
+) -> Result<ThrowawayRun, StartFailure> {
+    let path = std::env::temp_dir().join(format!("{}_{}.mp4", name, std::process::id()));
+    let encoder_error = Arc::new(Mutex::new(None));
+    let settings = Settings::new(
+        monitor,
+        CursorCaptureSettings::WithoutCursor,
+        DrawBorderSettings::WithoutBorder,
+        ColorFormat::Bgra8,
+        ThrowawayFlags {
+            path: path.clone(),
+            width,
+            height,
+            max_frames,
+            encoder_error: encoder_error.clone(),
+        },
+    );
+
+    let run = match ThrowawayHandler::start_free_threaded(settings) {
+        Ok(control) => {
+            let handler = control.callback();
+            while !control.is_finished() && keep_going() {
+                std::thread::sleep(POLL_INTERVAL);
+            }
+            let stop_error = control.stop().err().map(|e| e.to_string());
+            let ended = Instant::now();
+
+            let mut handler = handler.lock();
+            let finish_error = handler
+                .encoder
+                .take()
+                .and_then(|encoder| encoder.finish().err())
+                .map(|e| e.to_string());
+            Ok(ThrowawayRun {
+                stats: handler.stats.clone(),
+                started: handler.started,
+                ended,
+                send_error: handler.send_error.take(),
+                stop_error,
+                finish_error,
+            })
+        }
+        Err(e) => Err(StartFailure {
+            capture_error: e.to_string(),
+            encoder_error: encoder_error.lock().ok().and_then(|mut error| error.take()),
+        }),
+    };
+
+    if path.exists() {
+        if let Err(e) = std::fs::remove_file(&path) {
+            warn!("Failed to remove capture file {}: {}", path.display(), e);
+        }
+    }
+    run
+}
+
+pub fn run(monitor: Monitor) -> CaptureTestResult {
+    let mut result = CaptureTestResult {
+        success: false,
+        width: monitor.width().unwrap_or(0),
+        height: monitor.height().unwrap_or(0),
+        frames_received: 0,
+        frames_encoded: 0,
+        first_frame_ms: None,
+        encoder_error: None,
+        capture_error: None,
+    };
+    info!(
+        "Testing capture at {}x{} ({} frames)",
+        result.width, result.height, TEST_FRAMES
+    );
+
+    let started = Instant::now();
+    match run_throwaway(
+        monitor,
+        result.width,
+        result.height,
+        "screen_demo_capture_test",
+        Some(TEST_FRAMES),
+        || started.elapsed() < TEST_TIMEOUT,
+    ) {
+        Ok(run) => {
+            result.frames_received = run.stats.frames_received;
+            result.frames_encoded = run.stats.frames_encoded;
+            result.first_frame_ms = run
+                .stats
+                .first_frame_at
+                .map(|at| at.duration_since(run.started).as_millis() as u64);
+            result.encoder_error = run.send_error.or(run.finish_error);
+            result.capture_error = run.stop_error;
+            if result.frames_received == 0 && result.capture_error.is_none() {
+                result.capture_error = Some(format!(
+                    "No frames arrived within {}s",
+                    TEST_TIMEOUT.as_secs()
+                ));
+            }
+        }
+        Err(failure) => {
+            result.encoder_error = failure.encoder_error;
+            result.capture_error = Some(failure.capture_error);
+        }
+    }
+
+    result.success = result.frames_encoded > 0
+        && result.encoder_error.is_none()
+        && result.capture_error.is_none();
+    info!("Capture test result: {:?}", result);
+    result
+}
+
+// Whether screen capture is possible at all, found by grabbing a single frame without encoding it
+pub fn check_permissions(monitor: Monitor) -> PermissionCheck {
+    let check = match GraphicsCaptureApi::is_supported() {
+        Ok(false) => PermissionCheck {
+            status: PermissionStatus::MissingFeature,
+            detail: Some("Windows Graphics Capture is not available on this system".to_string()),
+        },
+        Err(e) => PermissionCheck {
+            status: PermissionStatus::MissingFeature,
+            detail: Some(e.to_string()),
+        },
+        Ok(true) => match crate::screenshot::capture_png(monitor, None) {
+            Ok(_) => PermissionCheck {
+                status: PermissionStatus::Allowed,
+                detail: None,
+            },
+            Err(e) => {
+                let denied =
+                    e.contains(ACCESS_DENIED) || e.to_lowercase().contains("access is denied");
+                PermissionCheck {
+                    status: if denied {
+                        PermissionStatus::BlockedByPolicy
+                    } else {
+                        PermissionStatus::Failed
+                    },
+                    detail: Some(e),
+                }
+            }
+        },
+    };
+
+    if check.status == PermissionStatus::Allowed {
+        info!("Capture permission check passed");
+    } else {
+        warn!("Capture permission check failed: {:?}", check);
+    }
+    check
+}
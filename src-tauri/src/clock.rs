@@ -0,0 +1,51 @@
+// The clock a recording is timed on: QueryPerformanceCounter in 100ns units, the clock and units of
+// Frame::timespan (the capture's SystemRelativeTime). Mouse samples, clicks, cursor type changes,
+// window changes and segment marks all count from the same start on it, so none of them can drift
+// against each other or against the video, whichever thread stamps them. SystemTime, which the
+// input hook used to be timed with, is adjusted by time sync and has coarser ticks.
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::OnceLock;
+use windows::Win32::System::Performance::{QueryPerformanceCounter, QueryPerformanceFrequency};
+
+const TICKS_PER_SEC: i64 = 10_000_000; // 100ns units
+
+static START: AtomicI64 = AtomicI64::new(0); // now() when the capture started, 0 when not recording
+
+fn frequency() -> i64 {
+    static FREQUENCY: OnceLock<i64> = OnceLock::new();
+    *FREQUENCY.get_or_init(|| {
+        let mut frequency = 0;
+        unsafe {
+            QueryPerformanceFrequency(&mut frequency);
+        }
+        frequency.max(1)
+    })
+}
+
+// Current time in 100ns units, comparable with frame timespans
+pub fn now() -> i64 {
+    let mut counter = 0;
+    unsafe {
+        QueryPerformanceCounter(&mut counter);
+    }
+    (counter as i128 * TICKS_PER_SEC as i128 / frequency() as i128) as i64
+}
+
+pub fn start() {
+    START.store(now(), Ordering::SeqCst);
+}
+
+pub fn reset() {
+    START.store(0, Ordering::SeqCst);
+}
+
+// Seconds from the capture start to `time` (100ns units), None until the capture has started
+pub fn secs_at(time: i64) -> Option<f64> {
+    let start = START.load(Ordering::SeqCst);
+    (start != 0).then(|| (time - start) as f64 / TICKS_PER_SEC as f64)
+}
+
+// Seconds since the capture started, None until it has
+pub fn elapsed() -> Option<f64> {
+    secs_at(now())
+}
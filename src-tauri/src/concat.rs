@@ -0,0 +1,254 @@
+// Joining recordings back to back, e.g. the parts left by a monitor switch or an interruption.
+// Files with identical encoder parameters are remuxed as-is; otherwise the frames are decoded and
+// encoded again.
+use crate::media;
+use crate::mp4::{self, VideoProbe};
+use log::info;
+use serde::Serialize;
+use windows::core::HSTRING;
+use windows::Win32::Foundation::BOOL;
+use windows::Win32::Media::MediaFoundation::{
+    IMFMediaType, IMFSample, IMFSinkWriter, IMFSourceReader, MFCreateSinkWriterFromURL,
+    MFCreateSourceReaderFromURL, MF_MT_MPEG_SEQUENCE_HEADER, MF_SOURCE_READERF_ENDOFSTREAM,
+    MF_SOURCE_READER_ALL_STREAMS, MF_SOURCE_READER_FIRST_VIDEO_STREAM,
+};
+use windows_capture::encoder::{
+    AudioSettingsBuilder, ContainerSettingsBuilder, VideoEncoder, VideoSettingsBuilder,
+    VideoSettingsSubType,
+};
+
+const MIN_REENCODE_BITRATE: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConcatResult {
+    pub duration: f64,
+    pub reencoded: bool, // The inputs' codec headers differed, so the frames were encoded again
+}
+
+// Resolution, frame rate and codec have to match; bitrate and length may differ
+fn check_compatible(first: &VideoProbe, other: &VideoProbe, path: &str) -> Result<(), String> {
+    if other.codec != first.codec {
+        return Err(format!(
+            "{} is {} but the first recording is {}",
+            path, other.codec, first.codec
+        ));
+    }
+    if (other.width, other.height) != (first.width, first.height) {
+        return Err(format!(
+            "{} is {}x{} but the first recording is {}x{}",
+            path, other.width, other.height, first.width, first.height
+        ));
+    }
+    if other.fps.round() != first.fps.round() {
+        return Err(format!(
+            "{} is {:.0} fps but the first recording is {:.0} fps",
+            path, other.fps, first.fps
+        ));
+    }
+    Ok(())
+}
+
+// Reader for the compressed video samples of a file, with audio and other streams deselected
+pub unsafe fn open_compressed_reader(path: &str) -> Result<IMFSourceReader, String> {
+    let reader = MFCreateSourceReaderFromURL(&HSTRING::from(path), None)
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    reader
+        .SetStreamSelection(MF_SOURCE_READER_ALL_STREAMS.0 as u32, BOOL::from(false))
+        .map_err(|e| e.to_string())?;
+    reader
+        .SetStreamSelection(
+            MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32,
+            BOOL::from(true),
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(reader)
+}
+
+// Next compressed sample and its timestamp (100ns units), None at the end of the stream
+pub unsafe fn next_sample(reader: &IMFSourceReader) -> Result<Option<(IMFSample, i64)>, String> {
+    loop {
+        let mut flags = 0u32;
+        let mut timestamp = 0i64;
+        let mut sample = None;
+        reader
+            .ReadSample(
+                MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32,
+                0,
+                None,
+                Some(&mut flags as *mut u32),
+                Some(&mut timestamp as *mut i64),
+                Some(&mut sample as *mut _),
+            )
+            .map_err(|e| e.to_string())?;
+        if flags & MF_SOURCE_READERF_ENDOFSTREAM.0 as u32 != 0 {
+            return Ok(None);
+        }
+        if let Some(sample) = sample {
+            return Ok(Some((sample, timestamp)));
+        }
+    }
+}
+
+// Writer for `output` that takes compressed samples of `media_type` and passes them through
+// without an encoder. Returns the writer, already writing, and its stream index.
+pub unsafe fn passthrough_writer(
+    output: &str,
+    media_type: &IMFMediaType,
+) -> Result<(IMFSinkWriter, u32), String> {
+    let writer = MFCreateSinkWriterFromURL(&HSTRING::from(output), None, None)
+        .map_err(|e| format!("Failed to create {}: {}", output, e))?;
+    let stream = writer.AddStream(media_type).map_err(|e| e.to_string())?;
+    writer
+        .SetInputMediaType(stream, media_type, None)
+        .map_err(|e| e.to_string())?;
+    writer.BeginWriting().map_err(|e| e.to_string())?;
+    Ok((writer, stream))
+}
+
+unsafe fn sequence_header(media_type: &IMFMediaType) -> Option<Vec<u8>> {
+    let size = media_type.GetBlobSize(&MF_MT_MPEG_SEQUENCE_HEADER).ok()?;
+    let mut header = vec![0u8; size as usize];
+    media_type
+        .GetBlob(&MF_MT_MPEG_SEQUENCE_HEADER, &mut header, None)
+        .ok()?;
+    Some(header)
+}
+
+// Native video type of every input, if they can share one MP4 sample entry: the stream is copied
+// as-is, so the codec parameter sets (SPS/PPS) have to be byte-identical
+unsafe fn shared_media_type(paths: &[String]) -> Result<Option<IMFMediaType>, String> {
+    let mut first: Option<(IMFMediaType, Option<Vec<u8>>)> = None;
+    for path in paths {
+        let media_type = open_compressed_reader(path)?
+            .GetNativeMediaType(MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32, 0)
+            .map_err(|e| e.to_string())?;
+        let header = sequence_header(&media_type);
+        match &first {
+            None => first = Some((media_type, header)),
+            Some((_, first_header)) if header.is_some() && header == *first_header => {}
+            Some(_) => {
+                info!("{} has different codec headers, re-encoding", path);
+                return Ok(None);
+            }
+        }
+    }
+    Ok(first.map(|(media_type, _)| media_type))
+}
+
+// Copy every compressed sample into the writer, shifting each file to start where the previous ended
+unsafe fn remux(
+    paths: &[String],
+    probes: &[VideoProbe],
+    writer: &IMFSinkWriter,
+    stream: u32,
+) -> Result<(), String> {
+    let mut offset = 0i64;
+    for (path, probe) in paths.iter().zip(probes) {
+        let reader = open_compressed_reader(path)?;
+        let mut first_timestamp = None;
+        while let Some((sample, timestamp)) = next_sample(&reader)? {
+            let first = *first_timestamp.get_or_insert(timestamp);
+            sample
+                .SetSampleTime(offset + timestamp - first)
+                .map_err(|e| e.to_string())?;
+            writer
+                .WriteSample(stream, &sample)
+                .map_err(|e| e.to_string())?;
+        }
+        offset += (probe.duration * crate::HNS_PER_SEC as f64) as i64;
+    }
+    Ok(())
+}
+
+// Encoder for `output` with the codec, size and frame rate of `probe`, at `bitrate` (bps) or the
+// minimum re-encode bitrate if that's higher
+pub fn encoder_like(
+    probe: &VideoProbe,
+    bitrate: u64,
+    output: &str,
+) -> Result<VideoEncoder, String> {
+    let sub_type = if probe.codec == "avc1" {
+        VideoSettingsSubType::H264
+    } else {
+        VideoSettingsSubType::HEVC
+    };
+    let bitrate = (bitrate.min(u32::MAX as u64) as u32).max(MIN_REENCODE_BITRATE);
+    VideoEncoder::new(
+        VideoSettingsBuilder::new(probe.width, probe.height)
+            .sub_type(sub_type)
+            .frame_rate(probe.fps.round().max(1.0) as u32)
+            .bitrate(bitrate),
+        AudioSettingsBuilder::default().disabled(true),
+        ContainerSettingsBuilder::default(),
+        output,
+    )
+    .map_err(|e| e.to_string())
+}
+
+// Decode every frame and encode it into `output` with the first recording's settings
+fn reencode(paths: &[String], probes: &[VideoProbe], output: &str) -> Result<(), String> {
+    let bitrate = probes.iter().map(|probe| probe.bitrate).max().unwrap_or(0);
+    let mut encoder = encoder_like(&probes[0], bitrate, output)?;
+
+    let mut offset = 0.0;
+    let mut upload = Vec::new();
+    let mut send_error = None;
+    for (path, probe) in paths.iter().zip(probes) {
+        media::for_each_video_frame(path, |frame| {
+            crate::flip_rows_into(frame.data, frame.width, frame.height, &mut upload);
+            let pts = ((offset + frame.timestamp) * crate::HNS_PER_SEC as f64) as i64;
+            match encoder.send_frame_buffer(&upload, pts) {
+                Ok(()) => true,
+                Err(e) => {
+                    send_error = Some(e.to_string());
+                    false
+                }
+            }
+        })?;
+        if let Some(e) = send_error.take() {
+            return Err(e);
+        }
+        offset += probe.duration;
+    }
+    encoder.finish().map_err(|e| e.to_string())
+}
+
+// Join `paths` in order into a new MP4 at `output`
+pub fn concat(paths: &[String], output: &str) -> Result<ConcatResult, String> {
+    if paths.len() < 2 {
+        return Err("At least two recordings are needed".to_string());
+    }
+    let probes = paths
+        .iter()
+        .map(|path| mp4::probe_file(path).map_err(|e| format!("{}: {}", path, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (path, probe) in paths.iter().zip(&probes).skip(1) {
+        check_compatible(&probes[0], probe, path)?;
+    }
+    media::init_media_foundation()?;
+
+    let media_type = unsafe { shared_media_type(paths)? };
+    let reencoded = media_type.is_none();
+    match media_type {
+        Some(media_type) => unsafe {
+            let (writer, stream) = passthrough_writer(output, &media_type)?;
+            remux(paths, &probes, &writer, stream)?;
+            writer.Finalize().map_err(|e| e.to_string())?;
+        },
+        None => reencode(paths, &probes, output)?,
+    }
+
+    mp4::validate_file(output)?;
+    let duration = probes.iter().map(|probe| probe.duration).sum();
+    info!(
+        "Joined {} recordings into {} ({:.2}s, {})",
+        paths.len(),
+        output,
+        duration,
+        if reencoded { "re-encoded" } else { "remuxed" }
+    );
+    Ok(ConcatResult {
+        duration,
+        reencoded,
+    })
+}
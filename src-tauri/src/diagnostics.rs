@@ -0,0 +1,40 @@
+// Diagnostics bundle for bug reports: the recent log plus details of the last recording, zipped
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// Write `log_files` and the named JSON documents into a zip at `path`
+pub fn write_bundle(
+    path: &Path,
+    log_files: &[PathBuf],
+    documents: &[(&str, serde_json::Value)],
+) -> Result<(), String> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let mut zip = ZipWriter::new(file);
+    let options = SimpleFileOptions::default();
+
+    for log_file in log_files {
+        let Ok(contents) = std::fs::read(log_file) else {
+            continue; // The previous generation only exists once the log has rolled over
+        };
+        let name = log_file
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("screen-demo.log");
+        zip.start_file(name, options).map_err(|e| e.to_string())?;
+        zip.write_all(&contents).map_err(|e| e.to_string())?;
+    }
+
+    for (name, value) in documents {
+        let json = serde_json::to_vec_pretty(value).map_err(|e| e.to_string())?;
+        zip.start_file(format!("{}.json", name), options)
+            .map_err(|e| e.to_string())?;
+        zip.write_all(&json).map_err(|e| e.to_string())?;
+    }
+
+    zip.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
@@ -0,0 +1,283 @@
+// Media Foundation encoder discovery, so the UI only offers codecs this machine can encode
+use crate::media;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use windows::core::GUID;
+use windows::Win32::Media::MediaFoundation::{
+    IMFActivate, IMFTransform, MFCreateMediaType, MFMediaType_Video, MFTEnumEx,
+    MFT_FRIENDLY_NAME_Attribute, MFVideoFormat_AV1, MFVideoFormat_H264, MFVideoFormat_HEVC,
+    MFVideoInterlace_Progressive, MFT_CATEGORY_VIDEO_ENCODER, MFT_ENUM_FLAG,
+    MFT_ENUM_FLAG_HARDWARE, MFT_ENUM_FLAG_LOCALMFT, MFT_ENUM_FLAG_SORTANDFILTER,
+    MFT_ENUM_FLAG_SYNCMFT, MFT_REGISTER_TYPE_INFO, MF_MT_AVG_BITRATE, MF_MT_FRAME_RATE,
+    MF_MT_FRAME_SIZE, MF_MT_INTERLACE_MODE, MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE,
+    MF_TRANSFORM_ASYNC_UNLOCK,
+};
+use windows::Win32::System::Com::CoTaskMemFree;
+
+// Largest first; the first size an encoder accepts as its output type is reported as its maximum
+const PROBE_SIZES: [(u32, u32); 5] = [
+    (7680, 4320),
+    (4096, 2304),
+    (3840, 2160),
+    (2560, 1440),
+    (1920, 1080),
+];
+
+// Probing activates every encoder, so do it once per run
+static CAPABILITIES: Mutex<Option<Vec<EncoderCapability>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoCodec {
+    H264,
+    #[default]
+    Hevc,
+    Av1, // Reported by get_supported_encoders, but the recorder can't write it yet
+}
+
+impl VideoCodec {
+    fn subtype(&self) -> GUID {
+        match self {
+            VideoCodec::H264 => MFVideoFormat_H264,
+            VideoCodec::Hevc => MFVideoFormat_HEVC,
+            VideoCodec::Av1 => MFVideoFormat_AV1,
+        }
+    }
+
+    // Highest-level bitrate cap of the codec spec (H.264 High 5.2, HEVC/AV1 high tier 6.2).
+    // Media Foundation doesn't expose per-encoder bitrate limits.
+    fn max_bitrate(&self) -> u32 {
+        match self {
+            VideoCodec::H264 => 300_000_000,
+            VideoCodec::Hevc | VideoCodec::Av1 => 800_000_000,
+        }
+    }
+}
+
+// Quality-vs-speed tradeoff for a recording. The Media Foundation encoder behind windows-capture
+// doesn't expose its complexity or thread count, so the preset only controls the bitrate: fewer
+// bits means less entropy coding work for software encoders (and smaller files), at the cost of
+// blockier fast motion. Hardware encoders barely use the CPU either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderPreset {
+    #[default]
+    Quality, // 10 Mbps, crisp text even with lots of motion
+    Balanced, // 6 Mbps
+    Speed,    // 3 Mbps, for machines that drop frames at the higher settings
+}
+
+impl EncoderPreset {
+    // The "low/medium/high" quality argument of start_recording
+    pub fn from_quality(quality: &str) -> Option<Self> {
+        match quality {
+            "high" => Some(EncoderPreset::Quality),
+            "medium" => Some(EncoderPreset::Balanced),
+            "low" => Some(EncoderPreset::Speed),
+            _ => None,
+        }
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        match self {
+            EncoderPreset::Quality => 10_000_000,
+            EncoderPreset::Balanced => 6_000_000,
+            EncoderPreset::Speed => 3_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncoderCapability {
+    pub codec: VideoCodec,
+    pub name: String,
+    pub hardware: bool,
+    pub max_width: Option<u32>, // None if no probe size was accepted
+    pub max_height: Option<u32>,
+    pub max_bitrate: u32,
+}
+
+unsafe fn enumerate_encoders(codec: VideoCodec, flags: MFT_ENUM_FLAG) -> Vec<IMFActivate> {
+    let output = MFT_REGISTER_TYPE_INFO {
+        guidMajorType: MFMediaType_Video,
+        guidSubtype: codec.subtype(),
+    };
+    let mut activates: *mut Option<IMFActivate> = std::ptr::null_mut();
+    let mut count = 0u32;
+    if let Err(e) = MFTEnumEx(
+        MFT_CATEGORY_VIDEO_ENCODER,
+        flags,
+        None,
+        Some(&output),
+        &mut activates,
+        &mut count,
+    ) {
+        warn!("Failed to enumerate {:?} encoders: {}", codec, e);
+        return Vec::new();
+    }
+
+    let mut result = Vec::new();
+    if !activates.is_null() {
+        for i in 0..count as usize {
+            if let Some(activate) = (*activates.add(i)).take() {
+                result.push(activate);
+            }
+        }
+        CoTaskMemFree(Some(activates as *const _));
+    }
+    result
+}
+
+// Largest probe size the encoder accepts as an output type
+unsafe fn probe_max_size(activate: &IMFActivate, codec: VideoCodec) -> Option<(u32, u32)> {
+    let transform: IMFTransform = activate.ActivateObject().ok()?;
+    // Hardware encoders are asynchronous and refuse type negotiation until unlocked
+    if let Ok(attributes) = transform.GetAttributes() {
+        let _ = attributes.SetUINT32(&MF_TRANSFORM_ASYNC_UNLOCK, 1);
+    }
+
+    let accepted = PROBE_SIZES.iter().copied().find(|&(width, height)| {
+        let Ok(media_type) = MFCreateMediaType() else {
+            return false;
+        };
+        media_type
+            .SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)
+            .is_ok()
+            && media_type.SetGUID(&MF_MT_SUBTYPE, &codec.subtype()).is_ok()
+            && media_type
+                .SetUINT64(&MF_MT_FRAME_SIZE, ((width as u64) << 32) | height as u64)
+                .is_ok()
+            && media_type
+                .SetUINT64(&MF_MT_FRAME_RATE, ((crate::TARGET_FPS as u64) << 32) | 1)
+                .is_ok()
+            && media_type.SetUINT32(&MF_MT_AVG_BITRATE, 10_000_000).is_ok()
+            && media_type
+                .SetUINT32(&MF_MT_INTERLACE_MODE, MFVideoInterlace_Progressive.0 as u32)
+                .is_ok()
+            && transform.SetOutputType(0, &media_type, 0).is_ok()
+    });
+
+    let _ = activate.ShutdownObject();
+    accepted
+}
+
+fn probe_encoders() -> Vec<EncoderCapability> {
+    if let Err(e) = media::init_media_foundation() {
+        warn!("Failed to initialize Media Foundation: {}", e);
+        return Vec::new();
+    }
+
+    let mut capabilities = Vec::new();
+    for codec in [VideoCodec::H264, VideoCodec::Hevc, VideoCodec::Av1] {
+        let passes = [
+            (true, MFT_ENUM_FLAG_HARDWARE | MFT_ENUM_FLAG_SORTANDFILTER),
+            (
+                false,
+                MFT_ENUM_FLAG_SYNCMFT | MFT_ENUM_FLAG_LOCALMFT | MFT_ENUM_FLAG_SORTANDFILTER,
+            ),
+        ];
+        for (hardware, flags) in passes {
+            for activate in unsafe { enumerate_encoders(codec, flags) } {
+                let name =
+                    unsafe { media::get_string_attribute(&activate, &MFT_FRIENDLY_NAME_Attribute) }
+                        .unwrap_or_else(|| format!("{:?} encoder", codec));
+                let max_size = unsafe { probe_max_size(&activate, codec) };
+                info!(
+                    "Found {} {:?} encoder '{}', max size {:?}",
+                    if hardware { "hardware" } else { "software" },
+                    codec,
+                    name,
+                    max_size
+                );
+                capabilities.push(EncoderCapability {
+                    codec,
+                    name,
+                    hardware,
+                    max_width: max_size.map(|(width, _)| width),
+                    max_height: max_size.map(|(_, height)| height),
+                    max_bitrate: codec.max_bitrate(),
+                });
+            }
+        }
+    }
+    capabilities
+}
+
+pub fn supported_encoders() -> Vec<EncoderCapability> {
+    let mut cached = match CAPABILITIES.lock() {
+        Ok(cached) => cached,
+        Err(_) => return probe_encoders(),
+    };
+    cached.get_or_insert_with(probe_encoders).clone()
+}
+
+// Reject a custom bitrate outside what the codec allows. Only the average bitrate can be set:
+// windows-capture's VideoSettingsBuilder doesn't expose the rate control mode (CBR/VBR) or a peak
+// bitrate, so Media Foundation's default mode for the chosen encoder is used.
+pub fn check_bitrate(codec: VideoCodec, bitrate: u32) -> Result<(), String> {
+    if bitrate == 0 || bitrate > codec.max_bitrate() {
+        return Err(format!(
+            "Bitrate {} is outside the {:?} range (1 to {} bps)",
+            bitrate,
+            codec,
+            codec.max_bitrate()
+        ));
+    }
+    Ok(())
+}
+
+// Changing the bitrate mid-recording would mean setting CODECAPI_AVEncCommonMeanBitRate on the
+// running encoder through ICodecAPI. windows-capture fixes the bitrate in the encoding profile when
+// it builds its MediaTranscoder pipeline and never exposes the encoder, so a recording keeps the
+// bitrate it started with; a new one is needed to change it.
+pub fn check_bitrate_change() -> Result<(), String> {
+    Err("The capture encoder can't change its bitrate while recording".to_string())
+}
+
+// Check a codec/resolution before creating the encoder, so an unsupported combination fails with
+// a clear message instead of deep inside VideoEncoder::new
+pub fn check_support(codec: VideoCodec, width: u32, height: u32) -> Result<(), String> {
+    if codec == VideoCodec::Av1 {
+        return Err("AV1 recording is not supported yet".to_string());
+    }
+
+    let all = supported_encoders();
+    if all.is_empty() {
+        // Enumeration itself failed; don't block recording on the probe
+        info!(
+            "No encoder information available, skipping the {:?} check",
+            codec
+        );
+        return Ok(());
+    }
+    let encoders: Vec<EncoderCapability> = all
+        .into_iter()
+        .filter(|encoder| encoder.codec == codec)
+        .collect();
+    if encoders.is_empty() {
+        return Err(format!(
+            "No {:?} encoder is available on this machine",
+            codec
+        ));
+    }
+
+    let fits = encoders
+        .iter()
+        .any(|encoder| match (encoder.max_width, encoder.max_height) {
+            (Some(max_width), Some(max_height)) => {
+                // Compare the long and short sides so portrait monitors aren't rejected
+                width.max(height) <= max_width.max(max_height)
+                    && width.min(height) <= max_width.min(max_height)
+            }
+            // The probe couldn't tell; let the encoder decide
+            _ => true,
+        });
+    if !fits {
+        return Err(format!(
+            "No {:?} encoder on this machine supports {}x{}",
+            codec, width, height
+        ));
+    }
+    Ok(())
+}
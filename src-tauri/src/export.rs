@@ -0,0 +1,302 @@
+// Exporting a trimmed copy of a recording: re-encoded so frames can be changed on the way (fades
+// from and to black), or copied as-is between keyframes for a fast trim without quality loss
+use crate::concat;
+use crate::media;
+use crate::mp4;
+use log::info;
+use serde::{Deserialize, Serialize};
+use windows::Win32::Media::MediaFoundation::{
+    MFSampleExtension_CleanPoint, MF_SOURCE_READER_FIRST_VIDEO_STREAM,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub start_secs: Option<f64>, // Trim range on the source's timeline; None keeps that end
+    pub end_secs: Option<f64>,
+    // Ramp from black after the trim start and to black before the trim end. Together they can't
+    // be longer than the trimmed clip.
+    pub fade_in_secs: f64,
+    pub fade_out_secs: f64,
+    // Put the frames on an exact grid at the source's frame rate, repeating frames over capture
+    // gaps and dropping extras, so duration == frame_count / fps. On by default; recordings are
+    // variable-rate unless RecordingOptions.constant_frame_rate was set.
+    pub constant_frame_rate: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            start_secs: None,
+            end_secs: None,
+            fade_in_secs: 0.0,
+            fade_out_secs: 0.0,
+            constant_frame_rate: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportResult {
+    pub duration: f64,
+    pub frame_count: u32,
+}
+
+// Trim points trim_lossless actually cut at, snapped to keyframes. The output's 0 is start_secs on
+// the source's timeline.
+#[derive(Debug, Clone, Serialize)]
+pub struct LosslessTrim {
+    pub start_secs: f64,
+    pub end_secs: f64,
+    pub duration: f64,
+}
+
+// Trim range clamped to the file, checked against the fades
+fn clip_range(options: &ExportOptions, duration: f64) -> Result<(f64, f64), String> {
+    let start = options.start_secs.unwrap_or(0.0).max(0.0);
+    let end = options.end_secs.unwrap_or(duration).min(duration);
+    if start >= end {
+        return Err(format!(
+            "Invalid trim range {:.3}s to {:.3}s for a {:.3}s recording",
+            start, end, duration
+        ));
+    }
+    if options.fade_in_secs < 0.0 || options.fade_out_secs < 0.0 {
+        return Err("Fade durations can't be negative".to_string());
+    }
+    let clip = end - start;
+    let fades = options.fade_in_secs + options.fade_out_secs;
+    if fades > clip {
+        return Err(format!(
+            "Fades of {:.3}s in and {:.3}s out are longer than the {:.3}s clip",
+            options.fade_in_secs, options.fade_out_secs, clip
+        ));
+    }
+    Ok((start, end))
+}
+
+// Brightness of the frame `elapsed` seconds into a clip of `clip` seconds, 0 (black) to 256.
+// The fade out reaches black on the last frame, which starts one frame before the clip ends.
+fn fade_level(options: &ExportOptions, elapsed: f64, clip: f64, frame_secs: f64) -> u32 {
+    let mut level: f64 = 1.0;
+    if options.fade_in_secs > 0.0 {
+        level = level.min(elapsed / options.fade_in_secs);
+    }
+    if options.fade_out_secs > 0.0 {
+        let remaining = clip - frame_secs - elapsed;
+        level = level.min(remaining / options.fade_out_secs);
+    }
+    (level.clamp(0.0, 1.0) * 256.0).round() as u32
+}
+
+// Scale the color channels of a BGRA buffer towards black, leaving alpha alone
+fn apply_fade(frame: &mut [u8], level: u32) {
+    for pixel in frame.chunks_exact_mut(4) {
+        for channel in &mut pixel[..3] {
+            *channel = ((*channel as u32 * level) >> 8) as u8;
+        }
+    }
+}
+
+// Write the trimmed range of `path` to `output` with the source's codec, size, frame rate and
+// bitrate, applying the fades. Timestamps start at 0 on the trim start.
+pub fn export(path: &str, output: &str, options: &ExportOptions) -> Result<ExportResult, String> {
+    let probe = mp4::probe_file(path).map_err(|e| format!("{}: {}", path, e))?;
+    let (start, end) = clip_range(options, probe.duration)?;
+    let clip = end - start;
+    let frame_secs = 1.0 / probe.fps.max(1.0);
+    info!(
+        "Exporting {} ({:.3}s to {:.3}s, fade in {:.2}s, fade out {:.2}s) to {}",
+        path, start, end, options.fade_in_secs, options.fade_out_secs, output
+    );
+
+    let mut encoder = concat::encoder_like(&probe, probe.bitrate, output)?;
+    let mut faded = Vec::new();
+    let mut upload = Vec::new();
+    let mut frame_count = 0u32;
+    let mut next_slot = 0u64; // With constant_frame_rate, the first grid slot not yet filled
+    let mut send_error = None;
+    let slot_pts = |slot: u64| (slot as f64 * frame_secs * crate::HNS_PER_SEC as f64) as i64;
+    media::for_each_video_frame(path, |frame| {
+        if frame.timestamp < start {
+            return true;
+        }
+        if frame.timestamp >= end {
+            return false;
+        }
+        let elapsed = frame.timestamp - start;
+        // The first frame kept covers the start of the clip
+        let slot = if frame_count == 0 {
+            0
+        } else {
+            (elapsed / frame_secs) as u64
+        };
+        if options.constant_frame_rate {
+            if slot < next_slot {
+                // Arrived faster than the frame rate, this slot is already filled
+                return true;
+            }
+            // Repeat the previous frame over a capture gap
+            while next_slot < slot {
+                if let Err(e) = encoder.send_frame_buffer(&upload, slot_pts(next_slot)) {
+                    send_error = Some(e.to_string());
+                    return false;
+                }
+                frame_count += 1;
+                next_slot += 1;
+            }
+        }
+        let level = fade_level(options, elapsed, clip, frame_secs);
+        let data = if level < 256 {
+            faded.clear();
+            faded.extend_from_slice(frame.data);
+            apply_fade(&mut faded, level);
+            &faded[..]
+        } else {
+            frame.data
+        };
+        crate::flip_rows_into(data, frame.width, frame.height, &mut upload);
+        let pts = if options.constant_frame_rate {
+            slot_pts(slot)
+        } else {
+            (elapsed * crate::HNS_PER_SEC as f64) as i64
+        };
+        match encoder.send_frame_buffer(&upload, pts) {
+            Ok(()) => {
+                frame_count += 1;
+                next_slot = slot + 1;
+                true
+            }
+            Err(e) => {
+                send_error = Some(e.to_string());
+                false
+            }
+        }
+    })?;
+    if let Some(e) = send_error {
+        return Err(e);
+    }
+    if frame_count == 0 {
+        return Err(format!("No frames between {:.3}s and {:.3}s", start, end));
+    }
+    // Hold the last frame to the end of the clip, the screen may have been static
+    if options.constant_frame_rate {
+        let slots = ((clip / frame_secs).round() as u64).max(next_slot);
+        while next_slot < slots {
+            encoder
+                .send_frame_buffer(&upload, slot_pts(next_slot))
+                .map_err(|e| e.to_string())?;
+            frame_count += 1;
+            next_slot += 1;
+        }
+    }
+    encoder.finish().map_err(|e| e.to_string())?;
+
+    mp4::validate_file(output)?;
+    info!(
+        "Exported {} frames ({:.2}s) to {}",
+        frame_count, clip, output
+    );
+    Ok(ExportResult {
+        duration: clip,
+        frame_count,
+    })
+}
+
+// The cut point (sample index, timestamp) closest to `time` seconds after `first`
+fn nearest_cut<'a>(
+    cuts: impl Iterator<Item = &'a (usize, i64)>,
+    first: i64,
+    time: f64,
+) -> Option<(usize, i64)> {
+    let distance =
+        |timestamp: i64| ((timestamp - first) as f64 / crate::HNS_PER_SEC as f64 - time).abs();
+    cuts.copied()
+        .min_by(|a, b| distance(a.1).total_cmp(&distance(b.1)))
+}
+
+// Copy `start_secs` to `end_secs` of `path` into `output` without re-encoding. A copied stream can
+// only start on a keyframe, so both ends snap to the nearest keyframe (or the end of the file);
+// the returned range says where.
+pub fn trim_lossless(
+    path: &str,
+    start_secs: f64,
+    end_secs: f64,
+    output: &str,
+) -> Result<LosslessTrim, String> {
+    let probe = mp4::probe_file(path).map_err(|e| format!("{}: {}", path, e))?;
+    let start = start_secs.max(0.0);
+    let end = end_secs.min(probe.duration);
+    if start >= end {
+        return Err(format!(
+            "Invalid trim range {:.3}s to {:.3}s for a {:.3}s recording",
+            start_secs, end_secs, probe.duration
+        ));
+    }
+    media::init_media_foundation()?;
+
+    unsafe {
+        // Find the keyframes first; reading compressed samples doesn't decode anything
+        let reader = concat::open_compressed_reader(path)?;
+        let mut cuts = Vec::new(); // (sample index, timestamp) of each keyframe
+        let mut first = None;
+        let mut count = 0;
+        while let Some((sample, timestamp)) = concat::next_sample(&reader)? {
+            first.get_or_insert(timestamp);
+            if sample.GetUINT32(&MFSampleExtension_CleanPoint).unwrap_or(0) != 0 {
+                cuts.push((count, timestamp));
+            }
+            count += 1;
+        }
+        let first = first.ok_or_else(|| format!("{} has no video samples", path))?;
+        // The end of the file is a cut point too, but nothing can start there
+        cuts.push((
+            count,
+            first + (probe.duration * crate::HNS_PER_SEC as f64) as i64,
+        ));
+
+        let (start_index, start_timestamp) =
+            nearest_cut(cuts[..cuts.len() - 1].iter(), first, start)
+                .ok_or_else(|| format!("{} has no keyframes", path))?;
+        let (end_index, end_timestamp) = nearest_cut(
+            cuts.iter().filter(|(index, _)| *index > start_index),
+            first,
+            end,
+        )
+        .unwrap_or(cuts[cuts.len() - 1]);
+        let trim = LosslessTrim {
+            start_secs: (start_timestamp - first) as f64 / crate::HNS_PER_SEC as f64,
+            end_secs: (end_timestamp - first) as f64 / crate::HNS_PER_SEC as f64,
+            duration: (end_timestamp - start_timestamp) as f64 / crate::HNS_PER_SEC as f64,
+        };
+        info!(
+            "Trimming {} losslessly to {:.3}s - {:.3}s (asked for {:.3}s - {:.3}s) into {}",
+            path, trim.start_secs, trim.end_secs, start, end, output
+        );
+
+        let reader = concat::open_compressed_reader(path)?;
+        let media_type = reader
+            .GetNativeMediaType(MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32, 0)
+            .map_err(|e| e.to_string())?;
+        let (writer, stream) = concat::passthrough_writer(output, &media_type)?;
+        let mut index = 0;
+        while let Some((sample, timestamp)) = concat::next_sample(&reader)? {
+            if index >= end_index {
+                break;
+            }
+            // Frames shown before the start keyframe can't be decoded without what was cut
+            if index >= start_index && timestamp >= start_timestamp {
+                sample
+                    .SetSampleTime(timestamp - start_timestamp)
+                    .map_err(|e| e.to_string())?;
+                writer
+                    .WriteSample(stream, &sample)
+                    .map_err(|e| e.to_string())?;
+            }
+            index += 1;
+        }
+        writer.Finalize().map_err(|e| e.to_string())?;
+        mp4::validate_file(output)?;
+        Ok(trim)
+    }
+}
@@ -0,0 +1,190 @@
+// Monitors recorded alongside the main capture, each to its own file. These sessions only hand
+// frames to their encoder (no overlays, CFR or previews); the mouse track comes from the main
+// capture and tags which monitor the cursor was on.
+use log::{info, warn};
+use std::sync::Mutex;
+use windows_capture::{
+    capture::{CaptureControl, Context, GraphicsCaptureApiHandler},
+    encoder::{
+        AudioSettingsBuilder, ContainerSettingsBuilder, VideoEncoder, VideoSettingsBuilder,
+        VideoSettingsSubType,
+    },
+    frame::Frame,
+    graphics_capture_api::InternalCaptureControl,
+    monitor::Monitor,
+    settings::{ColorFormat, CursorCaptureSettings, DrawBorderSettings, Settings},
+};
+
+type CaptureError = Box<dyn std::error::Error + Send + Sync>;
+
+static CAPTURES: Mutex<Vec<ExtraCapture>> = Mutex::new(Vec::new());
+
+struct ExtraCapture {
+    monitor_index: usize,
+    path: String,
+    control: CaptureControl<ExtraHandler, CaptureError>,
+}
+
+// Encoder settings for one extra monitor, matching the main recording's codec, bitrate and rate
+#[derive(Clone)]
+pub struct ExtraSettings {
+    pub path: String,
+    pub sub_type: VideoSettingsSubType,
+    pub bitrate: u32,
+    pub frame_rate: u32,
+    pub show_capture_border: bool,
+}
+
+// A finished extra monitor file
+pub struct ExtraRecording {
+    pub monitor_index: usize,
+    pub path: String,
+    pub frame_count: u32,
+    pub duration: f64,
+}
+
+struct ExtraHandler {
+    encoder: Option<VideoEncoder>,
+    frame_count: u32,
+    first_pts: Option<i64>,
+    last_pts: Option<i64>,
+}
+
+impl GraphicsCaptureApiHandler for ExtraHandler {
+    type Flags = (ExtraSettings, u32, u32); // Settings and the monitor's size
+    type Error = CaptureError;
+
+    fn new(ctx: Context<Self::Flags>) -> Result<Self, Self::Error> {
+        let (settings, width, height) = ctx.flags;
+        let encoder = VideoEncoder::new(
+            VideoSettingsBuilder::new(width, height)
+                .sub_type(settings.sub_type)
+                .frame_rate(settings.frame_rate)
+                .bitrate(settings.bitrate),
+            AudioSettingsBuilder::default().disabled(true),
+            ContainerSettingsBuilder::default(),
+            &settings.path,
+        )?;
+        Ok(Self {
+            encoder: Some(encoder),
+            frame_count: 0,
+            first_pts: None,
+            last_pts: None,
+        })
+    }
+
+    fn on_frame_arrived(
+        &mut self,
+        frame: &mut Frame,
+        _capture_control: InternalCaptureControl,
+    ) -> Result<(), Self::Error> {
+        // None once stop has finished the file
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(());
+        };
+        let pts = frame.timespan().Duration;
+        encoder.send_frame(frame)?;
+        self.frame_count += 1;
+        self.first_pts.get_or_insert(pts);
+        self.last_pts = Some(pts);
+        Ok(())
+    }
+}
+
+pub fn is_active() -> bool {
+    CAPTURES
+        .lock()
+        .map(|captures| !captures.is_empty())
+        .unwrap_or(false)
+}
+
+// Start recording `monitor` to `settings.path`
+pub fn start(
+    monitor: Monitor,
+    monitor_index: usize,
+    settings: ExtraSettings,
+) -> Result<(), String> {
+    let width = monitor.width().map_err(|e| e.to_string())?;
+    let height = monitor.height().map_err(|e| e.to_string())?;
+    let path = settings.path.clone();
+    let draw_border = if settings.show_capture_border {
+        DrawBorderSettings::WithBorder
+    } else {
+        DrawBorderSettings::WithoutBorder
+    };
+    let capture_settings = Settings::new(
+        monitor,
+        CursorCaptureSettings::WithoutCursor, // Drawn by the editor from the shared mouse track
+        draw_border,
+        ColorFormat::Bgra8,
+        (settings, width, height),
+    );
+    let control = ExtraHandler::start_free_threaded(capture_settings).map_err(|e| e.to_string())?;
+    info!(
+        "Recording monitor {} ({}x{}) to {}",
+        monitor_index, width, height, path
+    );
+
+    CAPTURES
+        .lock()
+        .map_err(|e| e.to_string())?
+        .push(ExtraCapture {
+            monitor_index,
+            path,
+            control,
+        });
+    Ok(())
+}
+
+// Finish every extra monitor's file and stop its capture. Recordings that failed to finalize are
+// logged and left out.
+pub fn stop() -> Vec<ExtraRecording> {
+    let captures = CAPTURES
+        .lock()
+        .map(|mut captures| std::mem::take(&mut *captures))
+        .unwrap_or_default();
+
+    let mut finished = Vec::new();
+    for capture in captures {
+        // Take the encoder under the handler's lock so no frame is being sent while it finishes
+        let callback = capture.control.callback();
+        let (encoder, frame_count, duration) = {
+            let mut handler = callback.lock();
+            let duration = match (handler.first_pts, handler.last_pts) {
+                (Some(first), Some(last)) => (last - first) as f64 / crate::HNS_PER_SEC as f64,
+                _ => 0.0,
+            };
+            (handler.encoder.take(), handler.frame_count, duration)
+        };
+        if let Err(e) = capture.control.stop() {
+            warn!(
+                "Capture of monitor {} stopped with error: {:?}",
+                capture.monitor_index, e
+            );
+        }
+
+        match encoder.map(|encoder| encoder.finish()) {
+            Some(Ok(())) => {
+                info!(
+                    "Finished monitor {} recording: {} frames, {:.2}s",
+                    capture.monitor_index, frame_count, duration
+                );
+                finished.push(ExtraRecording {
+                    monitor_index: capture.monitor_index,
+                    path: capture.path,
+                    frame_count,
+                    duration,
+                });
+            }
+            Some(Err(e)) => warn!(
+                "Failed to finalize monitor {} recording: {}",
+                capture.monitor_index, e
+            ),
+            None => warn!(
+                "Monitor {} recording was already finished",
+                capture.monitor_index
+            ),
+        }
+    }
+    finished
+}
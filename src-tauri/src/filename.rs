@@ -0,0 +1,107 @@
+// Recording file names from a user template, e.g. "demo_{date}_{time}_{resolution}"
+use std::env;
+use std::path::{Path, PathBuf};
+use windows::Win32::System::SystemInformation::GetLocalTime;
+
+const RECORDINGS_DIR_NAME: &str = "screen_demo_recordings";
+const PLACEHOLDERS: [&str; 5] = ["date", "time", "monitor", "resolution", "counter"];
+const MAX_NAME_CHARS: usize = 200; // Leaves room for the directory within MAX_PATH
+
+// Values substituted into the template
+pub struct TemplateValues {
+    pub monitor: usize,
+    pub width: u32,
+    pub height: u32,
+    pub counter: u32,
+}
+
+// Device names Windows reserves in every directory, with or without an extension
+fn is_reserved_name(stem: &str) -> bool {
+    let stem = stem.to_ascii_uppercase();
+    matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit())
+}
+
+// Replace characters Windows doesn't allow in file names and avoid the reserved device names
+fn sanitize(name: &str) -> String {
+    let mut clean: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(MAX_NAME_CHARS)
+        .collect();
+    // Trailing dots and spaces are silently dropped by Windows
+    clean.truncate(clean.trim_end_matches(['.', ' ']).len());
+    let stem = clean.split('.').next().unwrap_or_default();
+    if is_reserved_name(stem) {
+        clean.insert(0, '_');
+    }
+    clean
+}
+
+fn placeholder_value(name: &str, values: &TemplateValues) -> Option<String> {
+    let now = unsafe { GetLocalTime() };
+    Some(match name {
+        "date" => format!("{:04}-{:02}-{:02}", now.wYear, now.wMonth, now.wDay),
+        "time" => format!("{:02}-{:02}-{:02}", now.wHour, now.wMinute, now.wSecond),
+        "monitor" => values.monitor.to_string(),
+        "resolution" => format!("{}x{}", values.width, values.height),
+        "counter" => format!("{:03}", values.counter),
+        _ => return None,
+    })
+}
+
+// Resolve a template into a safe .mp4 file name. Unknown or unclosed placeholders are an error,
+// so a typo doesn't silently end up in every file name.
+pub fn from_template(template: &str, values: &TemplateValues) -> Result<String, String> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        name.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("Unclosed placeholder in \"{}\"", template))?;
+        let placeholder = &rest[open + 1..open + close];
+        let value = placeholder_value(placeholder, values).ok_or_else(|| {
+            format!(
+                "Unknown placeholder {{{}}}, expected one of {}",
+                placeholder,
+                PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+            )
+        })?;
+        name.push_str(&value);
+        rest = &rest[open + close + 1..];
+    }
+    name.push_str(rest);
+
+    let name = name.strip_suffix(".mp4").unwrap_or(&name);
+    let name = sanitize(name);
+    if name.is_empty() {
+        return Err(format!("\"{}\" gives an empty file name", template));
+    }
+    Ok(format!("{}.mp4", name))
+}
+
+// Templated recordings get their own temp subdirectory, so release_recording can tell them apart
+// from other files while still allowing any name
+pub fn recordings_dir() -> PathBuf {
+    env::temp_dir().join(RECORDINGS_DIR_NAME)
+}
+
+// `name` in `dir`, with " (2)", " (3)"... added if a file by that name already exists
+pub fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let stem = name.strip_suffix(".mp4").unwrap_or(name);
+    (2..)
+        .map(|n| dir.join(format!("{} ({}).mp4", stem, n)))
+        .find(|path| !path.exists())
+        .unwrap_or(path)
+}
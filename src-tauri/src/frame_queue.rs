@@ -0,0 +1,191 @@
+// Bounded queue between capture and encode: the capture thread copies each finished frame in and a
+// worker thread sends them to the encoder, so a slow send_frame_buffer doesn't stall capture.
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use windows_capture::encoder::VideoEncoder;
+
+const DEFAULT_CAPACITY: usize = 8;
+
+static QUEUED_BYTES: AtomicU64 = AtomicU64::new(0); // Frame data waiting in any queue, for get_memory_stats
+
+// What push does when the queue is full
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueOverflow {
+    #[default]
+    DropOldest, // Keep capture timing; the oldest queued frame is lost
+    Block, // Wait for the encoder; capture stalls like it does without a queue
+}
+
+// RecordingOptions.frame_queue. Every slot holds a full raw frame (about 33 MB at 4K), so keep the
+// capacity small.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrameQueueOptions {
+    pub capacity: usize,
+    pub overflow: QueueOverflow,
+}
+
+impl Default for FrameQueueOptions {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            overflow: QueueOverflow::DropOldest,
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    frames: VecDeque<(Vec<u8>, i64)>, // Bottom-up BGRA buffer and pts
+    spare: Vec<Vec<u8>>,              // Sent buffers, reused by push
+    closed: bool,
+    overflowed: u32,
+    failed: u32, // Send failures not yet reported by push
+    last_error: Option<String>,
+}
+
+pub struct FrameQueue {
+    state: Arc<(Mutex<QueueState>, Condvar)>,
+    options: FrameQueueOptions,
+    worker: JoinHandle<VideoEncoder>,
+}
+
+// Send frames until the queue is closed and drained, then hand the encoder back
+fn drain(mut encoder: VideoEncoder, state: Arc<(Mutex<QueueState>, Condvar)>) -> VideoEncoder {
+    let (lock, signal) = &*state;
+    loop {
+        let (buffer, pts) = {
+            let Ok(mut queue) = lock.lock() else {
+                return encoder;
+            };
+            loop {
+                if let Some(frame) = queue.frames.pop_front() {
+                    break frame;
+                }
+                if queue.closed {
+                    return encoder;
+                }
+                queue = match signal.wait(queue) {
+                    Ok(queue) => queue,
+                    Err(_) => return encoder,
+                };
+            }
+        };
+        // A slot just freed up for a blocked push
+        signal.notify_all();
+        QUEUED_BYTES.fetch_sub(buffer.len() as u64, Ordering::SeqCst);
+
+        let result = encoder.send_frame_buffer(&buffer, pts);
+        if let Ok(mut queue) = lock.lock() {
+            if let Err(e) = result {
+                queue.failed += 1;
+                queue.last_error = Some(e.to_string());
+            }
+            queue.spare.push(buffer);
+        }
+    }
+}
+
+impl FrameQueue {
+    pub fn start(encoder: VideoEncoder, options: FrameQueueOptions) -> Self {
+        let options = FrameQueueOptions {
+            capacity: options.capacity.max(1),
+            ..options
+        };
+        let state = Arc::new((Mutex::new(QueueState::default()), Condvar::new()));
+        let worker = thread::spawn({
+            let state = state.clone();
+            move || drain(encoder, state)
+        });
+        info!(
+            "Encoding through a frame queue of {} ({:?} when full)",
+            options.capacity, options.overflow
+        );
+        Self {
+            state,
+            options,
+            worker,
+        }
+    }
+
+    // Queue a copy of `buffer`. Errs with the encoder's last error if frames failed since the
+    // previous push, so the capture handler sees failures like it does with direct sends.
+    pub fn push(&self, buffer: &[u8], pts: i64) -> Result<(), String> {
+        let (lock, signal) = &*self.state;
+        let mut queue = lock.lock().map_err(|e| e.to_string())?;
+        while queue.frames.len() >= self.options.capacity {
+            match self.options.overflow {
+                QueueOverflow::DropOldest => {
+                    if let Some((oldest, _)) = queue.frames.pop_front() {
+                        QUEUED_BYTES.fetch_sub(oldest.len() as u64, Ordering::SeqCst);
+                        queue.spare.push(oldest);
+                    }
+                    queue.overflowed += 1;
+                }
+                QueueOverflow::Block => {
+                    queue = signal.wait(queue).map_err(|e| e.to_string())?;
+                }
+            }
+        }
+
+        let mut frame = queue.spare.pop().unwrap_or_default();
+        frame.clear();
+        frame.extend_from_slice(buffer);
+        QUEUED_BYTES.fetch_add(frame.len() as u64, Ordering::SeqCst);
+        queue.frames.push_back((frame, pts));
+        signal.notify_all();
+
+        if queue.failed > 0 {
+            queue.failed = 0;
+            return Err(queue.last_error.take().unwrap_or_default());
+        }
+        Ok(())
+    }
+
+    // Frames waiting for the encoder
+    pub fn depth(&self) -> usize {
+        self.state
+            .0
+            .lock()
+            .map(|queue| queue.frames.len())
+            .unwrap_or(0)
+    }
+
+    // Frames dropped because the queue was full
+    pub fn overflowed(&self) -> u32 {
+        self.state
+            .0
+            .lock()
+            .map(|queue| queue.overflowed)
+            .unwrap_or(0)
+    }
+
+    // Send what's still queued, then finish the encoder
+    pub fn finish(self) -> Result<(), String> {
+        let overflowed = self.overflowed();
+        if overflowed > 0 {
+            warn!("Frame queue dropped {} frames while full", overflowed);
+        }
+        let (lock, signal) = &*self.state;
+        if let Ok(mut queue) = lock.lock() {
+            queue.closed = true;
+        }
+        signal.notify_all();
+
+        let encoder = self
+            .worker
+            .join()
+            .map_err(|_| "Frame queue worker panicked".to_string())?;
+        encoder.finish().map_err(|e| e.to_string())
+    }
+}
+
+// Bytes of frame data currently queued for the encoder
+pub fn queued_bytes() -> u64 {
+    QUEUED_BYTES.load(Ordering::SeqCst)
+}
@@ -1,37 +1,80 @@
+mod analysis;
+mod benchmark;
+mod capture_test;
+mod clock;
+mod concat;
+mod diagnostics;
+mod encoders;
+mod export;
+mod extra_monitors;
+mod filename;
+mod frame_queue;
+mod logging;
+mod media;
+mod mp4;
+mod quality_presets;
+mod redaction;
+mod replay;
+mod screenshot;
+mod session;
+mod smoothing;
+mod stop_pipe;
+mod taskbar;
+mod timestamp_overlay;
+mod track_export;
+mod tray;
+mod watermark;
+mod window_track;
+
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use encoders::{EncoderCapability, EncoderPreset, VideoCodec};
+use frame_queue::FrameQueue;
+use log::{debug, error, info, warn};
+use media::{CameraInfo, PipPosition};
 use memmap2::Mmap;
 use parking_lot::Mutex as ParkingMutex;
-use rdev::{listen, Event, EventType};
+use rdev::{listen, Button, Event, EventType};
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 use std::env;
 use std::fs::File;
 use std::io::{Read, Seek};
 use std::mem::zeroed;
-use std::sync::atomic::AtomicU16;
-use std::sync::atomic::AtomicU64;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicU16, AtomicU32, AtomicU64};
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
-use std::time::Instant;
-use tauri::Manager;
+use std::time::{Instant, SystemTime};
+use tauri::{Emitter, Manager};
 use tiny_http::{Response, Server, StatusCode};
+use watermark::{Watermark, WatermarkBitmap};
 use windows::core::PCWSTR;
 use windows::Win32::Foundation::POINT;
 use windows::Win32::Foundation::{BOOL, LPARAM, RECT};
 use windows::Win32::Graphics::Gdi::{
-    EnumDisplayMonitors, GetMonitorInfoW, HDC, HMONITOR, MONITORINFOEXW,
+    EnumDisplayDevicesW, EnumDisplayMonitors, EnumDisplaySettingsW, GetMonitorInfoW, DEVMODEW,
+    DISPLAY_DEVICEW, ENUM_CURRENT_SETTINGS, HDC, HMONITOR, MONITORINFOEXW,
+};
+use windows::Win32::Security::Cryptography::{
+    BCryptGenRandom, BCRYPT_ALG_HANDLE, BCRYPT_USE_SYSTEM_PREFERRED_RNG,
 };
+use windows::Win32::UI::HiDpi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
 use windows::Win32::UI::WindowsAndMessaging::GetCursorInfo;
 use windows::Win32::UI::WindowsAndMessaging::GetCursorPos;
 use windows::Win32::UI::WindowsAndMessaging::CURSORINFO;
-use windows::Win32::UI::WindowsAndMessaging::{LoadCursorW, IDC_ARROW, IDC_HAND, IDC_IBEAM};
+use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_REMOTESESSION};
+use windows::Win32::UI::WindowsAndMessaging::{
+    LoadCursorW, EDD_GET_DEVICE_INTERFACE_NAME, IDC_ARROW, IDC_HAND, IDC_IBEAM,
+};
 use windows_capture::{
-    capture::{Context, GraphicsCaptureApiHandler},
-    encoder::{AudioSettingsBuilder, ContainerSettingsBuilder, VideoEncoder, VideoSettingsBuilder},
-    frame::Frame,
+    capture::{CaptureControl, Context, GraphicsCaptureApiHandler},
+    encoder::{
+        AudioSettingsBuilder, ContainerSettingsBuilder, ImageEncoder, VideoEncoder,
+        VideoSettingsBuilder, VideoSettingsSubType,
+    },
+    frame::{Frame, ImageFormat},
     graphics_capture_api::InternalCaptureControl,
     monitor::Monitor,
     settings::{ColorFormat, CursorCaptureSettings, DrawBorderSettings, Settings},
@@ -39,28 +82,117 @@ use windows_capture::{
 
 // Global static variables that can be safely accessed from multiple threads
 static RECORDING: AtomicBool = AtomicBool::new(false); // Tracks if we're currently recording
-static mut VIDEO_PATH: Option<String> = None; // Stores the path where video will be saved
+static VIDEO_PATH: Mutex<Option<String>> = Mutex::new(None); // Stores the path where video will be saved
 static SHOULD_STOP: AtomicBool = AtomicBool::new(false); // Signals when to stop recording
-static IS_MOUSE_CLICKED: AtomicBool = AtomicBool::new(false);
 static SHOULD_LISTEN_CLICKS: AtomicBool = AtomicBool::new(false);
+static INPUT_LISTENER_STARTED: AtomicBool = AtomicBool::new(false); // rdev's hook can't be removed, so install it once
 static VIDEO_DATA: Mutex<Option<Vec<u8>>> = Mutex::new(None);
 static ENCODING_FINISHED: AtomicBool = AtomicBool::new(false);
 static ENCODER_ACTIVE: AtomicBool = AtomicBool::new(false);
 static VIDEO_MMAP: ParkingMutex<Option<Arc<Mmap>>> = ParkingMutex::new(None);
 static PORT: AtomicU16 = AtomicU16::new(0);
 static SERVER_PORTS: Mutex<Vec<u16>> = Mutex::new(Vec::new());
+static PROTOCOL_VIDEO_PATH: Mutex<Option<String>> = Mutex::new(None); // Recording served by the custom protocol
+static VIDEO_SERVERS: Mutex<Vec<(String, Arc<Server>)>> = Mutex::new(Vec::new()); // Running servers and the file each serves
+const VIDEO_SERVER_WORKERS: usize = 4; // <video> issues metadata and range requests in parallel
+const VIDEO_PROTOCOL: &str = "recording";
+const PROTOCOL_MAX_RANGE: u64 = 4 * 1024 * 1024; // Cap open-ended range requests like the asset protocol does
 static LAST_CURSOR_TYPE: Mutex<String> = Mutex::new(String::new());
-static LAST_CLICK_TIME: AtomicU64 = AtomicU64::new(0);
-static CLICK_LOGGED: AtomicBool = AtomicBool::new(false);
+static CLICK_EVENTS: Mutex<VecDeque<ClickEvent>> = Mutex::new(VecDeque::new()); // Every press/release, in order
+static CLICK_EPOCH: Mutex<Option<SystemTime>> = Mutex::new(None); // Wall-clock start of the recording, for ISO track timestamps
+static CURSOR_POLL_GENERATION: AtomicU64 = AtomicU64::new(0); // Bumped on every start/stop so stale pollers exit
+static MEMORY_MONITOR_GENERATION: AtomicU64 = AtomicU64::new(0); // Same scheme as CURSOR_POLL_GENERATION
+static CURSOR_TYPE_CHANGES: Mutex<Vec<(f64, String)>> = Mutex::new(Vec::new()); // (clock::elapsed seconds, new type)
+static RECORDED_MONITORS: Mutex<Vec<(usize, RECT)>> = Mutex::new(Vec::new()); // Index and desktop rect, main monitor first
+static FILENAME_COUNTER: AtomicU32 = AtomicU32::new(0); // {counter} in filename templates, per app session
+static SEGMENT_MARKS: Mutex<Vec<(f64, Option<f64>)>> = Mutex::new(Vec::new()); // (start, end) in clock::elapsed seconds
+const DOUBLE_CLICK_SECS: f64 = 0.3;
+const EXIT_FINALIZE_TIMEOUT_SECS: u64 = 20; // Longest the app waits on exit for a recording to finalize
+static STARTING: AtomicBool = AtomicBool::new(false); // Held for the whole of start_recording
+static PREVIEW_BUSY: AtomicBool = AtomicBool::new(false); // Set while a preview frame is being encoded
+static APP_HANDLE: Mutex<Option<tauri::AppHandle>> = Mutex::new(None);
+type CaptureError = Box<dyn std::error::Error + Send + Sync>;
+static CAPTURE_CONTROL: Mutex<Option<CaptureControl<CaptureHandler, CaptureError>>> =
+    Mutex::new(None);
+static CAPTURE_HANDOFF: Mutex<Option<CaptureHandler>> = Mutex::new(None); // Session state carried to the next monitor
+static ACTIVE_RECORDING: Mutex<Option<(Option<String>, RecordingOptions)>> = Mutex::new(None); // Monitor id and options in use
+static SWITCHING_MONITOR: AtomicBool = AtomicBool::new(false);
+static FINALIZING_ON_EXIT: AtomicBool = AtomicBool::new(false); // Exit was held back to finalize the recording
+static WATERMARK_OVERLAY: Mutex<Option<Arc<WatermarkBitmap>>> = Mutex::new(None); // Rendered in start_recording
+static APPLIED_SELF_EXCLUSION: Mutex<SelfExclusion> = Mutex::new(SelfExclusion::None);
+
+const TARGET_FPS: u32 = 30; // Default recording frame rate, and the rate of replays and probes
+const MAX_FPS: u32 = 120;
+const HNS_PER_SEC: i64 = 10_000_000; // Frame timespans are in 100ns units
+const DROP_THRESHOLD_FACTOR: f64 = 1.5; // Frame gaps longer than this many intervals count as a drop
+const DEGRADED_AFTER_ERRORS: u32 = 10; // Consecutive send_frame failures that mean the encoder can't keep up
+const MIN_RECORDING_FRAMES: u32 = 2; // Fewer and the video has no duration to play
+const MIN_THROTTLED_FPS: u32 = 5; // Capture is never slowed below this when the encoder falls behind
+
+// Live preview throttling
+const PREVIEW_INTERVAL_MS: u128 = 200; // ~5fps
+const STATS_INTERVAL_MS: u128 = 1000;
+const MEMORY_INTERVAL_MS: u64 = 5000; // `recording-memory` events and memory cap checks
+const PREVIEW_MAX_WIDTH: u32 = 320;
+const POSTER_MAX_WIDTH: u32 = 1280;
+const DEFAULT_MAX_MOUSE_POSITIONS: usize = 30 * 60 * 60; // 30 minutes at the ~60Hz sample rate
 
 // Add these new structures
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MousePosition {
     x: i32,
     y: i32,
+    // Seconds on the video's timeline when the sample was taken. Samples are about 16ms apart, but
+    // only as evenly as frames arrive (late frames give 33ms gaps) and thinning drops some, so
+    // consumers must place samples by this rather than by index. Never decreases along a track; see
+    // check_mouse_timestamps.
     timestamp: f64,
     isClicked: bool,
     cursor_type: String,
+    #[serde(default)]
+    click_type: Option<String>, // "single" or "double" on the sample where a press was seen
+    #[serde(default)]
+    velocity: Option<f64>, // Pixels/sec from the previous sample, filled in by stop_recording
+    #[serde(default)]
+    acceleration: Option<f64>, // Pixels/sec² from the previous sample's velocity
+    // False while the cursor is on another monitor; x/y are then outside the recorded area and the
+    // editor should hide the cursor rather than draw it at the edge
+    #[serde(default = "default_on_screen")]
+    on_screen: bool,
+    // EnumDisplayMonitors index of the recorded monitor under the cursor, None when it's on none of
+    // them. Tells which file of a multi-monitor recording the cursor belongs to.
+    #[serde(default)]
+    monitor: Option<usize>,
+}
+
+fn default_on_screen() -> bool {
+    true
+}
+
+// Sample whose timestamp is earlier than the one before it, from check_mouse_timestamps
+#[derive(Debug, Clone, Serialize)]
+pub struct TimestampRegression {
+    index: usize,
+    timestamp: f64,
+    previous: f64,
+}
+
+// A mouse button press or release from the global input hook
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClickEvent {
+    x: i32, // Relative to the recorded monitor, like MousePosition
+    y: i32,
+    button: String, // "left", "right", "middle" or "other"
+    timestamp: f64, // Seconds since the recording started
+    pressed: bool,
+}
+
+// Part of a recording marked to keep with mark_segment_start/mark_segment_end, in seconds on the
+// video's timeline
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Segment {
+    start: f64,
+    end: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -72,1104 +204,4964 @@ pub struct MonitorInfo {
     width: u32,
     height: u32,
     is_primary: bool,
+    device_id: Option<String>, // Device interface path, stable across reboots and enumeration order
+    is_mirror: bool,           // Same desktop rect as an earlier monitor (duplicated display)
+    orientation: Orientation,
+    scale_factor: f64, // Display scaling, e.g. 1.5 at 150%; the rect is in physical pixels
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+}
+
+// Options passed from the frontend to start_recording, also used as the capture flags
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingOptions {
+    preview: bool, // Emit downscaled JPEG frames via `recording-preview-frame` while recording
+    webcam_device_id: Option<String>, // Camera id from get_cameras, composited picture-in-picture
+    webcam_position: PipPosition,
+    webcam_size: f32, // PiP width as a fraction of the screen width
+    // Duplicate/drop frames so the output is exactly frame_rate. Off by default since it sends every
+    // frame through the CPU path; export_recording makes CFR copies instead. Each frame is stamped
+    // with the start of the grid slot its timespan falls in, on the same QPC clock as the mouse
+    // samples (see clock.rs), so the track stays within one frame interval of the video and doesn't
+    // drift over a long recording.
+    constant_frame_rate: bool,
+    show_capture_border: bool, // Windows 11 draws a yellow border around the captured monitor
+    self_exclusion: SelfExclusion,
+    watermark: Option<Watermark>, // Text or image burned into a corner of every frame
+    // Burn the elapsed (or wall-clock) time into a corner of every frame, styled by timestamp_style.
+    // Frames then always take the CPU path.
+    show_timestamp: bool,
+    timestamp_style: timestamp_overlay::TimestampStyle,
+    // Parts of the screen to hide in every frame, burned in before encoding; set_redaction_regions
+    // changes them mid-recording. Frames take the CPU path while there are any.
+    redaction_regions: Vec<redaction::Rect>,
+    redaction_mode: redaction::RedactionMode,
+    // Record clicks and cursor positions. This installs a system-wide input hook (rdev), which some
+    // security tools flag; with it off the video still shows the cursor but there is no mouse track.
+    track_input: bool,
+    // Capture in 16-bit float (scRGB) and tone-map to SDR ourselves, for HDR monitors where the
+    // 8-bit capture comes out washed out. The encoder only takes 8-bit input, so this is not an HDR
+    // (HEVC Main10) recording; it just maps HDR content into SDR properly.
+    hdr: bool,
+    codec: VideoCodec, // Checked against get_supported_encoders before the encoder is created
+    encoder_preset: Option<EncoderPreset>, // Overrides the preset implied by start_recording's quality
+    // Average bitrate in bps, overriding the preset's. There is no CBR/VBR choice or peak bitrate;
+    // see encoders::check_bitrate.
+    bitrate: Option<u32>,
+    // Skip frames arriving faster than this, before any readback or encoding, to save CPU/battery
+    // on mostly static screens. With constant_frame_rate the kept frames are repeated to fill the
+    // frame_rate grid, so the duration stays right.
+    capture_fps_cap: Option<u32>,
+    // Frames per second the encoder is configured for, independent of quality; start_recording's
+    // fps argument overrides it
+    frame_rate: u32,
+    // Record even inside a Remote Desktop session, where graphics capture often delivers only black
+    // frames (e.g. once the client is minimized). There is no GDI fallback to switch to.
+    allow_remote_session: bool,
+    // Mouse samples kept in memory. Past this the older half of the track is thinned out, keeping
+    // clicks and cursor changes, so multi-hour recordings don't grow without bound.
+    max_mouse_positions: usize,
+    // Poll the cursor type on its own thread this often (ms), so hovers shorter than the ~16ms
+    // position sampling still show up. None samples it with the position only.
+    cursor_type_interval_ms: Option<u32>,
+    // Cursor types shown for less than this are merged into the previous type when recording stops,
+    // to hide flicker; 0 keeps every change
+    cursor_type_min_duration_ms: u32,
+    // Cursor smoothing (0 to smoothing::MAX_SMOOTHING) applied to the mouse track stop_recording
+    // returns. 0 returns the recorded path; smooth_mouse_track can smooth it later instead.
+    cursor_smoothing: u32,
+    // Poll the foreground window's title and process and return the changes from stop_recording.
+    // Off by default, since window titles can be private.
+    track_windows: bool,
+    // Hand frames to the encoder from a separate thread through a bounded queue, so encoder stalls
+    // don't delay capture. Frames then always take the CPU path. None sends them directly.
+    frame_queue: Option<frame_queue::FrameQueueOptions>,
+    // Process working set (MB) above which the mouse track is thinned out to free memory, checked
+    // every few seconds. Encoded video goes to disk, so the track is the buffer that can shrink.
+    memory_cap_mb: Option<u32>,
+    coordinate_space: CoordinateSpace,
+    // Encode at this width and height instead of the monitor's, letterboxing on the CPU path. Set
+    // from a custom quality preset by start_recording.
+    output_size: Option<(u32, u32)>,
+    // Output file name, e.g. "demo_{date}_{time}". Placeholders: {date} (YYYY-MM-DD), {time}
+    // (HH-MM-SS, local), {monitor} (index), {resolution} (WIDTHxHEIGHT) and {counter} (recordings
+    // this session). Characters Windows doesn't allow are replaced with "_" and ".mp4" is added.
+    filename_template: Option<String>,
+    // Let other processes stop this recording through the stop_pipe named pipe by sending
+    // "stop <token>", e.g. a test script. None (the default) doesn't open the pipe.
+    stop_pipe_token: Option<String>,
+    // Seconds at the start that hardware encoders may fill with blocky frames while they settle.
+    // They are still recorded; metadata.warmup_secs tells the editor to trim them off.
+    warmup_secs: Option<f64>,
+    // Leave the taskbar out by recording only the rest of the monitor. The mouse track is relative
+    // to the cropped area. No effect when the taskbar auto-hides.
+    exclude_taskbar: bool,
+    #[serde(skip)]
+    output_path: Option<std::path::PathBuf>, // Resolved from filename_template by start_recording
+    #[serde(skip)]
+    taskbar_crop: Option<taskbar::Crop>, // Resolved from exclude_taskbar for the recorded monitor
+}
+
+// Coordinates of the mouse track and click events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CoordinateSpace {
+    #[default]
+    Monitor, // Relative to the recorded monitor's top-left corner, i.e. video pixels
+    Virtual, // Virtual desktop coordinates, as Windows reports them (can be negative)
+}
+
+// How the app keeps its own window out of the recording
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfExclusion {
+    #[default]
+    None,
+    Minimize,           // Minimize on start, restore on stop
+    ExcludeFromCapture, // Stay visible but hidden from capture (WDA_EXCLUDEFROMCAPTURE, Windows 10 2004+)
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self {
+            preview: false,
+            webcam_device_id: None,
+            webcam_position: PipPosition::default(),
+            webcam_size: 0.2,
+            constant_frame_rate: false,
+            show_capture_border: false,
+            self_exclusion: SelfExclusion::None,
+            watermark: None,
+            show_timestamp: false,
+            timestamp_style: timestamp_overlay::TimestampStyle::default(),
+            redaction_regions: Vec::new(),
+            redaction_mode: redaction::RedactionMode::Fill,
+            track_input: true,
+            hdr: false,
+            codec: VideoCodec::Hevc,
+            encoder_preset: None,
+            bitrate: None,
+            capture_fps_cap: None,
+            frame_rate: TARGET_FPS,
+            allow_remote_session: false,
+            max_mouse_positions: DEFAULT_MAX_MOUSE_POSITIONS,
+            cursor_type_interval_ms: None,
+            cursor_type_min_duration_ms: 100,
+            cursor_smoothing: 0,
+            track_windows: false,
+            frame_queue: None,
+            memory_cap_mb: None,
+            coordinate_space: CoordinateSpace::Monitor,
+            output_size: None,
+            filename_template: None,
+            stop_pipe_token: None,
+            warmup_secs: None,
+            exclude_taskbar: false,
+            output_path: None,
+            taskbar_crop: None,
+        }
+    }
+}
+
+// Counts of the intervals between captured frames, to tell steady capture from stutter
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct FrameIntervalHistogram {
+    under_16ms: u32,
+    from_16_to_33ms: u32,
+    from_33_to_50ms: u32,
+    over_50ms: u32,
+}
+
+impl FrameIntervalHistogram {
+    fn record(&mut self, interval: std::time::Duration) {
+        let bucket = match interval.as_secs_f64() * 1000.0 {
+            ms if ms < 16.0 => &mut self.under_16ms,
+            ms if ms < 33.0 => &mut self.from_16_to_33ms,
+            ms if ms <= 50.0 => &mut self.from_33_to_50ms,
+            _ => &mut self.over_50ms,
+        };
+        *bucket += 1;
+    }
+}
+
+// Live encoder stats, emitted as `recording-stats` about once a second
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingStats {
+    elapsed: f64,
+    frame_count: u32,
+    encoded_frames: u32,
+    dropped_frames: u32,
+    failed_frames: u32,    // Rejected by the encoder, see `recording-degraded`
+    queue_depth: usize,    // Frames waiting in the frame queue, 0 without one
+    queue_overflowed: u32, // Frames the full frame queue dropped so far
+    file_size: u64,
+    bitrate_bps: f64, // File growth since the previous stats event
+}
+
+// Memory used by the process and its recording buffers, from get_memory_stats and, every few
+// seconds while recording, the `recording-memory` event
+#[derive(Debug, Clone, Serialize)]
+pub struct MemoryStats {
+    working_set_bytes: u64,
+    mouse_positions: usize,
+    mouse_positions_bytes: u64, // Approximate, from the sample count
+    click_events: usize,
+    frame_queue_bytes: u64, // Frames waiting for the encoder, see RecordingOptions.frame_queue
+    over_cap: bool,         // Above memory_cap_mb; the mouse track was thinned
+}
+
+// Payload of `recording-degraded`: the encoder kept rejecting frames, so capture was slowed down
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingDegraded {
+    failed_frames: u32, // Frames lost to encoder errors so far
+    capture_fps: u32,   // Capture rate from now on
+    error: String,
+}
+
+// Payload of `resolution-changed`: the captured frames changed size mid-recording (e.g. a game
+// switched display modes). The encoder keeps its size, so frames are letterboxed into it from now
+// on, and the mouse track is in the new resolution's pixels.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolutionChanged {
+    width: u32,
+    height: u32,
+    previous_width: u32,
+    previous_height: u32,
+    encode_width: u32,
+    encode_height: u32,
+    elapsed: f64, // Seconds since the capture started
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreviewFrame {
+    width: u32,
+    height: u32,
+    timestamp: f64,
+    data: String, // base64 JPEG
+}
+
+// Errors returned from the Tauri commands.
+// Serialized as { "kind": ..., "message": ... } so the frontend can branch on the kind.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "message")]
+pub enum CommandError {
+    NotRecording,
+    AlreadyRecording,
+    AlreadyStarting,
+    NoVideoFile,
+    InvalidMonitor(String),
+    MonitorUnavailable(String),
+    NoDisplaysAvailable, // Headless, or an RDP session without an active desktop
+    RemoteSession,
+    InvalidSegment(String),
+    InvalidFilename(String),
+    InvalidPreset(String),
+    WebcamFailed(String),
+    WatermarkFailed(String),
+    AnalysisFailed(String),
+    InvalidVideo(String), // File exists but isn't a finalized MP4, may be repairable
+    ReleaseFailed(String),
+    ConcatFailed(String), // Incompatible inputs, or remuxing/encoding failed
+    ExportFailed(String), // Invalid trim range or fades, or re-encoding failed
+    ReplayFailed(String),
+    UnsupportedEncoder(String),
+    EncoderFailed(String),
+    NoPortsAvailable,
+    ServerFailed(String),
+    StopPipeFailed(String),
+    InputNotTracked,        // The recording was started with track_input off
+    MouseTrackUnavailable,
+    InvalidTrimRange(String),
+    RecordingTooShort(u32), // Stopped before enough frames were encoded; holds the frame count
+    ChunkOutOfBounds,
+    RangeOutOfBounds(String), // get_video_bytes offset past the end of the file, or no length
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandError::NotRecording => write!(f, "Not recording"),
+            CommandError::AlreadyRecording => write!(f, "A recording is already in progress"),
+            CommandError::AlreadyStarting => write!(f, "A recording is already being started"),
+            CommandError::NoVideoFile => write!(f, "No usable video file was created"),
+            CommandError::InvalidMonitor(id) => write!(f, "Invalid monitor ID: {}", id),
+            CommandError::MonitorUnavailable(e) => write!(f, "Monitor unavailable: {}", e),
+            CommandError::NoDisplaysAvailable => write!(f, "No displays are available to capture"),
+            CommandError::RemoteSession => write!(
+                f,
+                "Hardware capture is not supported over Remote Desktop and would record black frames"
+            ),
+            CommandError::InvalidSegment(e) => write!(f, "Invalid segment mark: {}", e),
+            CommandError::InvalidFilename(e) => write!(f, "Invalid filename template: {}", e),
+            CommandError::InvalidPreset(e) => write!(f, "Invalid quality preset: {}", e),
+            CommandError::WebcamFailed(e) => write!(f, "Failed to start webcam: {}", e),
+            CommandError::WatermarkFailed(e) => write!(f, "Failed to prepare watermark: {}", e),
+            CommandError::AnalysisFailed(e) => write!(f, "Video analysis failed: {}", e),
+            CommandError::InvalidVideo(e) => write!(f, "Video file is invalid: {}", e),
+            CommandError::ReleaseFailed(e) => write!(f, "Failed to release recording: {}", e),
+            CommandError::ConcatFailed(e) => write!(f, "Failed to join recordings: {}", e),
+            CommandError::ExportFailed(e) => write!(f, "Failed to export recording: {}", e),
+            CommandError::ReplayFailed(e) => write!(f, "Instant replay failed: {}", e),
+            CommandError::UnsupportedEncoder(e) => write!(f, "Unsupported encoder settings: {}", e),
+            CommandError::EncoderFailed(e) => write!(f, "Encoder failed: {}", e),
+            CommandError::NoPortsAvailable => write!(f, "No available ports for the video server"),
+            CommandError::ServerFailed(e) => write!(f, "Failed to start video server: {}", e),
+            CommandError::StopPipeFailed(e) => write!(f, "Failed to open the stop pipe: {}", e),
+            CommandError::InputNotTracked => write!(f, "This recording doesn't track input"),
+            CommandError::MouseTrackUnavailable => write!(f, "Failed to get mouse positions"),
+            CommandError::InvalidTrimRange(e) => write!(f, "Invalid trim range: {}", e),
+            CommandError::RecordingTooShort(frames) => write!(
+                f,
+                "Recording stopped too soon: only {} frames were captured",
+                frames
+            ),
+            CommandError::ChunkOutOfBounds => write!(f, "Chunk index out of bounds"),
+            CommandError::RangeOutOfBounds(e) => write!(f, "Byte range out of bounds: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+// Compact mouse track: first sample plus per-sample deltas, optionally run-length encoded.
+// Runs are (count, value) pairs. Timestamps are stored as microsecond deltas so decoding doesn't drift.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncodedMouseTrack {
+    count: usize,
+    base_x: i32,
+    base_y: i32,
+    base_timestamp: f64,
+    dx: Vec<(u32, i32)>,
+    dy: Vec<(u32, i32)>,
+    dt_us: Vec<(u32, i64)>, // A single run means the samples are on a fixed step
+    clicked: Vec<(u32, bool)>,
+    cursor_type: Vec<(u32, String)>,
+    #[serde(default)]
+    click_type: Vec<(u32, Option<String>)>,
+    #[serde(default)]
+    on_screen: Vec<(u32, bool)>, // Empty in tracks from before it existed, read as all on screen
+    #[serde(default)]
+    monitor: Vec<(u32, Option<usize>)>,
+}
+
+// How stop_recording hands the video to the frontend
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoDelivery {
+    #[default]
+    Http, // Local tiny_http server, also reachable from outside the webview
+    Protocol, // Tauri custom protocol, no open socket
+}
+
+// Details about the finished recording, returned alongside the video URL from stop_recording
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecordingMetadata {
+    requested_fps: u32,
+    actual_fps: f64, // Encoded frames divided by the time between first and last frame
+    frame_count: u32,
+    duration: f64,
+    constant_frame_rate: bool,
+    duplicated_frames: u32,    // CFR: frames repeated to fill capture gaps
+    skipped_frames: u32, // Frames dropped because they arrived faster than the target rate or fps cap
+    failed_frames: u32,  // Frames the encoder rejected; these are missing from the video
+    refresh_rate: Option<u32>, // Hz of the recorded monitor, None if it couldn't be queried
+    // Capture pixel format: "bgra8", or "rgba16f" (scRGB) with hdr, which is tone-mapped to 8-bit.
+    // Either way the encoder gets full-range sRGB (BT.709 primaries) and converts it to YUV itself,
+    // so the editor should treat the video like any sRGB source.
+    color_format: String,
+    color_range: String,     // "full"
+    color_primaries: String, // "bt709"
+    // Seconds from capture start to the first encoded frame. stop_recording already rebases the
+    // mouse track and click events onto the video's timeline by this much.
+    first_frame_latency: Option<f64>,
+    poster: Option<String>, // Base64 JPEG of the first frame, for showing before the video loads
+    frame_intervals: FrameIntervalHistogram, // Time between captured frames, before any skipping
+    // Marked segments in order; the editor keeps only these. Empty if none were marked.
+    segments: Vec<Segment>,
+    coordinate_space: CoordinateSpace, // Of the mouse track and click events
+    cursor_smoothing: u32, // Already applied to the returned mouse track; 0 means unsmoothed
+    // Virtual desktop position of the recorded monitor (the last one after switch_monitor); subtract
+    // it from Virtual coordinates to get video pixels
+    monitor_origin: (i32, i32),
+    // RecordingOptions.warmup_secs, capped at the duration: where the editor's trim should start
+    warmup_secs: f64,
 }
 
 // Add this global static for storing mouse positions
 lazy_static::lazy_static! {
     static ref MOUSE_POSITIONS: Mutex<VecDeque<MousePosition>> = Mutex::new(VecDeque::new());
+    static ref RECORDING_METADATA: Mutex<RecordingMetadata> = Mutex::new(RecordingMetadata::default());
+    static ref FRAME_TIMESTAMPS: Mutex<Vec<f64>> = Mutex::new(Vec::new());
 }
 
 // Main struct that handles the screen capture process
 struct CaptureHandler {
     encoder: Option<VideoEncoder>, // Handles video encoding, wrapped in Option to allow taking ownership later
+    frame_queue: Option<FrameQueue>, // Owns the encoder instead when frames are queued; see RecordingOptions
     start: Instant,                // Tracks when recording started
     last_mouse_capture: Instant,
     frame_count: u32,
     last_frame_time: Instant,
     dropped_frames: u32,
+    frame_intervals: FrameIntervalHistogram,
+    encoded_frames: u32,
+    first_encoded_at: Option<Instant>,
+    last_encoded_at: Option<Instant>,
+    preview_enabled: bool,
+    last_preview: Instant,
+    webcam: Option<(PipPosition, f32)>, // Set when a webcam is composited into each frame
+    watermark: Option<(Arc<WatermarkBitmap>, PipPosition, f32)>,
+    timestamp: Option<timestamp_overlay::TimestampStyle>, // Set with RecordingOptions.show_timestamp
+    upload_buffer: Vec<u8>, // Bottom-up copy of composited frames for the encoder
+    constant_frame_rate: bool,
+    cfr_base: Option<i64>, // Timespan of the first frame, slot 0 of the CFR grid
+    cfr_started_at: Option<Instant>,
+    cfr_next_slot: u64, // Next slot on the CFR grid that has not been written
+    fps: u32,           // Encoder frame rate, also the CFR grid rate
+    duplicated_frames: u32,
+    skipped_frames: u32,
+    failed_frames: u32,
+    consecutive_send_errors: u32,
+    capture_interval: Option<i64>, // From capture_fps_cap, 100ns units; lowered when the encoder falls behind
+    next_capture_pts: Option<i64>, // Frames before this timespan are skipped under the fps cap
+    encode_width: u32, // Encoder size; frames from a different monitor are letterboxed to it
+    encode_height: u32,
+    scale_buffer: Vec<u8>,
+    crop: Option<taskbar::Crop>, // From RecordingOptions.exclude_taskbar
+    crop_buffer: Vec<u8>,
+    first_pts: Option<i64>,
+    frame_timestamps: Vec<f64>, // Seconds from the first frame, one per encoded frame
+    last_stats_at: Instant,
+    last_stats_size: u64,
+    track_input: bool, // Sample cursor positions for the mouse track
+    max_mouse_positions: usize,
+    clicks_seen: usize, // CLICK_EVENTS already folded into the mouse track
+    buttons_down: u32,
+    hdr_lut: Option<Vec<u8>>, // Half-float bits -> tone-mapped sRGB byte, set when capturing Rgba16F
+    hdr_buffer: Vec<u8>,      // BGRA8 conversion of the current Rgba16F frame
+    frame_size: Option<(u32, u32)>, // Of the last captured frame, to notice resolution changes
 }
 
-// Replace the get_cursor_type function with this cleaner version
-fn get_cursor_type() -> String {
-    unsafe {
-        let mut cursor_info: CURSORINFO = std::mem::zeroed();
-        cursor_info.cbSize = std::mem::size_of::<CURSORINFO>() as u32;
+// Output file of the current recording, or of the last one once it has stopped
+fn current_video_path() -> Option<String> {
+    VIDEO_PATH.lock().ok().and_then(|path| path.clone())
+}
 
-        if GetCursorInfo(&mut cursor_info).as_bool() {
-            let current_handle = cursor_info.hCursor.0;
+fn set_video_path(path: Option<String>) {
+    if let Ok(mut current) = VIDEO_PATH.lock() {
+        *current = path;
+    }
+}
 
-            // Load system cursors to get their actual handles
-            let arrow = LoadCursorW(None, PCWSTR(IDC_ARROW.0 as *const u16))
-                .unwrap()
-                .0;
-            let ibeam = LoadCursorW(None, PCWSTR(IDC_IBEAM.0 as *const u16))
-                .unwrap()
-                .0;
-            let hand = LoadCursorW(None, PCWSTR(IDC_HAND.0 as *const u16))
-                .unwrap()
-                .0;
+// Emit an event to the frontend if the app handle is available
+fn emit_event<S: Serialize + Clone>(event: &str, payload: S) {
+    if let Ok(handle) = APP_HANDLE.lock() {
+        if let Some(app) = handle.as_ref() {
+            if let Err(e) = app.emit(event, payload) {
+                warn!("Failed to emit '{}': {}", event, e);
+            }
+        }
+    }
+}
 
-            // Compare with actual system cursor handles without logging every check
-            match current_handle {
-                h if h == arrow => "default".to_string(),
-                h if h == ibeam => "text".to_string(),
-                h if h == hand => "pointer".to_string(),
-                _ => "other".to_string(),
+fn main_window() -> Option<tauri::WebviewWindow> {
+    APP_HANDLE.lock().ok()?.as_ref()?.get_webview_window("main")
+}
+
+// Keep our own window out of the recording
+fn apply_self_exclusion(mode: SelfExclusion) {
+    if mode == SelfExclusion::None {
+        return;
+    }
+    let Some(window) = main_window() else {
+        warn!("Main window not found, cannot exclude it from the recording");
+        return;
+    };
+
+    let result = match mode {
+        SelfExclusion::Minimize => window.minimize(),
+        SelfExclusion::ExcludeFromCapture => window.set_content_protected(true),
+        SelfExclusion::None => Ok(()),
+    };
+
+    match result {
+        Ok(_) => {
+            info!("Applied self exclusion: {:?}", mode);
+            if let Ok(mut applied) = APPLIED_SELF_EXCLUSION.lock() {
+                *applied = mode;
+            }
+            // Give the minimize animation time to finish before the first frame
+            if mode == SelfExclusion::Minimize {
+                thread::sleep(std::time::Duration::from_millis(300));
             }
-        } else {
-            "default".to_string()
         }
+        Err(e) => warn!("Failed to apply self exclusion {:?}: {}", mode, e),
     }
 }
 
-// Implementation of the GraphicsCaptureApiHandler trait for our CaptureHandler
-// This defines how our handler will interact with the Windows screen capture API
-impl GraphicsCaptureApiHandler for CaptureHandler {
-    type Flags = String; // Type used for passing configuration flags
-    type Error = Box<dyn std::error::Error + Send + Sync>; // Type used for error handling
+// Undo whatever apply_self_exclusion did
+fn restore_app_window() {
+    let mode = match APPLIED_SELF_EXCLUSION.lock() {
+        Ok(mut applied) => std::mem::take(&mut *applied),
+        Err(_) => return,
+    };
+    if mode == SelfExclusion::None {
+        return;
+    }
+    let Some(window) = main_window() else {
+        return;
+    };
 
-    // Called when creating a new capture session
-    fn new(ctx: Context<Self::Flags>) -> Result<Self, Self::Error> {
-        println!("Created capture handler with flags: {}", ctx.flags);
+    let result = match mode {
+        SelfExclusion::Minimize => window.unminimize().and_then(|_| window.set_focus()),
+        SelfExclusion::ExcludeFromCapture => window.set_content_protected(false),
+        SelfExclusion::None => Ok(()),
+    };
+    if let Err(e) = result {
+        warn!("Failed to restore app window: {}", e);
+    }
+}
 
-        // Reset all states
-        SHOULD_STOP.store(false, Ordering::SeqCst);
-        ENCODING_FINISHED.store(false, Ordering::SeqCst);
-        ENCODER_ACTIVE.store(false, Ordering::SeqCst);
+// Nearest-neighbour downscale of a BGRA buffer so its width is at most max_width
+fn downscale_bgra(pixels: &[u8], width: u32, height: u32, max_width: u32) -> (Vec<u8>, u32, u32) {
+    if width <= max_width {
+        return (pixels.to_vec(), width, height);
+    }
 
-        // Get primary monitor dimensions
-        let monitor = Monitor::primary()?;
-        let width = monitor.width()?;
-        let height = monitor.height()?;
-        println!("Recording at resolution: {}x{}", width, height);
+    let out_width = max_width;
+    let out_height = ((height as u64 * max_width as u64) / width as u64).max(1) as u32;
+    let mut out = vec![0u8; (out_width * out_height * 4) as usize];
+
+    for y in 0..out_height {
+        let src_y = (y as u64 * height as u64 / out_height as u64) as usize;
+        for x in 0..out_width {
+            let src_x = (x as u64 * width as u64 / out_width as u64) as usize;
+            let src = (src_y * width as usize + src_x) * 4;
+            let dst = (y as usize * out_width as usize + x as usize) * 4;
+            out[dst..dst + 4].copy_from_slice(&pixels[src..src + 4]);
+        }
+    }
+
+    (out, out_width, out_height)
+}
 
-        // Create temporary file path for the video
-        let temp_dir = env::temp_dir();
-        let video_path = temp_dir.join(format!(
-            "screen_recording_{}.mp4",
-            Instant::now().elapsed().as_millis()
+// Decode the first frame of a finished recording into a downscaled base64 JPEG
+fn poster_frame(video_path: &str) -> Result<String, String> {
+    let mut poster = None;
+    media::for_each_video_frame(video_path, |frame| {
+        poster = Some(downscale_bgra(
+            frame.data,
+            frame.width,
+            frame.height,
+            POSTER_MAX_WIDTH,
         ));
+        false
+    })?;
+    let (pixels, width, height) = poster.ok_or("The video has no frames")?;
+    let jpeg = ImageEncoder::new(ImageFormat::Jpeg, ColorFormat::Bgra8)
+        .encode(&pixels, width, height)
+        .map_err(|e| e.to_string())?;
+    Ok(BASE64.encode(jpeg))
+}
 
-        unsafe {
-            VIDEO_PATH = Some(video_path.to_string_lossy().to_string());
-        }
-        println!("Setting video output path: {}", video_path.display());
+// Nearest-neighbour scale a top-down BGRA image into a rectangle of another BGRA image
+#[allow(clippy::too_many_arguments)]
+fn blit_scaled_bgra(
+    dst: &mut [u8],
+    dst_width: u32,
+    dst_height: u32,
+    src: &[u8],
+    src_width: u32,
+    src_height: u32,
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+) {
+    if src_width == 0 || src_height == 0 || width == 0 || height == 0 {
+        return;
+    }
 
-        // Clear previous video data
-        if let Ok(mut data) = VIDEO_DATA.lock() {
-            *data = None;
+    let end_x = (x + width).min(dst_width);
+    let end_y = (y + height).min(dst_height);
+
+    for dy in y..end_y {
+        let src_y = ((dy - y) as u64 * src_height as u64 / height as u64) as usize;
+        for dx in x..end_x {
+            let src_x = ((dx - x) as u64 * src_width as u64 / width as u64) as usize;
+            let s = (src_y * src_width as usize + src_x) * 4;
+            let d = (dy as usize * dst_width as usize + dx as usize) * 4;
+            dst[d..d + 4].copy_from_slice(&src[s..s + 4]);
         }
+    }
+}
 
-        // Create encoder with very conservative settings
-        println!("Creating encoder with resolution: {}x{}", width, height);
-        
-        // Always use full resolution
-        let encode_width = width;
-        let encode_height = height;
-        
-        println!("Using full resolution: {}x{}", encode_width, encode_height);
-        
-        // Use reasonable encoder settings with higher bitrate for better quality
-        let video_settings = VideoSettingsBuilder::new(encode_width, encode_height)
-            .frame_rate(30) // Higher frame rate for smoother video
-            .bitrate(10_000_000); // Higher bitrate for better quality at full resolution
+// Tone-mapped sRGB byte for every half-float bit pattern. Rgba16F captures are scRGB: linear,
+// 1.0 = 80 nits, with SDR content at the system's SDR white level and HDR highlights above it.
+// Values up to HDR_KNEE of SDR white pass through, brighter ones roll off smoothly towards 1.0.
+fn build_hdr_lut() -> Vec<u8> {
+    const SDR_WHITE: f32 = 2.5; // 200 nits, the Windows default SDR content brightness
+    const HDR_KNEE: f32 = 0.8;
 
-        let encoder = VideoEncoder::new(
-            video_settings,
-            AudioSettingsBuilder::default().disabled(true),
-            ContainerSettingsBuilder::default(),
-            &video_path,
-        )?;
+    (0..=u16::MAX)
+        .map(|bits| {
+            let value = (half_to_f32(bits) / SDR_WHITE).max(0.0);
+            let mapped = if value.is_nan() {
+                0.0
+            } else if value <= HDR_KNEE {
+                value
+            } else {
+                let range = 1.0 - HDR_KNEE;
+                HDR_KNEE + range * (1.0 - (-(value - HDR_KNEE) / range).exp())
+            };
+            let srgb = if mapped <= 0.0031308 {
+                mapped * 12.92
+            } else {
+                1.055 * mapped.powf(1.0 / 2.4) - 0.055
+            };
+            (srgb * 255.0).round().clamp(0.0, 255.0) as u8
+        })
+        .collect()
+}
 
-        println!("Encoder created successfully");
-        ENCODER_ACTIVE.store(true, Ordering::SeqCst);
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24), // Subnormal
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
 
-        Ok(Self {
-            encoder: Some(encoder),
-            start: Instant::now(),
-            last_mouse_capture: Instant::now(),
-            frame_count: 0,
-            last_frame_time: Instant::now(),
-            dropped_frames: 0,
-        })
+// Convert a top-down RGBA half-float frame to BGRA8 through the tone-mapping table
+fn tone_map_rgba16f(src: &[u8], lut: &[u8], dst: &mut Vec<u8>) {
+    dst.resize(src.len() / 2, 0);
+    for (px, out) in src.chunks_exact(8).zip(dst.chunks_exact_mut(4)) {
+        let channel = |i: usize| lut[u16::from_le_bytes([px[i * 2], px[i * 2 + 1]]) as usize];
+        out[0] = channel(2);
+        out[1] = channel(1);
+        out[2] = channel(0);
+        out[3] = 255;
     }
+}
 
-    // Called every time a new frame is captured
-    fn on_frame_arrived(
-        &mut self,
-        frame: &mut Frame,
-        capture_control: InternalCaptureControl,
-    ) -> Result<(), Self::Error> {
-        // Only process frames if encoder is active
-        if !ENCODER_ACTIVE.load(Ordering::SeqCst) {
-            return Ok(());
-        }
+// The encoder expects raw buffers bottom-up, while captured frames are top-down
+fn flip_rows_into(src: &[u8], width: u32, height: u32, dst: &mut Vec<u8>) {
+    let row = width as usize * 4;
+    dst.resize(row * height as usize, 0);
+    for (y, src_row) in src.chunks_exact(row).take(height as usize).enumerate() {
+        let dst_start = (height as usize - 1 - y) * row;
+        dst[dst_start..dst_start + row].copy_from_slice(src_row);
+    }
+}
 
-        let now = Instant::now();
-        let frame_time = now.duration_since(self.last_frame_time);
+impl CaptureHandler {
+    // Store the achieved frame rate so stop_recording can report it
+    fn publish_metadata(&self) {
+        let (frame_count, duration, actual_fps) = if self.constant_frame_rate {
+            // Every slot on the grid is written, so duration == frame_count / fps by construction
+            let frame_count = self.cfr_next_slot as u32;
+            let duration = frame_count as f64 / self.fps as f64;
+            let actual_fps = if frame_count > 0 {
+                self.fps as f64
+            } else {
+                0.0
+            };
+            (frame_count, duration, actual_fps)
+        } else {
+            let duration = match (self.first_encoded_at, self.last_encoded_at) {
+                (Some(first), Some(last)) => last.duration_since(first).as_secs_f64(),
+                _ => 0.0,
+            };
+            let actual_fps = if duration > 0.0 && self.encoded_frames > 1 {
+                (self.encoded_frames - 1) as f64 / duration
+            } else {
+                0.0
+            };
+            (self.encoded_frames, duration, actual_fps)
+        };
 
-        // Monitor for potential frame drops (expecting ~16.7ms between frames at 60fps)
-        if frame_time.as_millis() > 20 {
-            self.dropped_frames += 1;
-            //println!("Potential frame drop: {}ms between frames", frame_time.as_millis());
+        info!(
+            "Achieved {:.2} fps over {:.2}s ({} frames, requested {} fps, {} duplicated, {} skipped)",
+            actual_fps, duration, frame_count, self.fps, self.duplicated_frames, self.skipped_frames
+        );
+        if actual_fps > 0.0 && (actual_fps - self.fps as f64).abs() > self.fps as f64 * 0.1 {
+            warn!("Actual frame rate differs from the requested rate by more than 10%");
         }
 
-        self.frame_count += 1;
-        self.last_frame_time = now;
-
-        // Log performance stats every second
-        if self.start.elapsed().as_secs() > 0 && self.frame_count % 60 == 0 {
-            println!(
-                "Recording stats: frames={}, drops={}, avg_interval={:.1}ms",
-                self.frame_count,
-                self.dropped_frames,
-                self.start.elapsed().as_millis() as f32 / self.frame_count as f32
-            );
+        if let Ok(mut metadata) = RECORDING_METADATA.lock() {
+            metadata.requested_fps = self.fps;
+            metadata.actual_fps = actual_fps;
+            metadata.frame_count = frame_count;
+            metadata.duration = duration;
+            metadata.constant_frame_rate = self.constant_frame_rate;
+            metadata.duplicated_frames = self.duplicated_frames;
+            metadata.skipped_frames = self.skipped_frames;
+            metadata.failed_frames = self.failed_frames;
+            metadata.frame_intervals = self.frame_intervals;
+            metadata.color_format = match self.hdr_lut {
+                Some(_) => "rgba16f".to_string(),
+                None => "bgra8".to_string(),
+            };
+            metadata.color_range = "full".to_string();
+            metadata.color_primaries = "bt709".to_string();
+        }
+        if let Ok(mut timestamps) = FRAME_TIMESTAMPS.lock() {
+            *timestamps = self.frame_timestamps.clone();
         }
+    }
 
-        let current_time = self.start.elapsed();
+    // Whether frames are still going to the encoder, directly or through the queue
+    fn is_encoding(&self) -> bool {
+        self.encoder.is_some() || self.frame_queue.is_some()
+    }
 
-        // Use thread_local for last frame time to avoid unsafe blocks
-        thread_local! {
-            static LAST_FRAME_TIME: std::cell::RefCell<Option<Instant>> = std::cell::RefCell::new(None);
+    // Send upload_buffer to the encoder, or queue a copy of it
+    fn send_upload_buffer(
+        &mut self,
+        pts: i64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        match (&self.frame_queue, self.encoder.as_mut()) {
+            (Some(queue), _) => queue.push(&self.upload_buffer, pts)?,
+            (None, Some(encoder)) => encoder.send_frame_buffer(&self.upload_buffer, pts)?,
+            (None, None) => return Err("The encoder has already been finalized".into()),
         }
+        Ok(())
+    }
 
-        // Calculate frame delta more safely
-        let frame_delta = LAST_FRAME_TIME.with(|last| {
-            let mut last = last.borrow_mut();
-            let now = Instant::now();
-            let delta = match *last {
-                Some(prev) => now.duration_since(prev),
-                None => std::time::Duration::from_millis(16),
-            };
-            *last = Some(now);
-            delta
-        });
+    // Frames have to be read back to the CPU for overlays, for repeating frames in CFR mode,
+    // for the frame queue, and for scaling after switching to a monitor with another resolution
+    fn needs_cpu_path(&self, frame: &Frame) -> bool {
+        self.frame_queue.is_some()
+            || self.hdr_lut.is_some()
+            || self.webcam.is_some()
+            || self.watermark.is_some()
+            || self.timestamp.is_some()
+            || redaction::is_active()
+            || self.crop.is_some()
+            || self.constant_frame_rate
+            || frame.width() != self.encode_width
+            || frame.height() != self.encode_height
+    }
 
-        // Log frame timing during fast changes
-        if frame_delta.as_millis() < 20 {
-            static mut FRAME_COUNT: u32 = 0;
-            static mut LAST_LOG_TIME: Option<Instant> = None;
+    // Fold click events since the last mouse sample into the button state. Returns the click type
+    // of the first new press, if any.
+    fn consume_click_events(&mut self) -> Option<String> {
+        let events = CLICK_EVENTS.lock().ok()?;
+        let mut click_type = None;
+        for (index, event) in events.iter().enumerate().skip(self.clicks_seen) {
+            if !event.pressed {
+                self.buttons_down = self.buttons_down.saturating_sub(1);
+                continue;
+            }
+            self.buttons_down += 1;
+            if click_type.is_none() {
+                let is_double =
+                    events
+                        .range(..index)
+                        .rev()
+                        .find(|e| e.pressed)
+                        .is_some_and(|previous| {
+                            previous.button == event.button
+                                && event.timestamp - previous.timestamp <= DOUBLE_CLICK_SECS
+                        });
+                click_type = Some(if is_double { "double" } else { "single" }.to_string());
+            }
+        }
+        self.clicks_seen = events.len();
+        click_type
+    }
 
-            unsafe {
-                FRAME_COUNT += 1;
+    // Move the session state (encoder, counters, timing) out so a capture on another monitor can continue it
+    fn take_session(&mut self) -> Self {
+        Self {
+            encoder: self.encoder.take(),
+            frame_queue: self.frame_queue.take(),
+            start: self.start,
+            last_mouse_capture: self.last_mouse_capture,
+            frame_count: self.frame_count,
+            last_frame_time: self.last_frame_time,
+            dropped_frames: self.dropped_frames,
+            frame_intervals: self.frame_intervals,
+            encoded_frames: self.encoded_frames,
+            first_encoded_at: self.first_encoded_at,
+            last_encoded_at: self.last_encoded_at,
+            preview_enabled: self.preview_enabled,
+            last_preview: self.last_preview,
+            webcam: self.webcam,
+            watermark: self.watermark.take(),
+            timestamp: self.timestamp,
+            upload_buffer: std::mem::take(&mut self.upload_buffer),
+            constant_frame_rate: self.constant_frame_rate,
+            cfr_base: self.cfr_base,
+            cfr_started_at: self.cfr_started_at,
+            cfr_next_slot: self.cfr_next_slot,
+            fps: self.fps,
+            duplicated_frames: self.duplicated_frames,
+            skipped_frames: self.skipped_frames,
+            failed_frames: self.failed_frames,
+            consecutive_send_errors: self.consecutive_send_errors,
+            capture_interval: self.capture_interval,
+            next_capture_pts: self.next_capture_pts,
+            encode_width: self.encode_width,
+            encode_height: self.encode_height,
+            scale_buffer: std::mem::take(&mut self.scale_buffer),
+            crop: self.crop,
+            crop_buffer: std::mem::take(&mut self.crop_buffer),
+            first_pts: self.first_pts,
+            frame_timestamps: std::mem::take(&mut self.frame_timestamps),
+            last_stats_at: self.last_stats_at,
+            last_stats_size: self.last_stats_size,
+            track_input: self.track_input,
+            max_mouse_positions: self.max_mouse_positions,
+            clicks_seen: self.clicks_seen,
+            buttons_down: self.buttons_down,
+            hdr_lut: self.hdr_lut.take(),
+            hdr_buffer: std::mem::take(&mut self.hdr_buffer),
+            frame_size: self.frame_size,
+        }
+    }
 
-                if let Some(last_log) = LAST_LOG_TIME {
-                    if last_log.elapsed().as_secs() >= 1 {
-                        let fps = FRAME_COUNT as f32;
-                        println!(
-                            "Capture performance: {:.1} FPS (avg frame interval: {:.1}ms)",
-                            fps,
-                            1000.0 / fps
-                        );
-                        FRAME_COUNT = 0;
-                        LAST_LOG_TIME = Some(Instant::now());
-                    }
-                } else {
-                    LAST_LOG_TIME = Some(Instant::now());
-                }
+    // Whether capture_fps_cap says to skip this frame. Kept frames advance a fixed grid, so timing
+    // jitter around the interval doesn't lower the effective rate.
+    fn skip_for_fps_cap(&mut self, timespan: i64) -> bool {
+        let Some(interval) = self.capture_interval else {
+            return false;
+        };
+        match self.next_capture_pts {
+            Some(next) if timespan < next => true,
+            Some(next) if timespan - next < interval => {
+                self.next_capture_pts = Some(next + interval);
+                false
+            }
+            // First frame, or the screen was static for longer than an interval
+            _ => {
+                self.next_capture_pts = Some(timespan + interval);
+                false
             }
         }
+    }
 
-        // Log any encoding errors with more detail
-        if let Err(e) = self.encoder.as_mut().unwrap().send_frame(frame) {
-            println!(
-                "Encoding error during frame at {}s: {}",
-                current_time.as_secs_f64(),
-                e
-            );
-            println!("Frame details: size={}x{}", frame.width(), frame.height());
-            
-            // Check if this is a critical error or we can continue
-            if self.frame_count < 100 {
-                // If errors happen during the first few frames, they're likely critical
-                return Err(e.into());
-            } else {
-                // For later frames, log the error but try to continue
-                println!("Attempting to continue encoding despite error...");
-            }
+    // A frame the encoder rejected after startup. A run of failures means it can't keep up, so halve
+    // the capture rate (CFR repeats frames to fill the gaps) and tell the frontend, rather than
+    // silently ending up with a shorter video.
+    fn record_send_failure(&mut self, error: &str) {
+        self.failed_frames += 1;
+        self.consecutive_send_errors += 1;
+        if self.consecutive_send_errors < DEGRADED_AFTER_ERRORS {
+            return;
         }
+        self.consecutive_send_errors = 0;
 
-        // Capture mouse position every 16ms (approximately 60fps)
-        if self.last_mouse_capture.elapsed().as_millis() >= 16 {
-            unsafe {
-                let mut point = POINT::default();
-                if GetCursorPos(&mut point).as_bool() {
-                    let is_clicked = IS_MOUSE_CLICKED.load(Ordering::SeqCst);
-
-                    // Get cursor type
-                    let cursor_type = get_cursor_type();
-
-                    // Log cursor type changes
-                    if let Ok(mut last_type) = LAST_CURSOR_TYPE.lock() {
-                        if *last_type != cursor_type {
-                            println!("Cursor changed from '{}' to '{}'", last_type, cursor_type);
-                            *last_type = cursor_type.clone();
-                        }
-                    }
+        let current_fps = self
+            .capture_interval
+            .map_or(self.fps, |interval| (HNS_PER_SEC / interval) as u32)
+            .min(self.fps);
+        let capture_fps = (current_fps / 2).max(MIN_THROTTLED_FPS);
+        self.capture_interval = Some(HNS_PER_SEC / capture_fps as i64);
+        warn!(
+            "Encoder rejected {} frames in a row ({} in total), capturing at {} fps",
+            DEGRADED_AFTER_ERRORS, self.failed_frames, capture_fps
+        );
+        emit_event(
+            "recording-degraded",
+            RecordingDegraded {
+                failed_frames: self.failed_frames,
+                capture_fps,
+                error: error.to_string(),
+            },
+        );
+    }
 
-                    // Adjust coordinates relative to the monitor's position
-                    let relative_x = point.x - MONITOR_X;
-                    let relative_y = point.y - MONITOR_Y;
+    // Slot index a frame timespan falls into on the constant-frame-rate grid
+    fn cfr_slot(&mut self, timespan: i64) -> u64 {
+        let base = *self.cfr_base.get_or_insert(timespan);
+        self.cfr_started_at.get_or_insert_with(Instant::now);
+        ((timespan - base).max(0) * self.fps as i64 / HNS_PER_SEC) as u64
+    }
 
-                    let mouse_pos = MousePosition {
-                        x: relative_x,
-                        y: relative_y,
-                        timestamp: self.start.elapsed().as_secs_f64(),
-                        isClicked: is_clicked,
-                        cursor_type,
-                    };
+    fn cfr_pts(&self, slot: u64) -> i64 {
+        self.cfr_base.unwrap_or(0) + slot as i64 * HNS_PER_SEC / self.fps as i64
+    }
 
-                    // Only store positions that are within the monitor bounds
-                    if let Ok(mut positions) = MOUSE_POSITIONS.lock() {
-                        positions.push_back(mouse_pos);
-                    }
-                }
-            }
-            self.last_mouse_capture = Instant::now();
+    // Warn when the captured frames change size. needs_cpu_path already sends mismatched frames
+    // through the letterboxing path, so this only makes the change visible.
+    fn check_frame_size(&mut self, width: u32, height: u32) {
+        let previous = self.frame_size.replace((width, height));
+        let Some((previous_width, previous_height)) = previous else {
+            return;
+        };
+        if (previous_width, previous_height) == (width, height) {
+            return;
         }
+        warn!(
+            "Capture resolution changed from {}x{} to {}x{}, letterboxing into {}x{}",
+            previous_width, previous_height, width, height, self.encode_width, self.encode_height
+        );
+        emit_event(
+            "resolution-changed",
+            ResolutionChanged {
+                width,
+                height,
+                previous_width,
+                previous_height,
+                encode_width: self.encode_width,
+                encode_height: self.encode_height,
+                elapsed: self.start.elapsed().as_secs_f64(),
+            },
+        );
+    }
 
-        // Check if we should stop recording
-        if SHOULD_STOP.load(Ordering::SeqCst) {
-            println!("Stopping capture and finalizing encoder...");
-            if let Some(encoder) = self.encoder.take() {
-                // First, disable the encoder active flag to prevent any more frames from being sent
-                ENCODER_ACTIVE.store(false, Ordering::SeqCst);
-                
-                // Get the current path where video is being saved
-                let video_path = unsafe {
-                    if let Some(path) = &VIDEO_PATH {
-                        path.clone()
-                    } else {
-                        println!("Error: No video path available during encoder shutdown");
-                        ENCODING_FINISHED.store(true, Ordering::SeqCst);
-                        capture_control.stop();
-                        return Ok(());
-                    }
-                };
-                
-                println!("Video being saved to: {}", video_path);
-                
-                // Use a separate thread with a timeout for finalization
-                thread::spawn(move || {
-                    println!("Attempting to finalize encoder with safety timeout...");
-                    
-                    // Create a channel to communicate when encoder.finish() completes
-                    let (tx, rx) = mpsc::channel();
-                    
-                    // Check if the file exists and has content before we even try to finalize
-                    let pre_finalize_size = match std::fs::metadata(&video_path) {
-                        Ok(metadata) => {
-                            let size = metadata.len();
-                            println!("Pre-finalization file size: {} bytes ({:.2} MB)", 
-                                size, size as f64 / (1024.0 * 1024.0));
-                            size
-                        },
-                        Err(e) => {
-                            println!("Error checking file before finalization: {}", e);
-                            0
-                        }
-                    };
-                    
-                    // If we already have some data in the file, we might be able to use it
-                    let has_usable_data = pre_finalize_size > 1024 * 1024; // More than 1MB
-                    
-                    // Spawn another thread that will actually call encoder.finish()
-                    thread::spawn(move || {
-                        println!("Encoder finalization worker thread started");
-                        let result = encoder.finish();
-                        // Send the result back, don't care if receiver is gone
-                        let _ = tx.send(result);
-                        println!("Encoder finalization worker thread completed");
-                    });
-                    
-                    // Use a much shorter timeout if we already have usable data
-                    let timeout = if has_usable_data {
-                        std::time::Duration::from_secs(5) // Short timeout if we have data
-                    } else {
-                        std::time::Duration::from_secs(10) // Longer timeout if we need finalization
-                    };
-                    
-                    println!("Waiting up to {}s for encoder to finalize...", timeout.as_secs());
-                    
-                    // Wait for finish() to complete with a timeout
-                    match rx.recv_timeout(timeout) {
-                        Ok(Ok(_)) => {
-                            println!("Encoder successfully finalized");
-                        }
-                        Ok(Err(e)) => {
-                            println!("Encoder returned an error during finalization: {}", e);
-                            println!("Will attempt to use the partially encoded video");
-                        }
-                        Err(e) => {
-                            println!("Timeout or error waiting for encoder to finalize: {}", e);
-                            println!("The encoder worker thread may still be running - proceeding with current file regardless");
-                        }
-                    }
-                    
-                    // Signal that encoding is finished regardless of the outcome
-                    ENCODING_FINISHED.store(true, Ordering::SeqCst);
-                    
-                    // Check if the video file exists and has a reasonable size
-                    match std::fs::metadata(&video_path) {
-                        Ok(metadata) => {
-                            let size = metadata.len();
-                            if size > 0 {
-                                println!("Video file created successfully: {} bytes ({:.2} MB)", 
-                                    size, size as f64 / (1024.0 * 1024.0));
-                                
-                                if size > pre_finalize_size {
-                                    println!("File grew by {} bytes during finalization", size - pre_finalize_size);
-                                } else if size == pre_finalize_size {
-                                    println!("File size did not change during finalization");
-                                }
-                            } else {
-                                println!("Warning: Video file exists but has zero size");
-                            }
-                        },
-                        Err(e) => {
-                            println!("Warning: Unable to access video file after recording: {}", e);
-                        }
-                    }
-                });
-            } else {
-                // If encoder was already taken
-                ENCODING_FINISHED.store(true, Ordering::SeqCst);
+    // Remember when an encoded frame lands in the video; the encoder also counts from the first pts
+    fn record_frame_pts(&mut self, pts: i64) {
+        if self.first_pts.is_none() {
+            // Frame timespans are on the recording clock, so this is when the first frame was
+            // captured, exactly; mouse samples are rebased onto the video by it
+            let latency = clock::secs_at(pts).unwrap_or_else(|| self.start.elapsed().as_secs_f64());
+            info!(
+                "First frame captured {:.0}ms after capture start",
+                latency * 1000.0
+            );
+            if let Ok(mut metadata) = RECORDING_METADATA.lock() {
+                metadata.first_frame_latency = Some(latency);
             }
-            
-            // Stop the capture immediately, don't wait for encoding
-            capture_control.stop();
-            println!("Capture stopped successfully");
+            emit_event("recording-first-frame", latency);
         }
+        let first = *self.first_pts.get_or_insert(pts);
+        self.frame_timestamps
+            .push((pts - first) as f64 / HNS_PER_SEC as f64);
+    }
 
-        Ok(())
+    // Work out the effective bitrate from how much the output file grew since the last call
+    fn emit_stats(&mut self) {
+        let file_size = current_video_path()
+            .and_then(|path| std::fs::metadata(path).ok())
+            .map(|metadata| metadata.len())
+            .unwrap_or(self.last_stats_size);
+        let interval = self.last_stats_at.elapsed().as_secs_f64();
+        let bitrate_bps = if interval > 0.0 {
+            file_size.saturating_sub(self.last_stats_size) as f64 * 8.0 / interval
+        } else {
+            0.0
+        };
+        self.last_stats_at = Instant::now();
+        self.last_stats_size = file_size;
+
+        debug!(
+            "Encoding at {:.2} Mbps, file size {:.2} MB",
+            bitrate_bps / 1_000_000.0,
+            file_size as f64 / (1024.0 * 1024.0)
+        );
+        emit_event(
+            "recording-stats",
+            RecordingStats {
+                elapsed: self.start.elapsed().as_secs_f64(),
+                frame_count: self.frame_count,
+                encoded_frames: self.encoded_frames,
+                dropped_frames: self.dropped_frames,
+                failed_frames: self.failed_frames,
+                queue_depth: self.frame_queue.as_ref().map_or(0, |queue| queue.depth()),
+                queue_overflowed: self
+                    .frame_queue
+                    .as_ref()
+                    .map_or(0, |queue| queue.overflowed()),
+                file_size,
+                bitrate_bps,
+            },
+        );
     }
 
-    // Called when capture session ends
-    fn on_closed(&mut self) -> Result<(), Self::Error> {
-        println!("Capture session ended");
-        // Ensure states are reset
-        ENCODER_ACTIVE.store(false, Ordering::SeqCst);
-        ENCODING_FINISHED.store(true, Ordering::SeqCst);
-        RECORDING.store(false, Ordering::SeqCst);
-        cleanup_resources();
+    // Repeat the last sent frame up to (not including) `slot` so capture gaps don't shorten the video
+    fn fill_cfr_gap(&mut self, slot: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.upload_buffer.is_empty() {
+            self.cfr_next_slot = slot;
+            return Ok(());
+        }
+
+        while self.cfr_next_slot < slot {
+            let pts = self.cfr_pts(self.cfr_next_slot);
+            self.send_upload_buffer(pts)?;
+            self.record_frame_pts(pts);
+            self.cfr_next_slot += 1;
+            self.duplicated_frames += 1;
+        }
         Ok(())
     }
-}
 
-// Add the monitor enumeration callback
-extern "system" fn monitor_enum_proc(
-    monitor: HMONITOR,
-    _: HDC,
-    _: *mut RECT,
-    data: LPARAM,
-) -> BOOL {
-    unsafe {
-        let monitors = &mut *(data.0 as *mut Vec<HMONITOR>);
-        monitors.push(monitor);
-        BOOL::from(true)
+    // Pad the tail of a CFR recording up to the stop time, the screen may have been static
+    fn finish_cfr(&mut self) {
+        if !self.constant_frame_rate || !self.is_encoding() {
+            return;
+        }
+        if let Some(started) = self.cfr_started_at {
+            let slot = (started.elapsed().as_secs_f64() * self.fps as f64) as u64;
+            if let Err(e) = self.fill_cfr_gap(slot) {
+                warn!("Failed to pad constant frame rate tail: {}", e);
+            }
+        }
     }
-}
 
-// Replace the get_monitors command with the Win32 version
-#[tauri::command]
-async fn get_monitors() -> Result<Vec<MonitorInfo>, String> {
-    println!("Starting monitor enumeration using Win32 API...");
+    // Finish the encoder on a worker thread; ENCODING_FINISHED is set once it's done or timed out
+    fn finalize_encoder(&mut self) {
+        self.finish_cfr();
+        self.publish_metadata();
+        let encoder = self.encoder.take();
+        let frame_queue = self.frame_queue.take();
+        if encoder.is_some() || frame_queue.is_some() {
+            // First, disable the encoder active flag to prevent any more frames from being sent
+            ENCODER_ACTIVE.store(false, Ordering::SeqCst);
 
-    unsafe {
-        let mut monitors: Vec<HMONITOR> = Vec::new();
-        let monitors_ptr = &mut monitors as *mut Vec<HMONITOR>;
+            // Get the current path where video is being saved
+            let Some(video_path) = current_video_path() else {
+                error!("No video path available during encoder shutdown");
+                ENCODING_FINISHED.store(true, Ordering::SeqCst);
+                return;
+            };
 
-        EnumDisplayMonitors(
-            HDC::default(),
-            None,
-            Some(monitor_enum_proc),
-            LPARAM(monitors_ptr as isize),
-        );
+            info!("Video being saved to: {}", video_path);
 
-        println!("Found {} monitor handles", monitors.len());
+            // Use a separate thread with a timeout for finalization
+            thread::spawn(move || {
+                info!("Attempting to finalize encoder with safety timeout...");
 
-        let mut monitor_infos = Vec::new();
+                // Create a channel to communicate when encoder.finish() completes
+                let (tx, rx) = mpsc::channel();
 
-        for (index, &monitor) in monitors.iter().enumerate() {
-            let mut monitor_info: MONITORINFOEXW = zeroed();
-            monitor_info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+                // Check if the file exists and has content before we even try to finalize
+                let pre_finalize_size = match std::fs::metadata(&video_path) {
+                    Ok(metadata) => {
+                        let size = metadata.len();
+                        debug!(
+                            "Pre-finalization file size: {} bytes ({:.2} MB)",
+                            size,
+                            size as f64 / (1024.0 * 1024.0)
+                        );
+                        size
+                    }
+                    Err(e) => {
+                        warn!("Error checking file before finalization: {}", e);
+                        0
+                    }
+                };
 
-            if GetMonitorInfoW(monitor, &mut monitor_info.monitorInfo as *mut _).as_bool() {
-                let rect = monitor_info.monitorInfo.rcMonitor;
-                println!(
-                    "Monitor {}: Position ({}, {}), Size {}x{}",
-                    index,
-                    rect.left,
-                    rect.top,
-                    rect.right - rect.left,
-                    rect.bottom - rect.top
-                );
+                // If we already have some data in the file, we might be able to use it
+                let has_usable_data = pre_finalize_size > 1024 * 1024; // More than 1MB
 
-                monitor_infos.push(MonitorInfo {
-                    id: index.to_string(),
-                    name: format!("Display {}", index + 1),
-                    x: rect.left,
-                    y: rect.top,
-                    width: (rect.right - rect.left) as u32,
-                    height: (rect.bottom - rect.top) as u32,
-                    is_primary: monitor_info.monitorInfo.dwFlags & 1 == 1,
+                // Spawn another thread that will actually call encoder.finish()
+                thread::spawn(move || {
+                    debug!("Encoder finalization worker thread started");
+                    // Queued frames are sent before the queue finishes its encoder
+                    let result = match (encoder, frame_queue) {
+                        (Some(encoder), _) => encoder.finish().map_err(|e| e.to_string()),
+                        (None, Some(frame_queue)) => frame_queue.finish(),
+                        (None, None) => Ok(()),
+                    };
+                    // Send the result back, don't care if receiver is gone
+                    let _ = tx.send(result);
+                    debug!("Encoder finalization worker thread completed");
                 });
-            } else {
-                println!("Failed to get info for monitor {}", index);
-            }
-        }
 
-        println!("Monitor details: {:#?}", monitor_infos);
-        Ok(monitor_infos)
-    }
-}
+                // Use a much shorter timeout if we already have usable data
+                let timeout = if has_usable_data {
+                    std::time::Duration::from_secs(5) // Short timeout if we have data
+                } else {
+                    std::time::Duration::from_secs(10) // Longer timeout if we need finalization
+                };
 
-// Add this function to clean up resources
-fn cleanup_resources() {
-    println!("Cleaning up resources...");
+                info!(
+                    "Waiting up to {}s for encoder to finalize...",
+                    timeout.as_secs()
+                );
 
-    // Make sure encoder is no longer active
-    ENCODER_ACTIVE.store(false, Ordering::SeqCst);
-    
-    // Ensure encoding is marked as finished to prevent deadlocks
-    ENCODING_FINISHED.store(true, Ordering::SeqCst);
+                // Wait for finish() to complete with a timeout
+                match rx.recv_timeout(timeout) {
+                    Ok(Ok(_)) => {
+                        info!("Encoder successfully finalized");
+                    }
+                    Ok(Err(e)) => {
+                        warn!("Encoder returned an error during finalization: {}", e);
+                        warn!("Will attempt to use the partially encoded video");
+                    }
+                    Err(e) => {
+                        warn!("Timeout or error waiting for encoder to finalize: {}", e);
+                        debug!("The encoder worker thread may still be running - proceeding with current file regardless");
+                    }
+                }
 
-    // Clean up any running servers
-    if let Ok(mut ports) = SERVER_PORTS.lock() {
-        if !ports.is_empty() {
-            println!("Cleaning up {} server ports", ports.len());
-            ports.clear();
-        }
-    }
+                // Signal that encoding is finished regardless of the outcome
+                ENCODING_FINISHED.store(true, Ordering::SeqCst);
 
-    // First, drop the memory map
-    {
-        let mut mmap = VIDEO_MMAP.lock();
-        if mmap.is_some() {
-            println!("Releasing memory map");
-            *mmap = None;
+                // Check if the video file exists and has a reasonable size
+                match std::fs::metadata(&video_path) {
+                    Ok(metadata) => {
+                        let size = metadata.len();
+                        if size > 0 {
+                            info!(
+                                "Video file created successfully: {} bytes ({:.2} MB)",
+                                size,
+                                size as f64 / (1024.0 * 1024.0)
+                            );
+
+                            if size > pre_finalize_size {
+                                debug!(
+                                    "File grew by {} bytes during finalization",
+                                    size - pre_finalize_size
+                                );
+                            } else if size == pre_finalize_size {
+                                debug!("File size did not change during finalization");
+                            }
+                        } else {
+                            warn!("Video file exists but has zero size");
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Unable to access video file after recording: {}", e);
+                    }
+                }
+            });
+        } else {
+            // If encoder was already taken
+            ENCODING_FINISHED.store(true, Ordering::SeqCst);
         }
     }
 
-    // Reset all state flags
-    RECORDING.store(false, Ordering::SeqCst);
-    SHOULD_STOP.store(false, Ordering::SeqCst);
-    
-    // Clear mouse positions
-    if let Ok(mut positions) = MOUSE_POSITIONS.lock() {
-        positions.clear();
+    // The capture ended without stop_recording (monitor unplugged, display driver reset, encoder
+    // failure). Finalize what was recorded and leave RECORDING set, so stop_recording still serves
+    // the partial take like it does after a finalization timeout.
+    fn interrupt(&mut self, reason: &str) {
+        warn!("Recording interrupted: {}", reason);
+        SHOULD_STOP.store(true, Ordering::SeqCst);
+        self.finalize_encoder();
+        emit_event("recording-interrupted", reason.to_string());
     }
 
-    // Signal click listener to stop
-    SHOULD_LISTEN_CLICKS.store(false, Ordering::SeqCst);
-    
-    // Note: we don't clear VIDEO_PATH here because the server might still need it
-    
-    println!("Resource cleanup completed");
-}
-
-// Modify start_recording
-#[tauri::command]
-async fn start_recording(monitor_id: Option<String>, quality: Option<String>) -> Result<(), String> {
-    println!("Starting recording with monitor_id: {:?}, quality: {:?}", monitor_id, quality);
+    // Copy the frame to the CPU, draw overlays into it and send the raw buffer to the encoder
+    fn send_composited_frame(
+        &mut self,
+        frame: &mut Frame,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let timespan = frame.timespan().Duration;
 
-    // First, ensure any previous recording is fully cleaned up
-    if RECORDING.load(Ordering::SeqCst) {
-        println!("Detected active recording, cleaning up first...");
-        SHOULD_STOP.store(true, Ordering::SeqCst);
+        let slot = if self.constant_frame_rate {
+            let slot = self.cfr_slot(timespan);
+            if slot < self.cfr_next_slot {
+                // Arrived faster than the target rate, this slot is already filled
+                self.skipped_frames += 1;
+                return Ok(());
+            }
+            self.fill_cfr_gap(slot)?;
+            Some(slot)
+        } else {
+            None
+        };
 
-        // Wait a bit for cleanup
-        thread::sleep(std::time::Duration::from_millis(500));
-    }
+        let mut buffer = frame.buffer()?;
+        let width = buffer.width();
+        let height = buffer.height();
+        let raw = buffer.as_nopadding_buffer()?;
+        let pixels: &mut [u8] = match &self.hdr_lut {
+            Some(lut) => {
+                tone_map_rgba16f(raw, lut, &mut self.hdr_buffer);
+                &mut self.hdr_buffer
+            }
+            None => raw,
+        };
+        // Frames that changed size since the crop was worked out are letterboxed whole
+        let (pixels, width, height) = match self.crop {
+            Some(crop) if crop.x + crop.width <= width && crop.y + crop.height <= height => {
+                taskbar::crop_into(pixels, width, crop, &mut self.crop_buffer);
+                (&mut self.crop_buffer[..], crop.width, crop.height)
+            }
+            _ => (pixels, width, height),
+        };
 
-    // Force cleanup regardless of previous state
-    cleanup_resources();
+        // Before the overlays, which are ours to show
+        if redaction::is_active() {
+            redaction::apply(pixels, width, height);
+        }
 
-    // Clear previous mouse positions
-    if let Ok(mut positions) = MOUSE_POSITIONS.lock() {
-        positions.clear();
-        println!("Cleared previous mouse positions");
-    }
-    
-    // Parse the quality setting
-    let quality_setting = match quality.as_deref() {
-        Some("high") => {
-            println!("Using high quality encoding");
-            "high"
+        if let Some((position, size)) = self.webcam {
+            media::composite_webcam(pixels, width, height, position, size);
         }
-        Some("medium") => { 
-            println!("Using medium quality encoding");
-            "medium"
+
+        if let Some((bitmap, position, opacity)) = &self.watermark {
+            watermark::composite_watermark(pixels, width, height, bitmap, *position, *opacity);
         }
-        Some("low") => {
-            println!("Using low quality encoding");
-            "low"
+
+        if let Some(style) = &self.timestamp {
+            let text = timestamp_overlay::text(style.format, self.start.elapsed());
+            timestamp_overlay::draw(pixels, width, height, &text, style);
         }
-        _ => {
-            println!("No quality specified, defaulting to high quality");
-            "high"
+
+        if width == self.encode_width && height == self.encode_height {
+            flip_rows_into(pixels, width, height, &mut self.upload_buffer);
+        } else {
+            // Letterbox into the encoder size, keeping the aspect ratio
+            let (encode_width, encode_height) = (self.encode_width, self.encode_height);
+            let scale =
+                (encode_width as f64 / width as f64).min(encode_height as f64 / height as f64);
+            let fit_width = ((width as f64 * scale) as u32).clamp(1, encode_width);
+            let fit_height = ((height as f64 * scale) as u32).clamp(1, encode_height);
+
+            self.scale_buffer.clear();
+            self.scale_buffer.extend(
+                std::iter::repeat_n([0, 0, 0, 255], (encode_width * encode_height) as usize)
+                    .flatten(),
+            );
+            blit_scaled_bgra(
+                &mut self.scale_buffer,
+                encode_width,
+                encode_height,
+                pixels,
+                width,
+                height,
+                (encode_width - fit_width) / 2,
+                (encode_height - fit_height) / 2,
+                fit_width,
+                fit_height,
+            );
+            flip_rows_into(
+                &self.scale_buffer,
+                encode_width,
+                encode_height,
+                &mut self.upload_buffer,
+            );
         }
-    };
-    
-    // Store quality setting in a thread-local for the encoder to access
-    // (Note: we still set this for future use, but currently ignore it to always use full resolution)
-    thread_local! {
-        static QUALITY_SETTING: std::cell::RefCell<&'static str> = std::cell::RefCell::new("high");
-    }
-    
-    QUALITY_SETTING.with(|quality| {
-        *quality.borrow_mut() = "high"; // Always use high quality
-    });
-    
-    let monitor = if let Some(ref id) = monitor_id {
-        println!("Trying to get monitor with ID: {}", id);
-        let index = id.parse::<usize>().map_err(|e| {
-            println!("Failed to parse monitor ID: {:?}", e);
-            "Invalid monitor ID".to_string()
-        })?;
 
-        Monitor::from_index(index + 1).map_err(|e| {
-            println!("Failed to get monitor from index: {:?}", e);
-            e.to_string()
-        })?
-    } else {
-        println!("No monitor ID provided, using primary");
-        Monitor::primary().map_err(|e| {
-            println!("Failed to get primary monitor: {:?}", e);
-            e.to_string()
-        })?
-    };
+        let pts = match slot {
+            Some(slot) => {
+                self.cfr_next_slot = slot + 1;
+                self.cfr_pts(slot)
+            }
+            None => timespan,
+        };
+        self.send_upload_buffer(pts)?;
+        self.record_frame_pts(pts);
+        Ok(())
+    }
+}
 
-    // Get monitor info to get the correct position
+// Replace the get_cursor_type function with this cleaner version
+fn get_cursor_type() -> String {
     unsafe {
-        let mut monitors: Vec<HMONITOR> = Vec::new();
-        let monitors_ptr = &mut monitors as *mut Vec<HMONITOR>;
+        let mut cursor_info: CURSORINFO = std::mem::zeroed();
+        cursor_info.cbSize = std::mem::size_of::<CURSORINFO>() as u32;
 
-        EnumDisplayMonitors(
-            HDC::default(),
-            None,
-            Some(monitor_enum_proc),
-            LPARAM(monitors_ptr as isize),
-        );
+        if GetCursorInfo(&mut cursor_info).as_bool() {
+            let current_handle = cursor_info.hCursor.0;
 
-        let monitor_index = monitor_id
-            .as_ref()
-            .and_then(|id| id.parse::<usize>().ok())
-            .unwrap_or(0);
+            // Load system cursors to get their actual handles
+            let arrow = LoadCursorW(None, PCWSTR(IDC_ARROW.0 as *const u16))
+                .unwrap()
+                .0;
+            let ibeam = LoadCursorW(None, PCWSTR(IDC_IBEAM.0 as *const u16))
+                .unwrap()
+                .0;
+            let hand = LoadCursorW(None, PCWSTR(IDC_HAND.0 as *const u16))
+                .unwrap()
+                .0;
+
+            // Compare with actual system cursor handles without logging every check
+            match current_handle {
+                h if h == arrow => "default".to_string(),
+                h if h == ibeam => "text".to_string(),
+                h if h == hand => "pointer".to_string(),
+                _ => "other".to_string(),
+            }
+        } else {
+            "default".to_string()
+        }
+    }
+}
+
+// Temp file for a recording without a filename template
+fn default_recording_path() -> std::path::PathBuf {
+    let millis = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    env::temp_dir().join(format!("screen_recording_{}.mp4", millis))
+}
+
+// Implementation of the GraphicsCaptureApiHandler trait for our CaptureHandler
+// This defines how our handler will interact with the Windows screen capture API
+impl GraphicsCaptureApiHandler for CaptureHandler {
+    type Flags = RecordingOptions; // Type used for passing configuration flags
+    type Error = Box<dyn std::error::Error + Send + Sync>; // Type used for error handling
+
+    // Called when creating a new capture session
+    fn new(ctx: Context<Self::Flags>) -> Result<Self, Self::Error> {
+        // Continuing a recording on another monitor: reuse the running encoder and state
+        if let Some(mut handoff) = CAPTURE_HANDOFF
+            .lock()
+            .ok()
+            .and_then(|mut handoff| handoff.take())
+        {
+            info!("Continuing capture session on a new monitor");
+            handoff.crop = ctx.flags.taskbar_crop;
+            return Ok(handoff);
+        }
+
+        info!("Created capture handler with flags: {:?}", ctx.flags);
+
+        // Reset all states
+        SHOULD_STOP.store(false, Ordering::SeqCst);
+        ENCODING_FINISHED.store(false, Ordering::SeqCst);
+        ENCODER_ACTIVE.store(false, Ordering::SeqCst);
+
+        // Get primary monitor dimensions
+        let monitor = Monitor::primary()?;
+        let width = monitor.width()?;
+        let height = monitor.height()?;
+        info!("Recording at resolution: {}x{}", width, height);
+        let (width, height) = ctx
+            .flags
+            .taskbar_crop
+            .map_or((width, height), |crop| (crop.width, crop.height));
+
+        // Create temporary file path for the video, unless start_recording already named it
+        let video_path = ctx
+            .flags
+            .output_path
+            .clone()
+            .unwrap_or_else(default_recording_path);
+
+        set_video_path(Some(video_path.to_string_lossy().to_string()));
+        session::add(&video_path.to_string_lossy());
+        info!("Setting video output path: {}", video_path.display());
+
+        // Clear previous video data
+        if let Ok(mut data) = VIDEO_DATA.lock() {
+            *data = None;
+        }
+
+        // Create encoder with very conservative settings
+        info!("Creating encoder with resolution: {}x{}", width, height);
+        
+        // Full resolution unless a preset picked an output size
+        let (encode_width, encode_height) = ctx.flags.output_size.unwrap_or((width, height));
+        
+        info!("Encoding at resolution: {}x{}", encode_width, encode_height);
+        
+        let preset = ctx.flags.encoder_preset.unwrap_or_default();
+        let bitrate = ctx.flags.bitrate.unwrap_or(preset.bitrate());
+        info!("Encoder preset: {:?} ({} bps)", preset, bitrate);
+        let sub_type = match ctx.flags.codec {
+            VideoCodec::H264 => VideoSettingsSubType::H264,
+            _ => VideoSettingsSubType::HEVC,
+        };
+        let video_settings = VideoSettingsBuilder::new(encode_width, encode_height)
+            .sub_type(sub_type)
+            .frame_rate(ctx.flags.frame_rate)
+            .bitrate(bitrate);
+
+        let encoder = VideoEncoder::new(
+            video_settings,
+            AudioSettingsBuilder::default().disabled(true),
+            ContainerSettingsBuilder::default(),
+            &video_path,
+        )?;
+
+        info!("Encoder created successfully");
+        let (encoder, frame_queue) = match ctx.flags.frame_queue.clone() {
+            Some(options) => (None, Some(FrameQueue::start(encoder, options))),
+            None => (Some(encoder), None),
+        };
+        ENCODER_ACTIVE.store(true, Ordering::SeqCst);
+        if ctx.flags.hdr {
+            // The encoder only accepts 8-bit BGRA, so HEVC Main10 passthrough isn't possible
+            info!("HDR mode: capturing scRGB and tone-mapping to SDR (8-bit HEVC output)");
+        }
+
+        // Mouse samples, clicks and frames are all timed from here on the recording clock
+        clock::start();
+        if let Ok(mut epoch) = CLICK_EPOCH.lock() {
+            *epoch = Some(SystemTime::now());
+        }
+
+        Ok(Self {
+            encoder,
+            frame_queue,
+            start: Instant::now(),
+            last_mouse_capture: Instant::now(),
+            frame_count: 0,
+            last_frame_time: Instant::now(),
+            dropped_frames: 0,
+            frame_intervals: FrameIntervalHistogram::default(),
+            encoded_frames: 0,
+            first_encoded_at: None,
+            last_encoded_at: None,
+            preview_enabled: ctx.flags.preview && !ctx.flags.hdr, // Previews read the raw frame as BGRA
+            last_preview: Instant::now(),
+            webcam: ctx
+                .flags
+                .webcam_device_id
+                .as_ref()
+                .map(|_| (ctx.flags.webcam_position, ctx.flags.webcam_size)),
+            watermark: ctx.flags.watermark.as_ref().and_then(|config| {
+                let bitmap = WATERMARK_OVERLAY.lock().ok()?.clone()?;
+                Some((bitmap, config.position, config.opacity))
+            }),
+            timestamp: ctx
+                .flags
+                .show_timestamp
+                .then_some(ctx.flags.timestamp_style),
+            upload_buffer: Vec::new(),
+            constant_frame_rate: ctx.flags.constant_frame_rate,
+            cfr_base: None,
+            cfr_started_at: None,
+            cfr_next_slot: 0,
+            fps: ctx.flags.frame_rate,
+            duplicated_frames: 0,
+            skipped_frames: 0,
+            failed_frames: 0,
+            consecutive_send_errors: 0,
+            capture_interval: ctx
+                .flags
+                .capture_fps_cap
+                .filter(|&fps| fps > 0)
+                .map(|fps| HNS_PER_SEC / fps as i64),
+            next_capture_pts: None,
+            encode_width,
+            encode_height,
+            scale_buffer: Vec::new(),
+            crop: ctx.flags.taskbar_crop,
+            crop_buffer: Vec::new(),
+            first_pts: None,
+            frame_timestamps: Vec::new(),
+            last_stats_at: Instant::now(),
+            last_stats_size: 0,
+            track_input: ctx.flags.track_input,
+            max_mouse_positions: ctx.flags.max_mouse_positions.max(2),
+            clicks_seen: 0,
+            buttons_down: 0,
+            hdr_lut: ctx.flags.hdr.then(build_hdr_lut),
+            hdr_buffer: Vec::new(),
+            frame_size: None,
+        })
+    }
+
+    // Called every time a new frame is captured
+    fn on_frame_arrived(
+        &mut self,
+        frame: &mut Frame,
+        capture_control: InternalCaptureControl,
+    ) -> Result<(), Self::Error> {
+        // Only process frames if encoder is active
+        if !ENCODER_ACTIVE.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let frame_time = now.duration_since(self.last_frame_time);
+
+        // Monitor for potential frame drops: a gap well past one frame interval at the target rate.
+        // The first frame is measured from handler creation, so it doesn't count.
+        if self.frame_count > 0
+            && frame_time.as_secs_f64() > DROP_THRESHOLD_FACTOR / self.fps as f64
+        {
+            self.dropped_frames += 1;
+            //println!("Potential frame drop: {}ms between frames", frame_time.as_millis());
+        }
+        if self.frame_count > 0 {
+            self.frame_intervals.record(frame_time);
+        }
+
+        self.frame_count += 1;
+        self.last_frame_time = now;
+        self.check_frame_size(frame.width(), frame.height());
+
+        // Log performance stats every second
+        if self.start.elapsed().as_secs() > 0 && self.frame_count % 60 == 0 {
+            debug!(
+                "Recording stats: frames={}, drops={}, avg_interval={:.1}ms",
+                self.frame_count,
+                self.dropped_frames,
+                self.start.elapsed().as_millis() as f32 / self.frame_count as f32
+            );
+        }
+
+        let current_time = self.start.elapsed();
+
+        // Use thread_local for last frame time to avoid unsafe blocks
+        thread_local! {
+            static LAST_FRAME_TIME: std::cell::RefCell<Option<Instant>> = std::cell::RefCell::new(None);
+        }
+
+        // Calculate frame delta more safely
+        let frame_delta = LAST_FRAME_TIME.with(|last| {
+            let mut last = last.borrow_mut();
+            let now = Instant::now();
+            let delta = match *last {
+                Some(prev) => now.duration_since(prev),
+                None => std::time::Duration::from_millis(16),
+            };
+            *last = Some(now);
+            delta
+        });
+
+        // Log frame timing during fast changes
+        if frame_delta.as_millis() < 20 {
+            static mut FRAME_COUNT: u32 = 0;
+            static mut LAST_LOG_TIME: Option<Instant> = None;
+
+            unsafe {
+                FRAME_COUNT += 1;
+
+                if let Some(last_log) = LAST_LOG_TIME {
+                    if last_log.elapsed().as_secs() >= 1 {
+                        let fps = FRAME_COUNT as f32;
+                        debug!(
+                            "Capture performance: {:.1} FPS (avg frame interval: {:.1}ms)",
+                            fps,
+                            1000.0 / fps
+                        );
+                        FRAME_COUNT = 0;
+                        LAST_LOG_TIME = Some(Instant::now());
+                    }
+                } else {
+                    LAST_LOG_TIME = Some(Instant::now());
+                }
+            }
+        }
+
+        let capped = self.skip_for_fps_cap(frame.timespan().Duration);
+        if capped {
+            self.skipped_frames += 1;
+        }
+
+        // Overlays and CFR need the frame on the CPU; otherwise hand the GPU surface straight to the encoder
+        let send_result = if capped {
+            Ok(())
+        } else if self.needs_cpu_path(frame) {
+            self.send_composited_frame(frame)
+        } else {
+            let pts = frame.timespan().Duration;
+            let result = self.encoder.as_mut().unwrap().send_frame(frame);
+            if result.is_ok() {
+                self.record_frame_pts(pts);
+            }
+            result.map_err(|e| e.into())
+        };
+
+        // Log any encoding errors with more detail
+        if let Err(e) = send_result {
+            error!(
+                "Encoding error during frame at {}s: {}",
+                current_time.as_secs_f64(),
+                e
+            );
+            debug!("Frame details: size={}x{}", frame.width(), frame.height());
+            
+            // Check if this is a critical error or we can continue
+            if self.frame_count < 100 {
+                // If errors happen during the first few frames, they're likely critical
+                self.interrupt(&format!("Encoding failed: {}", e));
+                return Err(e);
+            } else {
+                // For later frames, log the error but try to continue
+                warn!("Attempting to continue encoding despite error...");
+                self.record_send_failure(&e.to_string());
+            }
+        } else if !capped {
+            self.consecutive_send_errors = 0;
+            let encoded_at = Instant::now();
+            self.encoded_frames += 1;
+            self.first_encoded_at.get_or_insert(encoded_at);
+            self.last_encoded_at = Some(encoded_at);
+        }
+
+        // Emit a throttled, downscaled preview frame if enabled.
+        // Skip while the previous preview is still being encoded so we never queue up work.
+        if self.preview_enabled
+            && self.last_preview.elapsed().as_millis() >= PREVIEW_INTERVAL_MS
+            && !PREVIEW_BUSY.load(Ordering::SeqCst)
+        {
+            self.last_preview = Instant::now();
+            let timestamp = current_time.as_secs_f64();
+
+            match frame.buffer() {
+                Ok(mut buffer) => {
+                    let width = buffer.width();
+                    let height = buffer.height();
+                    match buffer.as_nopadding_buffer() {
+                        Ok(pixels) => {
+                            let (thumb, thumb_width, thumb_height) =
+                                downscale_bgra(pixels, width, height, PREVIEW_MAX_WIDTH);
+                            PREVIEW_BUSY.store(true, Ordering::SeqCst);
+
+                            // Encode off the capture thread so the encoder isn't starved
+                            thread::spawn(move || {
+                                match ImageEncoder::new(ImageFormat::Jpeg, ColorFormat::Bgra8)
+                                    .encode(&thumb, thumb_width, thumb_height)
+                                {
+                                    Ok(jpeg) => emit_event(
+                                        "recording-preview-frame",
+                                        PreviewFrame {
+                                            width: thumb_width,
+                                            height: thumb_height,
+                                            timestamp,
+                                            data: BASE64.encode(jpeg),
+                                        },
+                                    ),
+                                    Err(e) => warn!("Failed to encode preview frame: {}", e),
+                                }
+                                PREVIEW_BUSY.store(false, Ordering::SeqCst);
+                            });
+                        }
+                        Err(e) => warn!("Failed to read preview frame buffer: {}", e),
+                    }
+                }
+                Err(e) => warn!("Failed to map preview frame: {}", e),
+            }
+        }
+
+        // Capture mouse position at most every 16ms (approximately 60fps). Samples are only taken
+        // when a frame arrives, so the actual spacing varies; each is stamped with its real time.
+        if self.track_input && self.last_mouse_capture.elapsed().as_millis() >= 16 {
+            unsafe {
+                let mut point = POINT::default();
+                if GetCursorPos(&mut point).as_bool() {
+                    // A press and release between two samples still shows up as a click
+                    let mut click_type = self.consume_click_events();
+                    // set_click_tracking turned clicks off; a button held since then isn't shown
+                    if !SHOULD_LISTEN_CLICKS.load(Ordering::SeqCst) {
+                        self.buttons_down = 0;
+                        click_type = None;
+                    }
+                    let is_clicked = self.buttons_down > 0 || click_type.is_some();
+
+                    // Get cursor type
+                    let cursor_type = get_cursor_type();
+
+                    // Log cursor type changes
+                    if let Ok(mut last_type) = LAST_CURSOR_TYPE.lock() {
+                        if *last_type != cursor_type {
+                            debug!("Cursor changed from '{}' to '{}'", last_type, cursor_type);
+                            *last_type = cursor_type.clone();
+                        }
+                    }
+
+                    // Adjust coordinates relative to the monitor's position
+                    let (monitor_x, monitor_y) = MONITOR_ORIGIN
+                        .lock()
+                        .map(|origin| *origin)
+                        .unwrap_or((0, 0));
+                    let relative_x = point.x - monitor_x;
+                    let relative_y = point.y - monitor_y;
+                    let (visible_width, visible_height) =
+                        self.crop.map_or((frame.width(), frame.height()), |crop| {
+                            (crop.width, crop.height)
+                        });
+                    let on_screen = (0..visible_width as i32).contains(&relative_x)
+                        && (0..visible_height as i32).contains(&relative_y);
+                    let (track_x, track_y) = if VIRTUAL_COORDINATES.load(Ordering::SeqCst) {
+                        (point.x, point.y)
+                    } else {
+                        (relative_x, relative_y)
+                    };
+                    let monitor = RECORDED_MONITORS.lock().ok().and_then(|monitors| {
+                        monitors
+                            .iter()
+                            .find(|(_, rect)| {
+                                (rect.left..rect.right).contains(&point.x)
+                                    && (rect.top..rect.bottom).contains(&point.y)
+                            })
+                            .map(|(index, _)| *index)
+                    });
+
+                    let mouse_pos = MousePosition {
+                        x: track_x,
+                        y: track_y,
+                        timestamp: clock::elapsed().unwrap_or(0.0),
+                        isClicked: is_clicked,
+                        cursor_type,
+                        click_type,
+                        velocity: None,
+                        acceleration: None,
+                        on_screen,
+                        monitor,
+                    };
+
+                    // Off-screen samples are kept (flagged) so the track stays evenly sampled
+                    if let Ok(mut positions) = MOUSE_POSITIONS.lock() {
+                        positions.push_back(mouse_pos);
+                        if positions.len() > self.max_mouse_positions {
+                            thin_mouse_positions(&mut positions);
+                        }
+                    }
+                }
+            }
+            self.last_mouse_capture = Instant::now();
+        }
+
+        if self.last_stats_at.elapsed().as_millis() >= STATS_INTERVAL_MS {
+            self.emit_stats();
+        }
+
+        // Check if we should stop recording
+        if SHOULD_STOP.load(Ordering::SeqCst) {
+            info!("Stopping capture and finalizing encoder...");
+            self.finalize_encoder();
+            
+            // Stop the capture immediately, don't wait for encoding
+            capture_control.stop();
+            info!("Capture stopped successfully");
+        }
+
+        Ok(())
+    }
+
+    // Called when capture session ends
+    fn on_closed(&mut self) -> Result<(), Self::Error> {
+        // The old session closing during a monitor switch is expected
+        if SWITCHING_MONITOR.load(Ordering::SeqCst) {
+            info!("Previous capture session closed during monitor switch");
+            return Ok(());
+        }
+        // Closed while the encoder is still running: the monitor went away mid-recording
+        if self.is_encoding() {
+            self.interrupt("The captured monitor was disconnected");
+            return Ok(());
+        }
+        info!("Capture session ended");
+        self.publish_metadata();
+        // Ensure states are reset
+        ENCODER_ACTIVE.store(false, Ordering::SeqCst);
+        ENCODING_FINISHED.store(true, Ordering::SeqCst);
+        RECORDING.store(false, Ordering::SeqCst);
+        cleanup_resources();
+        Ok(())
+    }
+}
+
+// Add the monitor enumeration callback
+extern "system" fn monitor_enum_proc(
+    monitor: HMONITOR,
+    _: HDC,
+    _: *mut RECT,
+    data: LPARAM,
+) -> BOOL {
+    unsafe {
+        let monitors = &mut *(data.0 as *mut Vec<HMONITOR>);
+        monitors.push(monitor);
+        BOOL::from(true)
+    }
+}
+
+// Replace the get_monitors command with the Win32 version
+#[tauri::command]
+async fn get_monitors(exclude_mirrors: Option<bool>) -> Result<Vec<MonitorInfo>, CommandError> {
+    info!("Starting monitor enumeration using Win32 API...");
+
+    unsafe {
+        let monitors = monitor_handles();
+
+        debug!("Found {} monitor handles", monitors.len());
+        if monitors.is_empty() {
+            warn!("No monitors found, there is no interactive desktop");
+            return Err(CommandError::NoDisplaysAvailable);
+        }
+
+        let mut monitor_infos = Vec::new();
+
+        for (index, &monitor) in monitors.iter().enumerate() {
+            let mut monitor_info: MONITORINFOEXW = zeroed();
+            monitor_info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+
+            if GetMonitorInfoW(monitor, &mut monitor_info.monitorInfo as *mut _).as_bool() {
+                let rect = monitor_info.monitorInfo.rcMonitor;
+                debug!(
+                    "Monitor {}: Position ({}, {}), Size {}x{}",
+                    index,
+                    rect.left,
+                    rect.top,
+                    rect.right - rect.left,
+                    rect.bottom - rect.top
+                );
+
+                // Mirrored displays share the rect of the display they duplicate
+                let is_mirror = monitor_infos.iter().any(|other: &MonitorInfo| {
+                    other.x == rect.left
+                        && other.y == rect.top
+                        && other.width == (rect.right - rect.left) as u32
+                        && other.height == (rect.bottom - rect.top) as u32
+                });
+                let orientation = if rect.bottom - rect.top > rect.right - rect.left {
+                    Orientation::Portrait
+                } else {
+                    Orientation::Landscape
+                };
+
+                monitor_infos.push(MonitorInfo {
+                    id: index.to_string(),
+                    name: format!("Display {}", index + 1),
+                    x: rect.left,
+                    y: rect.top,
+                    width: (rect.right - rect.left) as u32,
+                    height: (rect.bottom - rect.top) as u32,
+                    is_primary: monitor_info.monitorInfo.dwFlags & 1 == 1,
+                    device_id: display_device_id(&monitor_info.szDevice),
+                    is_mirror,
+                    orientation,
+                    scale_factor: monitor_scale_factor(monitor),
+                });
+            } else {
+                warn!("Failed to get info for monitor {}", index);
+            }
+        }
+
+        // Ids stay the enumeration index, so filtering doesn't change what start_recording expects
+        if exclude_mirrors.unwrap_or(false) {
+            monitor_infos.retain(|monitor| !monitor.is_mirror);
+        }
+
+        debug!("Monitor details: {:#?}", monitor_infos);
+        Ok(monitor_infos)
+    }
+}
+
+// Add this function to clean up resources
+fn cleanup_resources() {
+    info!("Cleaning up resources...");
+
+    // Make sure encoder is no longer active
+    ENCODER_ACTIVE.store(false, Ordering::SeqCst);
+    
+    // Ensure encoding is marked as finished to prevent deadlocks
+    ENCODING_FINISHED.store(true, Ordering::SeqCst);
+
+    // Clean up any running servers
+    if let Ok(mut ports) = SERVER_PORTS.lock() {
+        if !ports.is_empty() {
+            debug!("Cleaning up {} server ports", ports.len());
+            ports.clear();
+        }
+    }
+
+    // First, drop the memory map
+    {
+        let mut mmap = VIDEO_MMAP.lock();
+        if mmap.is_some() {
+            debug!("Releasing memory map");
+            *mmap = None;
+        }
+    }
+
+    // Reset all state flags
+    RECORDING.store(false, Ordering::SeqCst);
+    SHOULD_STOP.store(false, Ordering::SeqCst);
+    tray::set_recording(false);
+    
+    // Clear mouse positions
+    if let Ok(mut positions) = MOUSE_POSITIONS.lock() {
+        positions.clear();
+    }
+    if let Ok(mut events) = CLICK_EVENTS.lock() {
+        events.clear();
+    }
+
+    // Signal click listener to stop
+    SHOULD_LISTEN_CLICKS.store(false, Ordering::SeqCst);
+    CURSOR_POLL_GENERATION.fetch_add(1, Ordering::SeqCst);
+    MEMORY_MONITOR_GENERATION.fetch_add(1, Ordering::SeqCst);
+    window_track::stop();
+    stop_pipe::stop();
+    redaction::clear();
+
+    // Stop the webcam reader if one was running
+    media::stop_webcam();
+
+    // Finish any other monitors' files; they stay in the temp directory like the main one
+    extra_monitors::stop();
+
+    // Bring our window back if it was hidden for the recording
+    restore_app_window();
+
+    // Forget the capture session; it stops itself once SHOULD_STOP is seen
+    if let Ok(mut control) = CAPTURE_CONTROL.lock() {
+        control.take();
+    }
+    if let Ok(mut active) = ACTIVE_RECORDING.lock() {
+        active.take();
+    }
+    
+    // Note: we don't clear VIDEO_PATH here because the server might still need it; the file is
+    // only removed by release_recording
+    
+    info!("Resource cleanup completed");
+}
+
+// Holds STARTING for start_recording and clears it when dropped, so it's released on every path
+struct StartGuard(&'static AtomicBool);
+
+impl StartGuard {
+    // None if another start already holds the flag
+    fn acquire(flag: &'static AtomicBool) -> Option<Self> {
+        flag.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+            .then_some(StartGuard(flag))
+    }
+}
+
+impl Drop for StartGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// Monitor handles in EnumDisplayMonitors order, which is also the order windows-capture indexes by
+fn monitor_handles() -> Vec<HMONITOR> {
+    let mut monitors: Vec<HMONITOR> = Vec::new();
+    let monitors_ptr = &mut monitors as *mut Vec<HMONITOR>;
+    unsafe {
+        EnumDisplayMonitors(
+            HDC::default(),
+            None,
+            Some(monitor_enum_proc),
+            LPARAM(monitors_ptr as isize),
+        );
+    }
+    monitors
+}
+
+fn wide_to_string(wide: &[u16]) -> String {
+    let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+    String::from_utf16_lossy(&wide[..len])
+}
+
+// Display scaling of a monitor, 1.0 at 96 DPI. The app is per-monitor DPI aware, so this is the
+// monitor's own setting rather than the primary monitor's.
+fn monitor_scale_factor(monitor: HMONITOR) -> f64 {
+    let (mut dpi_x, mut dpi_y) = (0u32, 0u32);
+    match unsafe { GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) } {
+        Ok(()) if dpi_x > 0 => dpi_x as f64 / 96.0,
+        Ok(()) => 1.0,
+        Err(e) => {
+            warn!(
+                "Failed to read the monitor DPI, assuming 100% scaling: {}",
+                e
+            );
+            1.0
+        }
+    }
+}
+
+// Device interface path of the monitor attached to a GDI display (e.g. \\?\DISPLAY#GSM5B7F#...).
+// Unlike the enumeration index it identifies the physical monitor and port.
+fn display_device_id(device_name: &[u16]) -> Option<String> {
+    unsafe {
+        let mut device: DISPLAY_DEVICEW = zeroed();
+        device.cb = std::mem::size_of::<DISPLAY_DEVICEW>() as u32;
+        if !EnumDisplayDevicesW(
+            PCWSTR(device_name.as_ptr()),
+            0,
+            &mut device,
+            EDD_GET_DEVICE_INTERFACE_NAME,
+        )
+        .as_bool()
+        {
+            return None;
+        }
+        let id = wide_to_string(&device.DeviceID);
+        (!id.is_empty()).then_some(id)
+    }
+}
+
+// Index of the monitor whose device id matches, ignoring case since Windows isn't consistent about it
+fn find_monitor_by_device_id(device_id: &str) -> Option<usize> {
+    monitor_handles().iter().position(|&hmonitor| unsafe {
+        let mut monitor_info: MONITORINFOEXW = zeroed();
+        monitor_info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+        GetMonitorInfoW(hmonitor, &mut monitor_info.monitorInfo as *mut _).as_bool()
+            && display_device_id(&monitor_info.szDevice)
+                .is_some_and(|id| id.eq_ignore_ascii_case(device_id))
+    })
+}
+
+// True inside a Remote Desktop session, where the desktop is rendered for the client and graphics
+// capture is unreliable
+fn is_remote_session() -> bool {
+    unsafe { GetSystemMetrics(SM_REMOTESESSION) != 0 }
+}
+
+// Look up the capture monitor for a frontend monitor id, along with its EnumDisplayMonitors index.
+// The id can be a stable device id from get_monitors, or an index as before.
+fn resolve_monitor(monitor_id: Option<&str>) -> Result<(Monitor, usize), CommandError> {
+    // Without a display Monitor::primary fails cryptically, or the capture thread hangs
+    if monitor_handles().is_empty() {
+        warn!("No monitors found, there is no interactive desktop");
+        return Err(CommandError::NoDisplaysAvailable);
+    }
+    if let Some(id) = monitor_id {
+        debug!("Trying to get monitor with ID: {}", id);
+        let index = match find_monitor_by_device_id(id) {
+            Some(index) => {
+                info!("Matched device id to monitor index {}", index);
+                index
+            }
+            None => id.parse::<usize>().map_err(|e| {
+                info!("No monitor with that device id and not an index: {:?}", e);
+                CommandError::InvalidMonitor(id.to_string())
+            })?,
+        };
+
+        let monitor = Monitor::from_index(index + 1).map_err(|e| {
+            warn!("Failed to get monitor from index: {:?}", e);
+            CommandError::MonitorUnavailable(e.to_string())
+        })?;
+        Ok((monitor, index))
+    } else {
+        info!("No monitor ID provided, using primary");
+        let monitor = Monitor::primary().map_err(|e| {
+            warn!("Failed to get primary monitor: {:?}", e);
+            CommandError::MonitorUnavailable(e.to_string())
+        })?;
+        Ok((monitor, 0))
+    }
+}
+
+// Top-left corner and refresh rate of the monitor at an EnumDisplayMonitors index
+fn monitor_geometry(index: usize) -> Option<((i32, i32), Option<u32>)> {
+    unsafe {
+        let hmonitor = *monitor_handles().get(index)?;
+        let mut monitor_info: MONITORINFOEXW = zeroed();
+        monitor_info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+
+        if !GetMonitorInfoW(hmonitor, &mut monitor_info.monitorInfo as *mut _).as_bool() {
+            return None;
+        }
+        let rect = monitor_info.monitorInfo.rcMonitor;
+        Some((
+            (rect.left, rect.top),
+            display_refresh_rate(&monitor_info.szDevice),
+        ))
+    }
+}
+
+// Desktop rectangle of the monitor at an EnumDisplayMonitors index
+fn monitor_rect(index: usize) -> Option<RECT> {
+    unsafe {
+        let hmonitor = *monitor_handles().get(index)?;
+        let mut monitor_info: MONITORINFOEXW = zeroed();
+        monitor_info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+        GetMonitorInfoW(hmonitor, &mut monitor_info.monitorInfo as *mut _)
+            .as_bool()
+            .then_some(monitor_info.monitorInfo.rcMonitor)
+    }
+}
+
+// Desktop position of a monitor's top-left recorded pixel, past the taskbar if it's cropped out
+fn cropped_origin(origin: (i32, i32), crop: Option<taskbar::Crop>) -> (i32, i32) {
+    crop.map_or(origin, |crop| {
+        (origin.0 + crop.x as i32, origin.1 + crop.y as i32)
+    })
+}
+
+// Both coordinates change together so the mouse tracker never sees a half-updated origin
+fn set_monitor_origin(origin: (i32, i32)) {
+    if let Ok(mut current) = MONITOR_ORIGIN.lock() {
+        *current = origin;
+    }
+    if let Ok(mut metadata) = RECORDING_METADATA.lock() {
+        metadata.monitor_origin = origin;
+    }
+    debug!("Set monitor position to: ({}, {})", origin.0, origin.1);
+}
+
+// Current refresh rate of a display, by its GDI device name (e.g. \\.\DISPLAY1)
+unsafe fn display_refresh_rate(device_name: &[u16]) -> Option<u32> {
+    let mut device_mode = DEVMODEW {
+        dmSize: std::mem::size_of::<DEVMODEW>() as u16,
+        ..Default::default()
+    };
+    if !EnumDisplaySettingsW(
+        PCWSTR(device_name.as_ptr()),
+        ENUM_CURRENT_SETTINGS,
+        &mut device_mode,
+    )
+    .as_bool()
+    {
+        return None;
+    }
+    // 0 and 1 both mean "hardware default" rather than a real rate
+    match device_mode.dmDisplayFrequency {
+        0 | 1 => None,
+        hz => Some(hz),
+    }
+}
+
+// Stamped when the hook sees the event; rdev's own event time is only SystemTime::now()
+fn record_click_event(button: Button, pressed: bool) {
+    let timestamp = clock::elapsed().unwrap_or(0.0);
+
+    // rdev doesn't report where the button went down, so read the cursor now
+    let mut point = POINT::default();
+    unsafe {
+        let _ = GetCursorPos(&mut point);
+    }
+    let (monitor_x, monitor_y) = if VIRTUAL_COORDINATES.load(Ordering::SeqCst) {
+        (0, 0)
+    } else {
+        MONITOR_ORIGIN
+            .lock()
+            .map(|origin| *origin)
+            .unwrap_or((0, 0))
+    };
+
+    let button = match button {
+        Button::Left => "left",
+        Button::Right => "right",
+        Button::Middle => "middle",
+        Button::Unknown(_) => "other",
+    };
+    if pressed {
+        debug!("Mouse {} pressed at {:.3}s", button, timestamp);
+    }
+
+    if let Ok(mut events) = CLICK_EVENTS.lock() {
+        events.push_back(ClickEvent {
+            x: point.x - monitor_x,
+            y: point.y - monitor_y,
+            button: button.to_string(),
+            timestamp,
+            pressed,
+        });
+    }
+}
+
+// Record cursor type changes between mouse samples until the next start/stop bumps the generation
+fn start_cursor_type_poller(interval_ms: u32) {
+    let generation = CURSOR_POLL_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    info!("Polling the cursor type every {}ms", interval_ms);
+
+    thread::spawn(move || {
+        let mut last_type = String::new();
+        while CURSOR_POLL_GENERATION.load(Ordering::SeqCst) == generation {
+            let cursor_type = get_cursor_type();
+            if cursor_type != last_type {
+                // Nothing is recorded until the capture has started
+                if let Some(timestamp) = clock::elapsed() {
+                    if let Ok(mut changes) = CURSOR_TYPE_CHANGES.lock() {
+                        changes.push((timestamp, cursor_type.clone()));
+                    }
+                }
+                last_type = cursor_type;
+            }
+            thread::sleep(std::time::Duration::from_millis(interval_ms as u64));
+        }
+    });
+}
+
+fn memory_stats(cap_mb: Option<u32>) -> MemoryStats {
+    let working_set_bytes = benchmark::working_set_bytes() as u64;
+    let mouse_positions = MOUSE_POSITIONS
+        .lock()
+        .map(|positions| positions.len())
+        .unwrap_or(0);
+    MemoryStats {
+        working_set_bytes,
+        mouse_positions,
+        mouse_positions_bytes: (mouse_positions * std::mem::size_of::<MousePosition>()) as u64,
+        click_events: CLICK_EVENTS.lock().map(|events| events.len()).unwrap_or(0),
+        frame_queue_bytes: frame_queue::queued_bytes(),
+        over_cap: cap_mb.is_some_and(|cap| working_set_bytes > cap as u64 * 1024 * 1024),
+    }
+}
+
+// Report memory use while recording and thin the mouse track whenever the process is over the cap
+fn start_memory_monitor(cap_mb: Option<u32>) {
+    let generation = MEMORY_MONITOR_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+
+    thread::spawn(move || loop {
+        thread::sleep(std::time::Duration::from_millis(MEMORY_INTERVAL_MS));
+        if MEMORY_MONITOR_GENERATION.load(Ordering::SeqCst) != generation {
+            break;
+        }
+        let stats = memory_stats(cap_mb);
+        if stats.over_cap {
+            warn!(
+                "Using {:.0} MB, over the {} MB cap; thinning {} mouse samples",
+                stats.working_set_bytes as f64 / (1024.0 * 1024.0),
+                cap_mb.unwrap_or(0),
+                stats.mouse_positions
+            );
+            if let Ok(mut positions) = MOUSE_POSITIONS.lock() {
+                thin_mouse_positions(&mut positions);
+                positions.shrink_to_fit();
+            }
+        }
+        emit_event("recording-memory", stats);
+    });
+}
+
+// Install the global mouse hook on first use. rdev's listen() never returns, so the thread stays
+// around and SHOULD_LISTEN_CLICKS decides whether events are recorded.
+fn start_input_listener() {
+    if INPUT_LISTENER_STARTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    thread::spawn(move || {
+        if let Err(error) = listen(move |event| {
+            // Check if we should continue listening
+            if !SHOULD_LISTEN_CLICKS.load(Ordering::SeqCst) {
+                return;
+            }
+
+            let (button, pressed) = match event.event_type {
+                EventType::ButtonPress(button) => (button, true),
+                EventType::ButtonRelease(button) => (button, false),
+                _ => return,
+            };
+            record_click_event(button, pressed);
+        }) {
+            warn!("Error in mouse listener: {:?}", error);
+            // The hook failed to install; allow the next recording to try again
+            INPUT_LISTENER_STARTED.store(false, Ordering::SeqCst);
+        }
+    });
+}
+
+// Modify start_recording
+// monitor_ids records several monitors at once, each to its own file: the first is the main
+// recording (mouse track, overlays, metadata) and the rest are returned separately by stop_recording.
+#[tauri::command]
+async fn start_recording(
+    monitor_id: Option<String>,
+    quality: Option<String>,
+    fps: Option<u32>,
+    options: Option<RecordingOptions>,
+    monitor_ids: Option<Vec<String>>,
+) -> Result<(), CommandError> {
+    // Only one start may run at a time, otherwise two capture threads fight over the statics
+    let Some(_start_guard) = StartGuard::acquire(&STARTING) else {
+        warn!("start_recording already in progress, rejecting concurrent call");
+        return Err(CommandError::AlreadyStarting);
+    };
+
+    let mut options = options.unwrap_or_default();
+    info!(
+        "Starting recording with monitor_id: {:?}, quality: {:?}, fps: {:?}, options: {:?}",
+        monitor_id, quality, fps, options
+    );
+
+    // First, ensure any previous recording is fully cleaned up
+    if RECORDING.load(Ordering::SeqCst) {
+        info!("Detected active recording, cleaning up first...");
+        SHOULD_STOP.store(true, Ordering::SeqCst);
+
+        // Wait a bit for cleanup
+        thread::sleep(std::time::Duration::from_millis(500));
+    }
+
+    // Force cleanup regardless of previous state
+    cleanup_resources();
+
+    // Clear previous mouse positions
+    if let Ok(mut positions) = MOUSE_POSITIONS.lock() {
+        positions.clear();
+        info!("Cleared previous mouse positions");
+    }
+
+    // Reset metadata from the previous recording
+    if let Ok(mut metadata) = RECORDING_METADATA.lock() {
+        *metadata = RecordingMetadata::default();
+    }
+    if let Ok(mut timestamps) = FRAME_TIMESTAMPS.lock() {
+        timestamps.clear();
+    }
+    
+    // A custom preset from set_quality_presets supplies the bitrate, frame rate and output size.
+    // An explicit bitrate, output size or fps argument still wins.
+    let custom_preset = quality.as_deref().and_then(quality_presets::find);
+    if let Some(preset) = &custom_preset {
+        info!("Using custom quality preset: {:?}", preset);
+        options.bitrate = options.bitrate.or(Some(preset.bitrate));
+        options.frame_rate = preset.fps;
+        options.output_size = options.output_size.or(preset.output_size());
+    }
+
+    // Map the quality setting to an encoder preset unless the options pick one explicitly
+    if options.encoder_preset.is_none() {
+        options.encoder_preset = Some(
+            quality
+                .as_deref()
+                .and_then(EncoderPreset::from_quality)
+                .unwrap_or_else(|| {
+                    if custom_preset.is_none() {
+                        info!("No valid quality specified, defaulting to high quality");
+                    }
+                    EncoderPreset::Quality
+                }),
+        );
+    }
+    info!("Using encoder preset: {:?}", options.encoder_preset);
+
+    // Frame rate is independent of quality, so e.g. a low bitrate can still record at 60fps
+    if let Some(fps) = fps {
+        options.frame_rate = fps;
+    }
+    if options.frame_rate == 0 || options.frame_rate > MAX_FPS {
+        return Err(CommandError::UnsupportedEncoder(format!(
+            "Frame rate must be between 1 and {} fps, got {}",
+            MAX_FPS, options.frame_rate
+        )));
+    }
+
+    if is_remote_session() {
+        if !options.allow_remote_session {
+            warn!("Refusing to record in a Remote Desktop session");
+            return Err(CommandError::RemoteSession);
+        }
+        warn!("Recording in a Remote Desktop session, frames may come out black");
+    }
+
+    let (monitor_id, extra_monitor_ids) = match monitor_ids {
+        Some(_) if monitor_id.is_some() => {
+            return Err(CommandError::InvalidMonitor(
+                "Pass either monitor_id or monitor_ids, not both".to_string(),
+            ));
+        }
+        Some(mut ids) if !ids.is_empty() => {
+            let extra = ids.split_off(1);
+            (ids.pop(), extra)
+        }
+        _ => (monitor_id, Vec::new()),
+    };
+    let (monitor, monitor_index) = resolve_monitor(monitor_id.as_deref())?;
+    let mut other_monitors = Vec::new();
+    for id in &extra_monitor_ids {
+        let (extra, index) = resolve_monitor(Some(id))?;
+        if index == monitor_index || other_monitors.iter().any(|&(_, other)| other == index) {
+            return Err(CommandError::InvalidMonitor(format!(
+                "{} is listed twice",
+                id
+            )));
+        }
+        other_monitors.push((extra, index));
+    }
+
+    // Fail early with a clear error rather than deep inside VideoEncoder::new
+    let (width, height) = (
+        monitor
+            .width()
+            .map_err(|e| CommandError::MonitorUnavailable(e.to_string()))?,
+        monitor
+            .height()
+            .map_err(|e| CommandError::MonitorUnavailable(e.to_string()))?,
+    );
+    options.taskbar_crop = options
+        .exclude_taskbar
+        .then(|| monitor_rect(monitor_index).and_then(taskbar::crop_for_monitor))
+        .flatten();
+    let (width, height) = options
+        .taskbar_crop
+        .map_or((width, height), |crop| (crop.width, crop.height));
+    let (width, height) = options.output_size.unwrap_or((width, height));
+    // The first call probes every encoder MFT, which can take a while; run it on a blocking
+    // thread so check_support only reads the cache. setup warms it, so this is usually instant.
+    let _ = tauri::async_runtime::spawn_blocking(encoders::supported_encoders).await;
+    encoders::check_support(options.codec, width, height)
+        .and_then(|()| match options.bitrate {
+            Some(bitrate) => encoders::check_bitrate(options.codec, bitrate),
+            None => Ok(()),
+        })
+        .map_err(|e| {
+            warn!("Rejecting recording: {}", e);
+            CommandError::UnsupportedEncoder(e)
+        })?;
+    for (extra, index) in &other_monitors {
+        let (extra_width, extra_height) = (
+            extra
+                .width()
+                .map_err(|e| CommandError::MonitorUnavailable(e.to_string()))?,
+            extra
+                .height()
+                .map_err(|e| CommandError::MonitorUnavailable(e.to_string()))?,
+        );
+        encoders::check_support(options.codec, extra_width, extra_height).map_err(|e| {
+            warn!("Rejecting recording of monitor {}: {}", index, e);
+            CommandError::UnsupportedEncoder(e)
+        })?;
+    }
+
+    if options
+        .stop_pipe_token
+        .as_ref()
+        .is_some_and(|token| token.is_empty() || token.contains(char::is_whitespace))
+    {
+        return Err(CommandError::StopPipeFailed(
+            "The token can't be empty or contain whitespace".to_string(),
+        ));
+    }
+
+    if let Some(template) = &options.filename_template {
+        let values = filename::TemplateValues {
+            monitor: monitor_index,
+            width,
+            height,
+            counter: FILENAME_COUNTER.load(Ordering::SeqCst) + 1,
+        };
+        let name = filename::from_template(template, &values).map_err(|e| {
+            warn!("Rejecting recording: {}", e);
+            CommandError::InvalidFilename(e)
+        })?;
+        let dir = filename::recordings_dir();
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            CommandError::InvalidFilename(format!("Failed to create {}: {}", dir.display(), e))
+        })?;
+        FILENAME_COUNTER.fetch_add(1, Ordering::SeqCst);
+        options.output_path = Some(filename::unique_path(&dir, &name));
+    }
+    // The other monitors' files are named after the main one, so fix its path up front
+    if !other_monitors.is_empty() && options.output_path.is_none() {
+        options.output_path = Some(default_recording_path());
+    }
+
+    VIRTUAL_COORDINATES.store(
+        options.coordinate_space == CoordinateSpace::Virtual,
+        Ordering::SeqCst,
+    );
+    options.cursor_smoothing = options.cursor_smoothing.min(smoothing::MAX_SMOOTHING);
+    if let Ok(mut metadata) = RECORDING_METADATA.lock() {
+        metadata.coordinate_space = options.coordinate_space;
+        metadata.cursor_smoothing = options.cursor_smoothing;
+        metadata.warmup_secs = options.warmup_secs.unwrap_or(0.0).max(0.0);
+    }
+
+    // Get monitor info to get the correct position
+    if let Some((origin, refresh_rate)) = monitor_geometry(monitor_index) {
+        set_monitor_origin(cropped_origin(origin, options.taskbar_crop));
+        info!("Monitor refresh rate: {:?} Hz", refresh_rate);
+        if let Ok(mut metadata) = RECORDING_METADATA.lock() {
+            metadata.refresh_rate = refresh_rate;
+        }
+    }
+
+    // Start the webcam before capture so the first frames already have it
+    if let Some(device_id) = options.webcam_device_id.clone() {
+        info!("Starting webcam capture for device: {}", device_id);
+        media::start_webcam(device_id).map_err(|e| {
+            warn!("Failed to start webcam: {}", e);
+            CommandError::WebcamFailed(e)
+        })?;
+    }
+
+    // Render the watermark once up front; the capture handler only blends it
+    let watermark_overlay = match &options.watermark {
+        Some(config) => Some(Arc::new(watermark::render_watermark(config).map_err(
+            |e| {
+                warn!("Failed to render watermark: {}", e);
+                media::stop_webcam();
+                CommandError::WatermarkFailed(e)
+            },
+        )?)),
+        None => None,
+    };
+    if let Ok(mut overlay) = WATERMARK_OVERLAY.lock() {
+        *overlay = watermark_overlay;
+    }
+    redaction::set(&options.redaction_regions, options.redaction_mode);
+
+    apply_self_exclusion(options.self_exclusion);
+
+    if let Ok(mut active) = ACTIVE_RECORDING.lock() {
+        *active = Some((monitor_id.clone(), options.clone()));
+    }
+
+    // Reset video path
+    set_video_path(None);
+    if let Ok(mut path) = PROTOCOL_VIDEO_PATH.lock() {
+        *path = None;
+    }
+
+    // Without input tracking the global hook is never installed and no clicks are recorded
+    if let Ok(mut events) = CLICK_EVENTS.lock() {
+        events.clear();
+    }
+    if let Ok(mut epoch) = CLICK_EPOCH.lock() {
+        *epoch = None;
+    }
+    clock::reset();
+    if let Ok(mut marks) = SEGMENT_MARKS.lock() {
+        marks.clear();
+    }
+    if let Ok(mut changes) = CURSOR_TYPE_CHANGES.lock() {
+        changes.clear();
+    }
+    if options.track_input {
+        // Signal that we should start listening for clicks
+        SHOULD_LISTEN_CLICKS.store(true, Ordering::SeqCst);
+        start_input_listener();
+        if let Some(interval_ms) = options.cursor_type_interval_ms {
+            start_cursor_type_poller(interval_ms.max(1));
+        }
+    } else {
+        info!("Input tracking disabled, recording without mouse track");
+    }
+    if options.track_windows {
+        window_track::start();
+    }
+
+    if let Ok(mut recorded) = RECORDED_MONITORS.lock() {
+        *recorded = std::iter::once(monitor_index)
+            .chain(other_monitors.iter().map(|&(_, index)| index))
+            .filter_map(|index| Some((index, monitor_rect(index)?)))
+            .collect();
+    }
+
+    // Other monitors start first, so a failure there doesn't leave the main capture to tear down
+    if let Some(main_path) = options.output_path.clone() {
+        let preset = options.encoder_preset.unwrap_or_default();
+        let stem = main_path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        for (extra, index) in other_monitors {
+            let settings = extra_monitors::ExtraSettings {
+                path: main_path
+                    .with_file_name(format!("{}_monitor{}.mp4", stem, index))
+                    .to_string_lossy()
+                    .to_string(),
+                sub_type: match options.codec {
+                    VideoCodec::H264 => VideoSettingsSubType::H264,
+                    _ => VideoSettingsSubType::HEVC,
+                },
+                bitrate: options.bitrate.unwrap_or(preset.bitrate()),
+                frame_rate: options.frame_rate,
+                show_capture_border: options.show_capture_border,
+            };
+            if let Err(e) = extra_monitors::start(extra, index, settings) {
+                error!("Screen capture of monitor {} failed: {}", index, e);
+                cleanup_resources();
+                return Err(CommandError::EncoderFailed(format!(
+                    "Monitor {}: {}",
+                    index, e
+                )));
+            }
+        }
+    }
+
+    let memory_cap_mb = options.memory_cap_mb;
+
+    if let Some(token) = options.stop_pipe_token.clone() {
+        if let Err(e) = stop_pipe::start(token, || stop_externally("a stop pipe client")) {
+            error!("Failed to open the stop pipe: {}", e);
+            cleanup_resources();
+            return Err(CommandError::StopPipeFailed(e));
+        }
+    }
+
+    // Capture runs on its own thread; keep the control so switch_monitor can stop this session
+    match start_capture_session(monitor, options) {
+        Ok(control) => {
+            if let Ok(mut current) = CAPTURE_CONTROL.lock() {
+                *current = Some(control);
+            }
+        }
+        Err(e) => {
+            error!("Screen capture failed: {}", e);
+            cleanup_resources();
+            return Err(CommandError::EncoderFailed(e));
+        }
+    }
+
+    start_memory_monitor(memory_cap_mb);
+
+    // Update recording state
+    RECORDING.store(true, Ordering::SeqCst);
+    tray::set_recording(true);
+    info!("Recording started successfully");
+    Ok(())
+}
+
+// Start a capture session on a monitor; the handler picks up CAPTURE_HANDOFF if one is waiting
+fn start_capture_session(
+    monitor: Monitor,
+    options: RecordingOptions,
+) -> Result<CaptureControl<CaptureHandler, CaptureError>, String> {
+    // Some users want the border as a recording indicator, default to a clean capture
+    let draw_border = if options.show_capture_border {
+        DrawBorderSettings::WithBorder
+    } else {
+        DrawBorderSettings::WithoutBorder
+    };
+    let color_format = if options.hdr {
+        ColorFormat::Rgba16F
+    } else {
+        ColorFormat::Bgra8
+    };
+    let settings = Settings::new(
+        monitor,
+        CursorCaptureSettings::WithoutCursor,
+        draw_border,
+        color_format,
+        options.clone(),
+    );
+    match CaptureHandler::start_free_threaded(settings) {
+        Ok(control) => Ok(control),
+        Err(e) if options.hdr => {
+            warn!(
+                "HDR capture (Rgba16F) is not available on this device ({}), falling back to SDR",
+                e
+            );
+            start_capture_session(
+                monitor,
+                RecordingOptions {
+                    hdr: false,
+                    ..options
+                },
+            )
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Check that the monitor can be captured and the encoder works by recording a few frames to a
+// temporary file, which is removed again. Diagnostics are returned rather than an error.
+#[tauri::command]
+async fn test_capture(
+    monitor_id: Option<String>,
+) -> Result<capture_test::CaptureTestResult, CommandError> {
+    if RECORDING.load(Ordering::SeqCst) || STARTING.load(Ordering::SeqCst) {
+        return Err(CommandError::AlreadyRecording);
+    }
+    let (monitor, _) = resolve_monitor(monitor_id.as_deref())?;
+
+    tauri::async_runtime::spawn_blocking(move || capture_test::run(monitor))
+        .await
+        .map_err(|e| CommandError::EncoderFailed(e.to_string()))
+}
+
+// Check that screen capture is allowed before the user tries to record, so a policy block or a
+// missing Windows feature is reported up front instead of as a recording that never starts
+#[tauri::command]
+async fn check_capture_permissions(
+    monitor_id: Option<String>,
+) -> Result<capture_test::PermissionCheck, CommandError> {
+    let (monitor, _) = resolve_monitor(monitor_id.as_deref())?;
+    tauri::async_runtime::spawn_blocking(move || capture_test::check_permissions(monitor))
+        .await
+        .map_err(|e| CommandError::MonitorUnavailable(e.to_string()))
+}
+
+// Record the monitor into a throwaway file for `seconds` and report capture/encode throughput,
+// dropped frames, per-frame processing time and peak memory. Not available while recording.
+#[tauri::command]
+async fn run_capture_benchmark(
+    seconds: u32,
+    monitor_id: Option<String>,
+) -> Result<benchmark::BenchmarkResult, CommandError> {
+    if RECORDING.load(Ordering::SeqCst) || STARTING.load(Ordering::SeqCst) {
+        return Err(CommandError::AlreadyRecording);
+    }
+    if seconds == 0 || seconds > benchmark::MAX_SECONDS {
+        return Err(CommandError::EncoderFailed(format!(
+            "Benchmark duration must be between 1 and {} seconds",
+            benchmark::MAX_SECONDS
+        )));
+    }
+    let (monitor, _) = resolve_monitor(monitor_id.as_deref())?;
+
+    tauri::async_runtime::spawn_blocking(move || benchmark::run(monitor, seconds))
+        .await
+        .map_err(|e| CommandError::EncoderFailed(e.to_string()))?
+        .map_err(CommandError::EncoderFailed)
+}
+
+// Change the bitrate of the recording in progress. The value is validated against the codec, but
+// the change itself is always rejected for now; see encoders::check_bitrate_change.
+#[tauri::command]
+async fn set_recording_bitrate(bps: u32) -> Result<(), CommandError> {
+    let (_, options) = ACTIVE_RECORDING
+        .lock()
+        .ok()
+        .and_then(|active| active.clone())
+        .filter(|_| RECORDING.load(Ordering::SeqCst))
+        .ok_or(CommandError::NotRecording)?;
+    encoders::check_bitrate(options.codec, bps)
+        .and_then(|()| encoders::check_bitrate_change())
+        .map_err(|e| {
+            warn!("Rejecting bitrate change to {} bps: {}", bps, e);
+            CommandError::UnsupportedEncoder(e)
+        })
+}
+
+// Move an active recording to another monitor without restarting the encoder.
+// The running session is stopped, its handler state (encoder included) is handed to a new session
+// on the target monitor, and frames of a different size are letterboxed to the original resolution.
+#[tauri::command]
+async fn switch_monitor(monitor_id: String) -> Result<(), CommandError> {
+    if !RECORDING.load(Ordering::SeqCst) || SHOULD_STOP.load(Ordering::SeqCst) {
+        return Err(CommandError::NotRecording);
+    }
+    // Each monitor already has its own file, and the track's monitor tags assume a fixed set
+    if extra_monitors::is_active() {
+        return Err(CommandError::InvalidMonitor(
+            "Can't switch monitors while recording several at once".to_string(),
+        ));
+    }
+
+    let (monitor, monitor_index) = resolve_monitor(Some(&monitor_id))?;
+    let (previous_monitor_id, mut options) = ACTIVE_RECORDING
+        .lock()
+        .ok()
+        .and_then(|active| active.clone())
+        .ok_or(CommandError::NotRecording)?;
+    let control = CAPTURE_CONTROL
+        .lock()
+        .ok()
+        .and_then(|mut control| control.take())
+        .ok_or(CommandError::NotRecording)?;
+
+    let previous_crop = options.taskbar_crop;
+    if options.exclude_taskbar {
+        options.taskbar_crop = monitor_rect(monitor_index).and_then(taskbar::crop_for_monitor);
+    }
+
+    info!("Switching recording to monitor {}", monitor_id);
+    SWITCHING_MONITOR.store(true, Ordering::SeqCst);
+
+    // Stop the old session and take its state once its thread has exited
+    let callback = control.callback();
+    if let Err(e) = control.stop() {
+        warn!("Previous capture session stopped with error: {:?}", e);
+    }
+    let session = callback.lock().take_session();
+    if !session.is_encoding() {
+        // The recording was finalized while we were switching
+        SWITCHING_MONITOR.store(false, Ordering::SeqCst);
+        return Err(CommandError::NotRecording);
+    }
+    if let Ok(mut handoff) = CAPTURE_HANDOFF.lock() {
+        *handoff = Some(session);
+    }
+
+    let result = match start_capture_session(monitor, options.clone()) {
+        Ok(control) => {
+            if let Some((origin, _)) = monitor_geometry(monitor_index) {
+                set_monitor_origin(cropped_origin(origin, options.taskbar_crop));
+            }
+            if let Ok(mut active) = ACTIVE_RECORDING.lock() {
+                *active = Some((Some(monitor_id), options));
+            }
+            Ok(control)
+        }
+        Err(e) => {
+            // Go back to the monitor we were on so the recording keeps going
+            warn!(
+                "Failed to capture new monitor ({}), resuming previous one",
+                e
+            );
+            resolve_monitor(previous_monitor_id.as_deref())
+                .map_err(|e| e.to_string())
+                .and_then(|(previous, _)| {
+                    options.taskbar_crop = previous_crop;
+                    start_capture_session(previous, options)
+                })
+                .map_err(|_| CommandError::MonitorUnavailable(e))
+        }
+    };
+    SWITCHING_MONITOR.store(false, Ordering::SeqCst);
+
+    match result {
+        Ok(control) => {
+            if let Ok(mut current) = CAPTURE_CONTROL.lock() {
+                *current = Some(control);
+            }
+            Ok(())
+        }
+        Err(e) => {
+            error!("Could not resume capture after failed switch, stopping recording");
+            if let Ok(mut handoff) = CAPTURE_HANDOFF.lock() {
+                handoff.take();
+            }
+            cleanup_resources();
+            Err(e)
+        }
+    }
+}
+
+// Add these constants near the top
+const CHUNK_SIZE: usize = 1024 * 1024; // 1MB chunks
+
+// Add this new command
+#[tauri::command]
+async fn get_video_chunk(chunk_index: usize) -> Result<String, CommandError> {
+    if let Some(mmap) = VIDEO_MMAP.lock().as_ref() {
+        let start = chunk_index * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min(mmap.len());
+
+        if start >= mmap.len() {
+            return Err(CommandError::ChunkOutOfBounds);
+        }
+
+        let chunk = &mmap[start..end];
+        Ok(BASE64.encode(chunk))
+    } else {
+        Err(CommandError::NoVideoFile)
+    }
+}
+
+// Read `length` bytes of the recording from `offset`, e.g. to fetch a moof box or the fragment
+// holding a keyframe. A range running past the end of the file is cut short. Returned as raw
+// bytes (an ArrayBuffer on the frontend) rather than base64 like get_video_chunk.
+#[tauri::command]
+async fn get_video_bytes(
+    offset: usize,
+    length: usize,
+) -> Result<tauri::ipc::Response, CommandError> {
+    let mmap = VIDEO_MMAP
+        .lock()
+        .as_ref()
+        .cloned()
+        .ok_or(CommandError::NoVideoFile)?;
+    if length == 0 || offset >= mmap.len() {
+        return Err(CommandError::RangeOutOfBounds(format!(
+            "{} bytes at {} in a {} byte file",
+            length,
+            offset,
+            mmap.len()
+        )));
+    }
+    let end = offset.saturating_add(length).min(mmap.len());
+    Ok(tauri::ipc::Response::new(mmap[offset..end].to_vec()))
+}
+
+// Initialize the memory map when recording stops
+fn init_video_mmap() -> Result<(), Box<dyn std::error::Error>> {
+    info!("Initializing video memory map...");
+    unsafe {
+        if let Some(path) = current_video_path() {
+            debug!("Trying to open video file at: {}", path);
+            
+            // Make multiple attempts to open the file
+            const MAX_ATTEMPTS: usize = 3;
+            let mut last_error = None;
+            
+            for attempt in 1..=MAX_ATTEMPTS {
+                match File::open(&path) {
+                    Ok(file) => {
+                        match file.metadata() {
+                            Ok(metadata) => {
+                                let file_size = metadata.len();
+                                debug!(
+                                    "File opened (attempt {}/{}), size: {} bytes",
+                                    attempt, MAX_ATTEMPTS, file_size
+                                );
+
+                                match Mmap::map(&file) {
+                                    Ok(mmap) => {
+                                        debug!(
+                                            "Memory map created successfully, size: {} bytes",
+                                            mmap.len()
+                                        );
+                                        *VIDEO_MMAP.lock() = Some(Arc::new(mmap));
+                                        return Ok(());
+                                    },
+                                    Err(e) => {
+                                        warn!(
+                                            "Failed to create memory map (attempt {}/{}): {}",
+                                            attempt, MAX_ATTEMPTS, e
+                                        );
+                                        last_error = Some(e);
+                                        // Try again after a short delay
+                                        thread::sleep(std::time::Duration::from_millis(200));
+                                    }
+                                }
+                            },
+                            Err(e) => {
+                                warn!(
+                                    "Failed to get file metadata (attempt {}/{}): {}",
+                                    attempt, MAX_ATTEMPTS, e
+                                );
+                                last_error = Some(e.into());
+                                thread::sleep(std::time::Duration::from_millis(200));
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        warn!(
+                            "Failed to open file (attempt {}/{}): {}",
+                            attempt, MAX_ATTEMPTS, e
+                        );
+                        last_error = Some(e.into());
+                        thread::sleep(std::time::Duration::from_millis(200));
+                    }
+                }
+            }
+            
+            // If we've tried multiple times and still failed, return the last error
+            if let Some(e) = last_error {
+                return Err(Box::new(e));
+            } else {
+                return Err("Failed to open video file after multiple attempts".into());
+            }
+        } else {
+            info!("No video path available for memory mapping");
+            return Err("No video path available".into());
+        }
+    }
+}
+
+// Origins the packaged app's webview can have: WebView2 serves it from http://tauri.localhost, or
+// https:// with useHttpsScheme; tauri://localhost is the macOS/Linux scheme
+const APP_ORIGINS: [&str; 3] = [
+    "http://tauri.localhost",
+    "https://tauri.localhost",
+    "tauri://localhost",
+];
+
+// Whether a webview origin may read the video. Dev builds load the frontend from the Vite server,
+// whose port can change, so any localhost port is accepted there.
+fn is_allowed_origin(origin: &str) -> bool {
+    if APP_ORIGINS.contains(&origin) {
+        return true;
+    }
+    cfg!(debug_assertions)
+        && ["http://localhost:", "http://127.0.0.1:"]
+            .iter()
+            .any(|prefix| {
+                origin
+                    .strip_prefix(prefix)
+                    .is_some_and(|port| port.parse::<u16>().is_ok())
+            })
+}
+
+// Sent by the webview for CORS requests; a plain <video src> doesn't send one
+fn request_origin(request: &tiny_http::Request) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Origin"))
+        .map(|h| h.value.as_str().to_string())
+}
+
+// Echo an allowed origin back; without one the browser blocks cross-origin reads
+fn add_cors_headers<R: std::io::Read>(response: &mut Response<R>, origin: Option<&str>) {
+    if let Some(origin) = origin {
+        response.add_header(
+            tiny_http::Header::from_bytes(&b"Access-Control-Allow-Origin"[..], origin.as_bytes())
+                .unwrap(),
+        );
+    }
+    // The allowed origin depends on the request, so caches must not reuse it for another one
+    response.add_header(tiny_http::Header::from_bytes(&b"Vary"[..], &b"Origin"[..]).unwrap());
+    response.add_header(
+        tiny_http::Header::from_bytes(
+            &b"Access-Control-Allow-Methods"[..],
+            &b"GET, HEAD, OPTIONS"[..],
+        )
+        .unwrap(),
+    );
+    response.add_header(
+        tiny_http::Header::from_bytes(
+            &b"Access-Control-Allow-Headers"[..],
+            &b"Range, X-Recording-Token"[..],
+        )
+        .unwrap(),
+    );
+}
+
+// Random per-recording token so other users on the machine can't fetch the video
+fn generate_server_token() -> Result<String, CommandError> {
+    let mut bytes = [0u8; 16];
+    unsafe {
+        BCryptGenRandom(
+            BCRYPT_ALG_HANDLE::default(),
+            &mut bytes,
+            BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+        )
+    }
+    .map_err(|e| CommandError::ServerFailed(format!("Failed to generate token: {}", e)))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Compare secrets without bailing on the first mismatching byte
+fn tokens_match(candidate: &str, token: &str) -> bool {
+    candidate.len() == token.len()
+        && candidate
+            .bytes()
+            .zip(token.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// Token can come from the ?token= query param of `url` (video src, fetch) or the
+// X-Recording-Token header, passed as `token_header`
+fn request_has_token(url: &str, token_header: Option<&str>, token: &str) -> bool {
+    let from_query = url.split_once('?').and_then(|(_, query)| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+    });
+
+    [from_query, token_header]
+        .into_iter()
+        .flatten()
+        .any(|candidate| tokens_match(candidate, token))
+}
+
+// What a Range header asks of a file, see parse_byte_range
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ByteRange {
+    Whole,          // No Range header, or one we ignore (malformed, several ranges)
+    Part(u64, u64), // Inclusive start and end, both inside the file
+    Unsatisfiable,  // Starts past the end of the file or ends before it starts; answered with 416
+}
+
+// Parse a `bytes=start-end`, `bytes=start-` or `bytes=-suffix` Range header value against a file of
+// `file_size` bytes. An open end is capped at `max_len` bytes from the start, and an end past the
+// file is clamped to its last byte.
+fn parse_byte_range(value: Option<&str>, file_size: u64, max_len: u64) -> ByteRange {
+    let Some((start, end)) = value
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+        .filter(|range| !range.contains(','))
+        .and_then(|range| range.split_once('-'))
+    else {
+        return ByteRange::Whole;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        // The last `suffix` bytes
+        return match end.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if file_size == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Part(file_size.saturating_sub(suffix), file_size - 1),
+            Err(_) => ByteRange::Whole,
+        };
+    }
+    let Ok(start) = start.parse::<u64>() else {
+        return ByteRange::Whole;
+    };
+    if start >= file_size {
+        return ByteRange::Unsatisfiable;
+    }
+    let end = if end.is_empty() {
+        start.saturating_add(max_len.max(1) - 1)
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) => end,
+            Err(_) => return ByteRange::Whole,
+        }
+    };
+    if end < start {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Part(start, end.min(file_size - 1))
+}
+
+// Serve the finalized recording over the custom protocol, with range support for <video> seeking
+fn serve_protocol_video(request: &tauri::http::Request<Vec<u8>>) -> tauri::http::Response<Vec<u8>> {
+    use tauri::http::{header, Response as HttpResponse, StatusCode as HttpStatus};
+
+    let empty = |status: HttpStatus| {
+        HttpResponse::builder()
+            .status(status)
+            .body(Vec::new())
+            .unwrap()
+    };
+
+    let Some(path) = PROTOCOL_VIDEO_PATH
+        .lock()
+        .ok()
+        .and_then(|path| path.clone())
+    else {
+        info!("Protocol request with no registered recording");
+        return empty(HttpStatus::NOT_FOUND);
+    };
+
+    let mut file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Failed to open video for protocol request: {}", e);
+            return empty(HttpStatus::NOT_FOUND);
+        }
+    };
+    let file_size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if file_size == 0 {
+        return empty(HttpStatus::NOT_FOUND);
+    }
+
+    // A missing end is capped so seeking doesn't load the whole file
+    let range = parse_byte_range(
+        request
+            .headers()
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok()),
+        file_size,
+        PROTOCOL_MAX_RANGE,
+    );
+    let (start, end) = match range {
+        ByteRange::Whole => (0, file_size - 1),
+        ByteRange::Part(start, end) => (start, end),
+        ByteRange::Unsatisfiable => {
+            return HttpResponse::builder()
+                .status(HttpStatus::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", file_size))
+                .body(Vec::new())
+                .unwrap();
+        }
+    };
+
+    let mut data = Vec::with_capacity((end - start + 1) as usize);
+    if let Err(e) = file
+        .seek(std::io::SeekFrom::Start(start))
+        .and_then(|_| (&mut file).take(end - start + 1).read_to_end(&mut data))
+    {
+        warn!("Failed to read video range {}-{}: {}", start, end, e);
+        return empty(HttpStatus::INTERNAL_SERVER_ERROR);
+    }
+
+    let mut response = HttpResponse::builder()
+        // Only the webview can reach the scheme, but its origin differs from the app's
+        .header(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .header(header::CONTENT_TYPE, "video/mp4")
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::CACHE_CONTROL, "no-store")
+        .header(header::CONTENT_ENCODING, "identity")
+        .header(header::CONTENT_LENGTH, data.len());
+    response = if range != ByteRange::Whole {
+        response.status(HttpStatus::PARTIAL_CONTENT).header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, file_size),
+        )
+    } else {
+        response.status(HttpStatus::OK)
+    };
+    response.body(data).unwrap()
+}
+
+// MP4 is already compressed, so we only ever send identity. False if the client explicitly refuses it
+// (identity;q=0, or *;q=0 without an identity entry). `accept_encoding` is the header's value.
+fn accepts_identity_encoding(accept_encoding: Option<&str>) -> bool {
+    let Some(accept_encoding) = accept_encoding else {
+        return true;
+    };
+
+    let mut identity = None;
+    let mut wildcard = None;
+    for entry in accept_encoding.split(',') {
+        let mut parts = entry.split(';');
+        let coding = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match coding.as_str() {
+            "identity" => identity = Some(quality),
+            "*" => wildcard = Some(quality),
+            _ => {}
+        }
+    }
+
+    identity.or(wildcard).is_none_or(|quality| quality > 0.0)
+}
+
+// Answer one video server request, streaming the requested range from the worker's file handle
+fn handle_video_request(request: tiny_http::Request, file: &mut File, file_size: u64, token: &str) {
+    debug!("Received request: {} {}", request.method(), request.url());
+    let origin = request_origin(&request);
+
+    // Say which origin was refused, a bare CORS failure in the webview gives no clue
+    if let Some(rejected) = origin
+        .as_deref()
+        .filter(|origin| !is_allowed_origin(origin))
+    {
+        warn!("Rejecting request from disallowed origin {}", rejected);
+        let _ = request.respond(Response::empty(403));
+        return;
+    }
+
+    // Handle OPTIONS preflight request
+    if request.method() == &tiny_http::Method::Options {
+        debug!("Handling OPTIONS request");
+        let mut response = Response::empty(204);
+        add_cors_headers(&mut response, origin.as_deref());
+        let _ = request.respond(response);
+        return;
+    }
 
-        if let Some(&hmonitor) = monitors.get(monitor_index) {
-            let mut monitor_info: MONITORINFOEXW = zeroed();
-            monitor_info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+    let token_header = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("X-Recording-Token"))
+        .map(|h| h.value.as_str());
+    if !request_has_token(request.url(), token_header, token) {
+        warn!("Rejecting request with missing or wrong token");
+        let mut response = Response::empty(403);
+        add_cors_headers(&mut response, origin.as_deref());
+        let _ = request.respond(response);
+        return;
+    }
 
-            if GetMonitorInfoW(hmonitor, &mut monitor_info.monitorInfo as *mut _).as_bool() {
-                let rect = monitor_info.monitorInfo.rcMonitor;
-                MONITOR_X = rect.left;
-                MONITOR_Y = rect.top;
-                println!("Set monitor position to: ({}, {})", MONITOR_X, MONITOR_Y);
+    let accept_encoding = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Accept-Encoding"))
+        .map(|h| h.value.as_str());
+    if !accepts_identity_encoding(accept_encoding) {
+        warn!("Client refuses identity encoding, rejecting");
+        let mut response = Response::empty(406);
+        add_cors_headers(&mut response, origin.as_deref());
+        let _ = request.respond(response);
+        return;
+    }
+
+    // Handle range request. Responses are streamed, so an open end runs to the end of the file.
+    let range_header = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Range"))
+        .map(|h| h.value.as_str());
+    debug!("Range request: {:?}", range_header);
+    let range = parse_byte_range(range_header, file_size, u64::MAX);
+    let (start, end) = match range {
+        ByteRange::Whole => (0, file_size - 1),
+        ByteRange::Part(start, end) => (start, end),
+        ByteRange::Unsatisfiable => {
+            warn!(
+                "Unsatisfiable range {:?} for {} bytes",
+                range_header, file_size
+            );
+            let mut response = Response::empty(416);
+            add_cors_headers(&mut response, origin.as_deref());
+            response.add_header(
+                tiny_http::Header::from_bytes(
+                    &b"Content-Range"[..],
+                    format!("bytes */{}", file_size).as_bytes(),
+                )
+                .unwrap(),
+            );
+            let _ = request.respond(response);
+            return;
+        }
+    };
+
+    debug!("Serving range: bytes {}-{}/{}", start, end, file_size);
+
+    if let Err(e) = file.seek(std::io::SeekFrom::Start(start)) {
+        warn!("Error seeking in file: {}", e);
+        let _ = request.respond(Response::empty(500));
+        return;
+    }
+
+    let mut response = Response::new(
+        if range == ByteRange::Whole {
+            StatusCode(200)
+        } else {
+            StatusCode(206)
+        },
+        vec![],
+        file.take(end - start + 1),
+        Some((end - start + 1) as usize),
+        None,
+    );
+
+    add_cors_headers(&mut response, origin.as_deref());
+
+    // Add content type header
+    response.add_header(
+        tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"video/mp4"[..]).unwrap(),
+    );
+
+    // Be explicit so proxies neither re-compress nor cache the stream
+    response.add_header(
+        tiny_http::Header::from_bytes(&b"Content-Encoding"[..], &b"identity"[..]).unwrap(),
+    );
+    response.add_header(
+        tiny_http::Header::from_bytes(&b"Cache-Control"[..], &b"no-store"[..]).unwrap(),
+    );
+
+    // Add headers for range requests
+    if range != ByteRange::Whole {
+        response.add_header(
+            tiny_http::Header::from_bytes(
+                &b"Content-Range"[..],
+                format!("bytes {}-{}/{}", start, end, file_size).as_bytes(),
+            )
+            .unwrap(),
+        );
+    }
+
+    match request.respond(response) {
+        Ok(_) => debug!("Response sent successfully"),
+        Err(e) => warn!("Error sending response: {}", e),
+    }
+}
+
+// The OS can briefly hold the file after the encoder finalizes it, so retry with backoff
+fn open_video_with_retry(path: &str) -> std::io::Result<File> {
+    const MAX_ATTEMPTS: u32 = 3;
+    let mut attempt = 1;
+    loop {
+        match File::open(path) {
+            Ok(file) => return Ok(file),
+            Err(e) if attempt < MAX_ATTEMPTS => {
+                let delay = 200 * 2u64.pow(attempt - 1);
+                warn!(
+                    "Failed to open video for serving (attempt {}/{}): {}, retrying in {}ms",
+                    attempt, MAX_ATTEMPTS, e, delay
+                );
+                thread::sleep(std::time::Duration::from_millis(delay));
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Modify start_video_server to track ports
+// Returns the port and the token every request has to carry
+fn start_video_server(video_path: String) -> Result<(u16, String), CommandError> {
+    info!("Starting video server for: {}", video_path);
+
+    // Verify file exists and is readable first
+    let file_size = match std::fs::metadata(&video_path) {
+        Ok(metadata) => {
+            let size = metadata.len();
+            info!(
+                "Video file verified: {} bytes ({:.2} MB)",
+                size,
+                size as f64 / (1024.0 * 1024.0)
+            );
+            
+            if size == 0 {
+                warn!("Video file exists but is empty");
+                return Err(CommandError::NoVideoFile);
+            }
+            size
+        }
+        Err(e) => {
+            warn!("Cannot access video file: {}", e);
+            return Err(CommandError::NoVideoFile);
+        }
+    };
+
+    // Clean up old ports first
+    if let Ok(mut ports) = SERVER_PORTS.lock() {
+        ports.clear();
+    }
+
+    let token = generate_server_token()?;
+
+    // Let the OS pick a free ephemeral port, then read back what it gave us
+    let server = Server::http("127.0.0.1:0").map_err(|e| {
+        error!("Failed to bind video server: {}", e);
+        CommandError::NoPortsAvailable
+    })?;
+    let port = server
+        .server_addr()
+        .to_ip()
+        .map(|addr| addr.port())
+        .ok_or_else(|| {
+            CommandError::ServerFailed("Server is not bound to an IP address".to_string())
+        })?;
+    info!("Server started on port {}", port);
+    if let Ok(mut ports) = SERVER_PORTS.lock() {
+        ports.push(port);
+    }
+
+    PORT.store(port, Ordering::SeqCst);
+
+    // Each worker opens the file itself: cloned handles share one cursor, so a seek by one worker
+    // would move another's read mid-response
+    let file = open_video_with_retry(&video_path).map_err(|e| {
+        warn!("Failed to open video file for serving: {}", e);
+        CommandError::ServerFailed(e.to_string())
+    })?;
+    // Get the current file size again in case it changed
+    let file_size = file.metadata().map(|m| m.len()).unwrap_or(file_size);
+    info!("Video file opened successfully: {} bytes", file_size);
+
+    let server = Arc::new(server);
+    let server_token: Arc<str> = Arc::from(token.as_str());
+
+    // Open them all before starting any worker, so a failure leaves no orphaned threads behind
+    let mut files = vec![file];
+    for worker in 1..VIDEO_SERVER_WORKERS {
+        files.push(open_video_with_retry(&video_path).map_err(|e| {
+            warn!("Failed to open video file for worker {}: {}", worker, e);
+            CommandError::ServerFailed(e.to_string())
+        })?);
+    }
+
+    for (worker, mut worker_file) in files.into_iter().enumerate() {
+        let server = Arc::clone(&server);
+        let server_token = Arc::clone(&server_token);
+
+        thread::spawn(move || {
+            debug!("Video server worker {} started", worker);
+            for request in server.incoming_requests() {
+                handle_video_request(request, &mut worker_file, file_size, &server_token);
+            }
+            debug!("Video server worker {} ended", worker);
+        });
+    }
+
+    if let Ok(mut servers) = VIDEO_SERVERS.lock() {
+        servers.push((video_path, server));
+    }
+
+    Ok((port, token))
+}
+
+// Shut down the servers for a file; each unblock releases one worker, which drops its file handle
+fn stop_video_servers(video_path: &str) {
+    let Ok(mut servers) = VIDEO_SERVERS.lock() else {
+        return;
+    };
+    servers.retain(|(path, server)| {
+        if path != video_path {
+            return true;
+        }
+        for _ in 0..VIDEO_SERVER_WORKERS {
+            server.unblock();
+        }
+        info!("Stopped video server for {}", path);
+        false
+    });
+}
+
+// Run-length encode a sequence into (count, value) pairs.
+// With merge_runs off every value gets its own run, which keeps the format uniform.
+fn run_length_encode<T: PartialEq>(
+    values: impl IntoIterator<Item = T>,
+    merge_runs: bool,
+) -> Vec<(u32, T)> {
+    let mut runs: Vec<(u32, T)> = Vec::new();
+    for value in values {
+        match runs.last_mut() {
+            Some((count, last)) if merge_runs && *last == value => *count += 1,
+            _ => runs.push((1, value)),
+        }
+    }
+    runs
+}
+
+fn run_length_decode<T: Clone>(runs: &[(u32, T)]) -> impl Iterator<Item = T> + '_ {
+    runs.iter()
+        .flat_map(|(count, value)| std::iter::repeat_n(value.clone(), *count as usize))
+}
+
+impl EncodedMouseTrack {
+    fn encode(positions: &[MousePosition], rle: bool) -> Self {
+        let Some(first) = positions.first() else {
+            return Self::default();
+        };
+
+        let offsets_us: Vec<i64> = positions
+            .iter()
+            .map(|p| ((p.timestamp - first.timestamp) * 1_000_000.0).round() as i64)
+            .collect();
+
+        Self {
+            count: positions.len(),
+            base_x: first.x,
+            base_y: first.y,
+            base_timestamp: first.timestamp,
+            dx: run_length_encode(
+                positions
+                    .iter()
+                    .scan(first.x, |prev, p| Some(p.x - std::mem::replace(prev, p.x))),
+                rle,
+            ),
+            dy: run_length_encode(
+                positions
+                    .iter()
+                    .scan(first.y, |prev, p| Some(p.y - std::mem::replace(prev, p.y))),
+                rle,
+            ),
+            dt_us: run_length_encode(
+                offsets_us.iter().scan(0i64, |prev, &offset| {
+                    Some(offset - std::mem::replace(prev, offset))
+                }),
+                rle,
+            ),
+            clicked: run_length_encode(positions.iter().map(|p| p.isClicked), true),
+            cursor_type: run_length_encode(positions.iter().map(|p| p.cursor_type.clone()), true),
+            click_type: run_length_encode(positions.iter().map(|p| p.click_type.clone()), true),
+            on_screen: run_length_encode(positions.iter().map(|p| p.on_screen), true),
+            monitor: run_length_encode(positions.iter().map(|p| p.monitor), true),
+        }
+    }
+
+    // Inverse of encode; timestamps round-trip to the microsecond and motion is recomputed
+    pub fn decode(&self) -> Vec<MousePosition> {
+        let mut x = self.base_x;
+        let mut y = self.base_y;
+        let mut offset_us = 0i64;
+
+        let mut positions: Vec<MousePosition> = run_length_decode(&self.dx)
+            .zip(run_length_decode(&self.dy))
+            .zip(run_length_decode(&self.dt_us))
+            .zip(run_length_decode(&self.clicked))
+            .zip(run_length_decode(&self.cursor_type))
+            .zip(run_length_decode(&self.click_type))
+            .zip(run_length_decode(&self.on_screen).chain(std::iter::repeat(true)))
+            .take(self.count)
+            .map(
+                |((((((dx, dy), dt), is_clicked), cursor_type), click_type), on_screen)| {
+                    x += dx;
+                    y += dy;
+                    offset_us += dt;
+                    MousePosition {
+                        x,
+                        y,
+                        timestamp: self.base_timestamp + offset_us as f64 / 1_000_000.0,
+                        isClicked: is_clicked,
+                        cursor_type,
+                        click_type,
+                        velocity: None,
+                        acceleration: None,
+                        on_screen,
+                        monitor: None,
+                    }
+                },
+            )
+            .collect();
+        // Empty in tracks from before it existed, leaving every sample untagged
+        for (position, monitor) in positions.iter_mut().zip(run_length_decode(&self.monitor)) {
+            position.monitor = monitor;
+        }
+        compute_motion(&mut positions);
+        positions
+    }
+}
+
+// Drop every other sample from the older half of the track. Presses, button state changes, cursor
+// changes and monitor crossings are always kept, so clicks and transitions survive; repeated
+// thinning leaves the oldest part of a long recording the sparsest.
+fn thin_mouse_positions(positions: &mut VecDeque<MousePosition>) {
+    let samples = positions.make_contiguous();
+    let older = samples.len() / 2;
+    let keep: Vec<bool> = (0..samples.len())
+        .map(|i| {
+            let p = &samples[i];
+            let transition = i > 0 && {
+                let previous = &samples[i - 1];
+                previous.isClicked != p.isClicked
+                    || previous.cursor_type != p.cursor_type
+                    || previous.on_screen != p.on_screen
+                    || previous.monitor != p.monitor
+            };
+            i >= older || i % 2 == 0 || p.click_type.is_some() || transition
+        })
+        .collect();
+
+    let before = positions.len();
+    let mut keep = keep.into_iter();
+    positions.retain(|_| keep.next().unwrap_or(true));
+    debug!(
+        "Thinned mouse track from {} to {} samples",
+        before,
+        positions.len()
+    );
+}
+
+// Move the track onto the video's timeline: mouse sampling starts before the first frame is encoded,
+// so samples are shifted back by that delay. Earlier samples are dropped, except the last one which
+// becomes the cursor position at t=0.
+fn align_to_first_frame(positions: &mut Vec<MousePosition>, offset: f64) {
+    if offset <= 0.0 {
+        return;
+    }
+    let first_inside = positions.partition_point(|p| p.timestamp < offset);
+    let keep_from = first_inside.saturating_sub(1);
+    if keep_from > 0 {
+        info!(
+            "Dropping {} mouse samples from before the first frame",
+            keep_from
+        );
+    }
+    positions.drain(..keep_from);
+    for p in positions.iter_mut() {
+        p.timestamp = (p.timestamp - offset).max(0.0);
+    }
+}
+
+// Fill in velocity and acceleration from adjacent samples. Samples with no time step since the
+// previous one (or the first sample) get None rather than an infinite speed.
+fn compute_motion(positions: &mut [MousePosition]) {
+    let mut previous: Option<(i32, i32, f64)> = None;
+    let mut previous_velocity: Option<f64> = None;
+    for p in positions.iter_mut() {
+        let (velocity, acceleration) = match previous {
+            Some((x, y, timestamp)) if p.timestamp > timestamp => {
+                let dt = p.timestamp - timestamp;
+                let distance = (((p.x - x) as f64).powi(2) + ((p.y - y) as f64).powi(2)).sqrt();
+                let velocity = distance / dt;
+                (
+                    Some(velocity),
+                    previous_velocity.map(|v| (velocity - v) / dt),
+                )
+            }
+            _ => (None, None),
+        };
+        p.velocity = velocity;
+        p.acceleration = acceleration;
+        previous = Some((p.x, p.y, p.timestamp));
+        previous_velocity = velocity;
+    }
+}
+
+// Samples that go back in time. Equal timestamps are allowed (compute_motion skips them).
+fn find_timestamp_regressions(positions: &[MousePosition]) -> Vec<TimestampRegression> {
+    positions
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[1].timestamp < pair[0].timestamp)
+        .map(|(i, pair)| TimestampRegression {
+            index: i + 1,
+            timestamp: pair[1].timestamp,
+            previous: pair[0].timestamp,
+        })
+        .collect()
+}
+
+// Add this function near the other utility functions
+fn process_cursor_changes(positions: &mut Vec<MousePosition>, min_duration_ms: u32) {
+    if min_duration_ms == 0 {
+        return;
+    }
+
+    let mut i = 0;
+    while i < positions.len().saturating_sub(1) {
+        let current_type = &positions[i].cursor_type;
+        let mut j = i + 1;
+
+        while j < positions.len() && positions[j].cursor_type == *current_type {
+            j += 1;
+        }
+
+        // If this cursor type lasted less than MIN_DURATION_MS and we're not at the start
+        let duration = (positions[j - 1].timestamp - positions[i].timestamp) * 1000.0;
+        if duration < min_duration_ms as f64 && i > 0 {
+            // Replace the short duration with the previous type
+            let prev_type = positions[i - 1].cursor_type.clone();
+            for pos in positions.iter_mut().take(j).skip(i) {
+                pos.cursor_type = prev_type.clone();
+            }
+        }
+        i = j;
+    }
+}
+
+// Add a sample wherever the cursor poller saw the type change between two position samples, at
+// the change's time and the previous sample's position. `offset` moves the changes onto the video's
+// timeline like the track.
+fn merge_cursor_type_changes(positions: &mut Vec<MousePosition>, offset: f64) {
+    let changes = CURSOR_TYPE_CHANGES
+        .lock()
+        .map(|mut changes| std::mem::take(&mut *changes))
+        .unwrap_or_default();
+    let mut added = 0;
+    for (timestamp, cursor_type) in changes {
+        let timestamp = timestamp - offset;
+        let index = positions.partition_point(|p| p.timestamp <= timestamp);
+        let Some(previous) = index.checked_sub(1).map(|i| &positions[i]) else {
+            continue; // Before the first sample
+        };
+        if previous.cursor_type == cursor_type {
+            continue;
+        }
+        let sample = MousePosition {
+            timestamp,
+            cursor_type,
+            click_type: None,
+            ..previous.clone()
+        };
+        positions.insert(index, sample);
+        added += 1;
+    }
+    if added > 0 {
+        debug!("Added {} samples for cursor type changes", added);
+    }
+}
+
+// A monitor recorded to its own file alongside the main one (start_recording's monitor_ids)
+#[derive(Debug, Clone, Serialize)]
+pub struct MonitorRecording {
+    monitor: usize, // EnumDisplayMonitors index, as in MousePosition.monitor
+    video_url: String,
+    path: String, // Released with release_recording like the main file
+    // Top-left corner relative to the main monitor (in desktop space with Virtual coordinates);
+    // subtract from the mouse track's x/y to place the cursor in this video
+    offset_x: i32,
+    offset_y: i32,
+    frame_count: u32,
+    duration: f64,
+}
+
+// What stop_recording and save_buffer return: video URL, number of mouse positions, metadata, click
+// events, cursor trail segments and the other monitors' recordings. The track itself stays in
+// MOUSE_POSITIONS; page it with get_mouse_positions_range.
+type RecordingOutput = (
+    String,
+    usize,
+    RecordingMetadata,
+    Vec<ClickEvent>,
+    Vec<analysis::TrailSegment>,
+    Vec<MonitorRecording>,
+    Vec<window_track::WindowEvent>, // Empty unless RecordingOptions.track_windows
+);
+
+// Hand a finished MP4 to the frontend, over the local HTTP server or the custom protocol
+fn serve_video(
+    video_path: String,
+    delivery: Option<VideoDelivery>,
+) -> Result<String, CommandError> {
+    match delivery.unwrap_or_default() {
+        VideoDelivery::Http => start_video_server(video_path).map(|(port, token)| {
+            info!("Server started successfully on port {}", port);
+            format!("http://localhost:{}/?token={}", port, token)
+        }),
+        VideoDelivery::Protocol => {
+            info!("Registering video with the {} protocol", VIDEO_PROTOCOL);
+            if let Ok(mut path) = PROTOCOL_VIDEO_PATH.lock() {
+                *path = Some(video_path);
+            }
+            // WebView2 exposes custom schemes as http://<scheme>.localhost; the query busts its cache
+            let nonce = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or(0);
+            Ok(format!(
+                "http://{}.localhost/recording.mp4?v={}",
+                VIDEO_PROTOCOL, nonce
+            ))
+        }
+    }
+}
+
+// Click events moved onto the video's timeline like the mouse track; clicks before the first frame
+// are dropped
+fn aligned_click_events(offset: f64) -> Vec<ClickEvent> {
+    CLICK_EVENTS
+        .lock()
+        .map(|events| {
+            events
+                .iter()
+                .filter(|event| event.timestamp >= offset)
+                .map(|event| ClickEvent {
+                    timestamp: event.timestamp - offset,
+                    ..event.clone()
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Rebase the marked segments onto the video's timeline, closing a still-open one at the end of the
+// video. Segments that end up empty (marked before the first frame) are dropped.
+fn take_segments(offset: f64, duration: f64) -> Vec<Segment> {
+    let marks = SEGMENT_MARKS
+        .lock()
+        .map(|mut marks| std::mem::take(&mut *marks))
+        .unwrap_or_default();
+    marks
+        .into_iter()
+        .map(|(start, end)| Segment {
+            start: (start - offset).clamp(0.0, duration),
+            end: end.map_or(duration, |end| (end - offset).clamp(0.0, duration)),
+        })
+        .filter(|segment| segment.end > segment.start)
+        .collect()
+}
+
+// Stop or resume recording clicks without stopping the recording, e.g. while typing a password. The
+// mouse track goes on, but shows no clicks or held buttons while tracking is off. Sends
+// `click-tracking-changed` with the new state.
+#[tauri::command]
+async fn set_click_tracking(enabled: bool) -> Result<(), CommandError> {
+    let (_, options) = ACTIVE_RECORDING
+        .lock()
+        .ok()
+        .and_then(|active| active.clone())
+        .filter(|_| RECORDING.load(Ordering::SeqCst) && !SHOULD_STOP.load(Ordering::SeqCst))
+        .ok_or(CommandError::NotRecording)?;
+    if !options.track_input {
+        return Err(CommandError::InputNotTracked);
+    }
+    if SHOULD_LISTEN_CLICKS.swap(enabled, Ordering::SeqCst) != enabled {
+        info!(
+            "Click tracking {} at {:.3}s",
+            if enabled { "resumed" } else { "paused" },
+            clock::elapsed().unwrap_or(0.0)
+        );
+        emit_event("click-tracking-changed", enabled);
+    }
+    Ok(())
+}
+
+// Replace the redacted regions of the recording in progress, e.g. to cover a window that just
+// opened or to uncover one that closed. Takes effect from the next frame; the mode stays as started.
+#[tauri::command]
+async fn set_redaction_regions(regions: Vec<redaction::Rect>) -> Result<(), CommandError> {
+    let (monitor_id, mut options) = ACTIVE_RECORDING
+        .lock()
+        .ok()
+        .and_then(|active| active.clone())
+        .filter(|_| RECORDING.load(Ordering::SeqCst) && !SHOULD_STOP.load(Ordering::SeqCst))
+        .ok_or(CommandError::NotRecording)?;
+    info!("Redacting {} region(s): {:?}", regions.len(), regions);
+    redaction::set(&regions, options.redaction_mode);
+    // Kept with the options so they carry over a monitor switch
+    options.redaction_regions = regions;
+    if let Ok(mut active) = ACTIVE_RECORDING.lock() {
+        *active = Some((monitor_id, options));
+    }
+    Ok(())
+}
+
+// Start a segment to keep at the current point of the recording. Returns its start in seconds since
+// the recording started; stop_recording reports the segments on the video's timeline.
+#[tauri::command]
+async fn mark_segment_start() -> Result<f64, CommandError> {
+    if !RECORDING.load(Ordering::SeqCst) {
+        return Err(CommandError::NotRecording);
+    }
+    let now = clock::elapsed()
+        .ok_or_else(|| CommandError::InvalidSegment("Capture hasn't started yet".to_string()))?;
+    let mut marks = SEGMENT_MARKS
+        .lock()
+        .map_err(|e| CommandError::InvalidSegment(e.to_string()))?;
+    if marks.last().is_some_and(|(_, end)| end.is_none()) {
+        return Err(CommandError::InvalidSegment(
+            "A segment is already open".to_string(),
+        ));
+    }
+    marks.push((now, None));
+    info!("Segment {} started at {:.3}s", marks.len(), now);
+    Ok(now)
+}
+
+// End the open segment. A segment still open when the recording stops runs to the end of the video.
+#[tauri::command]
+async fn mark_segment_end() -> Result<f64, CommandError> {
+    if !RECORDING.load(Ordering::SeqCst) {
+        return Err(CommandError::NotRecording);
+    }
+    let now = clock::elapsed()
+        .ok_or_else(|| CommandError::InvalidSegment("Capture hasn't started yet".to_string()))?;
+    let mut marks = SEGMENT_MARKS
+        .lock()
+        .map_err(|e| CommandError::InvalidSegment(e.to_string()))?;
+    let count = marks.len();
+    match marks.last_mut() {
+        Some((_, end @ None)) => {
+            *end = Some(now);
+            info!("Segment {} ended at {:.3}s", count, now);
+            Ok(now)
+        }
+        _ => Err(CommandError::InvalidSegment(
+            "No segment is open".to_string(),
+        )),
+    }
+}
+
+// Serve the other monitors' finished files. One that isn't a valid MP4 is left out rather than
+// failing the main recording. Always over HTTP, since the custom protocol serves a single file.
+fn monitor_recordings(recordings: Vec<extra_monitors::ExtraRecording>) -> Vec<MonitorRecording> {
+    let rects = RECORDED_MONITORS
+        .lock()
+        .map(|monitors| monitors.clone())
+        .unwrap_or_default();
+    // Virtual coordinates are already in desktop space, so offsets are each monitor's own origin
+    let main_origin = if VIRTUAL_COORDINATES.load(Ordering::SeqCst) {
+        (0, 0)
+    } else {
+        rects
+            .first()
+            .map_or((0, 0), |(_, rect)| (rect.left, rect.top))
+    };
+
+    recordings
+        .into_iter()
+        .filter_map(|recording| {
+            if let Err(e) = mp4::validate_file(&recording.path) {
+                warn!(
+                    "Monitor {} recording is not a valid MP4: {}",
+                    recording.monitor_index, e
+                );
+                return None;
+            }
+            let video_url = serve_video(recording.path.clone(), Some(VideoDelivery::Http))
+                .map_err(|e| {
+                    warn!(
+                        "Failed to serve monitor {} recording: {}",
+                        recording.monitor_index, e
+                    )
+                })
+                .ok()?;
+            let (left, top) = rects
+                .iter()
+                .find(|(index, _)| *index == recording.monitor_index)
+                .map_or(main_origin, |(_, rect)| (rect.left, rect.top));
+            Some(MonitorRecording {
+                monitor: recording.monitor_index,
+                video_url,
+                path: recording.path,
+                offset_x: left - main_origin.0,
+                offset_y: top - main_origin.1,
+                frame_count: recording.frame_count,
+                duration: recording.duration,
+            })
+        })
+        .collect()
+}
+
+// Modify the existing stop_recording command
+// Pass `trail` to also get cursor trail segments built from the mouse track; left out, the list is empty.
+// The MP4 stays in the temp directory and keeps being served; from here the caller owns it and must
+// call release_recording once it's done (exported or discarded). Starting a new recording doesn't
+// delete it.
+#[tauri::command]
+async fn stop_recording(
+    _: tauri::AppHandle,
+    delivery: Option<VideoDelivery>,
+    trail: Option<analysis::TrailOptions>,
+) -> Result<RecordingOutput, CommandError> {
+    info!("Starting recording stop process...");
+
+    if !RECORDING.load(Ordering::SeqCst) {
+        info!("Not recording, cleaning up any stale resources...");
+        cleanup_resources();
+        return Err(CommandError::NotRecording);
+    }
+
+    // Signal capture to stop 
+    SHOULD_STOP.store(true, Ordering::SeqCst);
+    restore_app_window();
+    let extra_recordings = extra_monitors::stop();
+    
+    // Get the video path first, in case it gets cleared during cleanup
+    let Some(video_path) = current_video_path() else {
+        cleanup_resources();
+        return Err(CommandError::NoVideoFile);
+    };
+    
+    info!("Expecting video at: {}", video_path);
+    
+    // Check if the file already exists before waiting for encoder
+    let pre_wait_file_exists = match std::fs::metadata(&video_path) {
+        Ok(metadata) => {
+            let size = metadata.len();
+            info!(
+                "Video file already exists with size: {} bytes ({:.2} MB)",
+                size,
+                size as f64 / (1024.0 * 1024.0)
+            );
+            size > 0
+        },
+        Err(_) => {
+            info!("Video file does not exist yet, will wait for encoder");
+            false
+        }
+    };
+    
+    // If file already exists with content, don't wait as long
+    let max_wait_time = if pre_wait_file_exists {
+        info!("Using shorter wait time since video file already exists");
+        std::time::Duration::from_secs(5)
+    } else {
+        info!("Using standard wait time for encoder");
+        std::time::Duration::from_secs(15)
+    };
+    
+    // Wait for encoder to finish or timeout
+    let start = Instant::now();
+    let mut last_status_time = start;
+    
+    while !ENCODING_FINISHED.load(Ordering::SeqCst) && start.elapsed() < max_wait_time {
+        // Check status and print progress every second
+        if last_status_time.elapsed().as_secs() >= 1 {
+            debug!(
+                "Waiting for encoder to finish or timeout... ({}/{}s)",
+                start.elapsed().as_secs(),
+                max_wait_time.as_secs()
+            );
+
+            // Check if the file is growing
+            if let Ok(metadata) = std::fs::metadata(&video_path) {
+                let size = metadata.len();
+                debug!(
+                    "Current video file size: {} bytes ({:.2} MB)",
+                    size,
+                    size as f64 / (1024.0 * 1024.0)
+                );
             }
+            
+            last_status_time = Instant::now();
         }
+        
+        thread::sleep(std::time::Duration::from_millis(250));
     }
+    
+    if !ENCODING_FINISHED.load(Ordering::SeqCst) {
+        warn!(
+            "Encoder still running after {}s - proceeding with current file state",
+            start.elapsed().as_secs()
+        );
+    } else {
+        info!(
+            "Encoder finished within timeout period ({}s)",
+            start.elapsed().as_secs()
+        );
 
-    // Configure capture settings
-    let settings = Settings::new(
-        monitor,
-        CursorCaptureSettings::WithoutCursor,
-        DrawBorderSettings::Default,
-        ColorFormat::Bgra8,
-        "Recording started".to_string(),
-    );
-
-    // Reset video path
-    unsafe {
-        VIDEO_PATH = None;
+        // An almost instant stop leaves the encoder with no frames. The file can still have its
+        // headers and pass the checks below, but it won't play, so don't serve it.
+        let frame_count = RECORDING_METADATA
+            .lock()
+            .map(|metadata| metadata.frame_count)
+            .unwrap_or(0);
+        if frame_count < MIN_RECORDING_FRAMES {
+            warn!(
+                "Only {} frames were encoded, discarding {}",
+                frame_count, video_path
+            );
+            cleanup_resources();
+            let _ = std::fs::remove_file(&video_path);
+            session::remove(&video_path);
+            return Err(CommandError::RecordingTooShort(frame_count));
+        }
+    }
+    
+    // Check if video file exists and is non-empty
+    let file_exists = match std::fs::metadata(&video_path) {
+        Ok(metadata) => {
+            let size = metadata.len();
+            info!(
+                "Final video file size: {} bytes ({:.2} MB)",
+                size,
+                size as f64 / (1024.0 * 1024.0)
+            );
+            size > 0
+        }
+        Err(e) => {
+            warn!("Error checking video file: {}", e);
+            false
+        }
+    };
+    
+    if !file_exists {
+        error!("No usable video file found, cleaning up");
+        cleanup_resources();
+        return Err(CommandError::NoVideoFile);
     }
 
-    // Signal that we should start listening for clicks
-    SHOULD_LISTEN_CLICKS.store(true, Ordering::SeqCst);
+    // A non-empty file can still be missing its moov if finalization timed out
+    if let Err(e) = mp4::validate_file(&video_path) {
+        error!("Video file is not a valid MP4: {}", e);
+        cleanup_resources();
+        return Err(CommandError::InvalidVideo(e));
+    }
+    
+    // Stop mouse tracking 
+    SHOULD_LISTEN_CLICKS.store(false, Ordering::SeqCst);
+    CURSOR_POLL_GENERATION.fetch_add(1, Ordering::SeqCst);
+    MEMORY_MONITOR_GENERATION.fetch_add(1, Ordering::SeqCst);
+    window_track::stop();
+    stop_pipe::stop();
+    
+    // Regardless of encoder state, try to serve the file
+    info!("Attempting to serve video file from: {}", video_path);
+    
+    let video_url = serve_video(video_path.clone(), delivery);
 
-    // Spawn mouse listener thread
-    thread::spawn(move || {
-        if let Err(error) = listen(move |event| {
-            // Check if we should continue listening
-            if !SHOULD_LISTEN_CLICKS.load(Ordering::SeqCst) {
-                return;
-            }
+    match video_url {
+        Ok(video_url) => {
+            let mut metadata = RECORDING_METADATA
+                .lock()
+                .map(|metadata| metadata.clone())
+                .unwrap_or_default();
+            let offset = metadata.first_frame_latency.unwrap_or(0.0);
+            metadata.poster = poster_frame(&video_path)
+                .map_err(|e| warn!("Failed to extract poster frame: {}", e))
+                .ok();
+            metadata.segments = take_segments(offset, metadata.duration);
+            metadata.warmup_secs = metadata.warmup_secs.min(metadata.duration);
+            session::finish(&video_path, metadata.duration);
+            let (min_cursor_type_ms, cursor_smoothing) = ACTIVE_RECORDING
+                .lock()
+                .ok()
+                .and_then(|active| {
+                    active.as_ref().map(|(_, options)| {
+                        (
+                            options.cursor_type_min_duration_ms,
+                            options.cursor_smoothing,
+                        )
+                    })
+                })
+                .unwrap_or((RecordingOptions::default().cursor_type_min_duration_ms, 0));
 
-            match event.event_type {
-                EventType::ButtonPress(_) => {
-                    if !CLICK_LOGGED.load(Ordering::SeqCst) {
-                        IS_MOUSE_CLICKED.store(true, Ordering::SeqCst);
-                        CLICK_LOGGED.store(true, Ordering::SeqCst);
-                        println!("Mouse clicked");
-                    }
-                }
-                EventType::ButtonRelease(_) => {
-                    IS_MOUSE_CLICKED.store(false, Ordering::SeqCst);
-                    CLICK_LOGGED.store(false, Ordering::SeqCst);
+            let (mouse_position_count, trail_segments) = if let Ok(mut positions) =
+                MOUSE_POSITIONS.lock()
+            {
+                let mut processed: Vec<MousePosition> = positions.drain(..).collect();
+                align_to_first_frame(&mut processed, offset);
+                merge_cursor_type_changes(&mut processed, offset);
+                process_cursor_changes(&mut processed, min_cursor_type_ms);
+                smoothing::smooth_positions(&mut processed, cursor_smoothing);
+                compute_motion(&mut processed);
+                let regressions = find_timestamp_regressions(&processed);
+                if let Some(first) = regressions.first() {
+                    warn!(
+                        "Mouse track has {} timestamp regressions, the first at sample {} ({:.3}s after {:.3}s)",
+                        regressions.len(),
+                        first.index,
+                        first.timestamp,
+                        first.previous
+                    );
                 }
-                _ => {}
-            }
-        }) {
-            println!("Error in mouse listener: {:?}", error);
+                let trail_segments = trail
+                    .map(|options| analysis::build_trail_segments(&processed, &options))
+                    .unwrap_or_default();
+                // The processed track replaces the raw samples for the paging commands
+                let count = processed.len();
+                *positions = processed.into();
+                (count, trail_segments)
+            } else {
+                (0, Vec::new())
+            };
+
+            let click_events = aligned_click_events(offset);
+
+            let monitor_recordings = monitor_recordings(extra_recordings);
+            let window_events = window_track::take(offset);
+
+            // Don't clean up resources here, as we need the file to remain available
+            Ok((
+                video_url,
+                mouse_position_count,
+                metadata,
+                click_events,
+                trail_segments,
+                monitor_recordings,
+                window_events,
+            ))
         }
-    });
+        Err(e) => {
+            error!("Failed to serve video: {}", e);
+            cleanup_resources();
+            Err(e)
+        }
+    }
+}
+
+// Stop the recording for a stop_pipe client or the tray menu, the same way the frontend's
+// stop_recording call does. The frontend gets that call's output through
+// `recording-stopped-externally`; the caller gets the path of the video file.
+fn stop_externally(requester: &str) -> Result<String, String> {
+    let app = APP_HANDLE
+        .lock()
+        .ok()
+        .and_then(|handle| handle.clone())
+        .ok_or("The app isn't running")?;
+    let path = current_video_path().ok_or("Not recording")?;
+    info!("Stopping the recording for {}", requester);
+    let output = tauri::async_runtime::block_on(stop_recording(app, None, None))
+        .map_err(|e| e.to_string())?;
+    emit_event("recording-stopped-externally", output);
+    Ok(path)
+}
 
-    // Spawn new thread for capture process
+// Closing the app mid-recording would leave the MP4 without its index and unplayable, so exit is held
+// back while the recording is stopped the usual way. Runs off the main thread: stopping restores the
+// app window, which has to go through the main thread's event loop. The app exits once the file is
+// finalized, or after EXIT_FINALIZE_TIMEOUT_SECS if the encoder hangs.
+fn finalize_on_exit(app: tauri::AppHandle) {
+    let path = current_video_path().unwrap_or_default();
+    warn!("App exiting while recording, finalizing {}", path);
+    let (tx, rx) = mpsc::channel();
+    let stop_app = app.clone();
     thread::spawn(move || {
-        if let Err(e) = CaptureHandler::start(settings) {
-            eprintln!("Screen capture failed: {:?}", e);
+        let _ = tx.send(tauri::async_runtime::block_on(stop_recording(
+            stop_app, None, None,
+        )));
+    });
+    thread::spawn(move || {
+        match rx.recv_timeout(std::time::Duration::from_secs(EXIT_FINALIZE_TIMEOUT_SECS)) {
+            Ok(Ok(_)) => info!("Recovered the recording on exit: {}", path),
+            Ok(Err(e)) => error!("Failed to finalize the recording on exit: {}", e),
+            Err(_) => error!(
+                "Timed out finalizing the recording on exit, {} may be unplayable",
+                path
+            ),
         }
+        app.exit(0);
     });
-
-    // Update recording state
-    RECORDING.store(true, Ordering::SeqCst);
-    println!("Recording started successfully");
-    Ok(())
 }
 
-// Add these constants near the top
-const CHUNK_SIZE: usize = 1024 * 1024; // 1MB chunks
+// Current memory use, to show alongside a recording or check before starting a long one
+#[tauri::command]
+async fn get_memory_stats() -> Result<MemoryStats, String> {
+    let cap_mb = ACTIVE_RECORDING.lock().ok().and_then(|active| {
+        active
+            .as_ref()
+            .and_then(|(_, options)| options.memory_cap_mb)
+    });
+    Ok(memory_stats(cap_mb))
+}
 
-// Add this new command
+// Replace the custom quality presets start_recording accepts by name, saved to the app config
+// directory. Names must differ from the built-in "high", "medium" and "low".
 #[tauri::command]
-async fn get_video_chunk(chunk_index: usize) -> Result<String, String> {
-    if let Some(mmap) = VIDEO_MMAP.lock().as_ref() {
-        let start = chunk_index * CHUNK_SIZE;
-        let end = (start + CHUNK_SIZE).min(mmap.len());
+async fn set_quality_presets(
+    presets: Vec<quality_presets::QualityPreset>,
+) -> Result<(), CommandError> {
+    quality_presets::set(presets, MAX_FPS).map_err(|e| {
+        warn!("Rejecting quality presets: {}", e);
+        CommandError::InvalidPreset(e)
+    })
+}
 
-        if start >= mmap.len() {
-            return Err("Chunk index out of bounds".to_string());
-        }
+#[tauri::command]
+async fn get_quality_presets() -> Result<Vec<quality_presets::QualityPreset>, String> {
+    Ok(quality_presets::all())
+}
 
-        let chunk = &mmap[start..end];
-        Ok(BASE64.encode(chunk))
-    } else {
-        Err("No video file available".to_string())
+// File the in-progress recording is being written to, e.g. to watch it grow. None when not
+// recording, including after stop_recording.
+#[tauri::command]
+async fn get_current_recording_path() -> Result<Option<String>, String> {
+    if !RECORDING.load(Ordering::SeqCst) {
+        return Ok(None);
     }
+    Ok(current_video_path())
 }
 
-// Initialize the memory map when recording stops
-fn init_video_mmap() -> Result<(), Box<dyn std::error::Error>> {
-    println!("Initializing video memory map...");
-    unsafe {
-        if let Some(path) = &VIDEO_PATH {
-            println!("Trying to open video file at: {}", path);
-            
-            // Make multiple attempts to open the file
-            const MAX_ATTEMPTS: usize = 3;
-            let mut last_error = None;
-            
-            for attempt in 1..=MAX_ATTEMPTS {
-                match File::open(path) {
-                    Ok(file) => {
-                        match file.metadata() {
-                            Ok(metadata) => {
-                                let file_size = metadata.len();
-                                println!("File opened (attempt {}/{}), size: {} bytes", 
-                                    attempt, MAX_ATTEMPTS, file_size);
-                                
-                                match Mmap::map(&file) {
-                                    Ok(mmap) => {
-                                        println!("Memory map created successfully, size: {} bytes", mmap.len());
-                                        *VIDEO_MMAP.lock() = Some(Arc::new(mmap));
-                                        return Ok(());
-                                    },
-                                    Err(e) => {
-                                        println!("Failed to create memory map (attempt {}/{}): {}", 
-                                            attempt, MAX_ATTEMPTS, e);
-                                        last_error = Some(e);
-                                        // Try again after a short delay
-                                        thread::sleep(std::time::Duration::from_millis(200));
-                                    }
-                                }
-                            },
-                            Err(e) => {
-                                println!("Failed to get file metadata (attempt {}/{}): {}", 
-                                    attempt, MAX_ATTEMPTS, e);
-                                last_error = Some(e.into());
-                                thread::sleep(std::time::Duration::from_millis(200));
-                            }
-                        }
-                    },
-                    Err(e) => {
-                        println!("Failed to open file (attempt {}/{}): {}", 
-                            attempt, MAX_ATTEMPTS, e);
-                        last_error = Some(e.into());
-                        thread::sleep(std::time::Duration::from_millis(200));
-                    }
-                }
-            }
-            
-            // If we've tried multiple times and still failed, return the last error
-            if let Some(e) = last_error {
-                return Err(Box::new(e));
-            } else {
-                return Err("Failed to open video file after multiple attempts".into());
-            }
-        } else {
-            println!("No video path available for memory mapping");
-            return Err("No video path available".into());
-        }
+// Recordings made since the app started, oldest first, including the one in progress. Released
+// files drop out of the list.
+#[tauri::command]
+async fn list_session_recordings() -> Result<Vec<session::RecordingEntry>, String> {
+    let recording = current_video_path().filter(|_| RECORDING.load(Ordering::SeqCst));
+    Ok(session::list(recording.as_deref(), |path| {
+        let served_by_server = VIDEO_SERVERS
+            .lock()
+            .is_ok_and(|servers| servers.iter().any(|(served, _)| served == path));
+        let served_by_protocol = PROTOCOL_VIDEO_PATH
+            .lock()
+            .is_ok_and(|protocol| protocol.as_deref() == Some(path));
+        served_by_server || served_by_protocol
+    }))
+}
+
+// Serve a finished MP4 again and return a fresh URL, e.g. after a webview reload lost the old one
+// or when opening a saved project. Any servers still running for the file are replaced.
+#[tauri::command]
+async fn serve_recording(
+    path: String,
+    delivery: Option<VideoDelivery>,
+) -> Result<String, CommandError> {
+    let current = current_video_path();
+    let is_current = current.as_deref() == Some(path.as_str());
+    if is_current && (RECORDING.load(Ordering::SeqCst) || STARTING.load(Ordering::SeqCst)) {
+        return Err(CommandError::AlreadyRecording);
+    }
+    if !std::path::Path::new(&path).is_file() {
+        return Err(CommandError::NoVideoFile);
     }
+    mp4::validate_file(&path).map_err(CommandError::InvalidVideo)?;
+
+    info!("Re-serving recording: {}", path);
+    stop_video_servers(&path);
+    serve_video(path, delivery)
 }
 
-// Modify the CORS headers function to handle both dev and prod environments
-fn add_cors_headers<R: std::io::Read>(response: &mut Response<R>) {
-    // Check if we're in development by trying to access localhost:1420
-    let origin = if cfg!(debug_assertions) {
-        "http://localhost:1420"
-    } else {
-        "http://tauri.localhost"
+// Stop serving a recording and delete its file. Defaults to the last recording.
+// Only files this app wrote to the temp directory (recordings and saved replays) can be released.
+#[tauri::command]
+async fn release_recording(path: Option<String>) -> Result<(), CommandError> {
+    let current = current_video_path();
+    let path = match path {
+        Some(path) => path,
+        None => current.clone().ok_or(CommandError::NoVideoFile)?,
     };
 
-    response.add_header(
-        tiny_http::Header::from_bytes(&b"Access-Control-Allow-Origin"[..], origin.as_bytes())
-            .unwrap(),
-    );
-    response.add_header(
-        tiny_http::Header::from_bytes(&b"Access-Control-Allow-Methods"[..], &b"GET, OPTIONS"[..])
-            .unwrap(),
-    );
+    let file = std::path::Path::new(&path);
+    let name = file.file_name().and_then(|name| name.to_str());
+    let owned = match file.parent() {
+        Some(dir) if dir == env::temp_dir() => name.is_some_and(|name| {
+            (name.starts_with("screen_recording_") || name.starts_with("screen_replay_"))
+                && name.ends_with(".mp4")
+        }),
+        // Templated recordings can have any name, but only in the app's own directory
+        Some(dir) if dir == filename::recordings_dir() => {
+            name.is_some_and(|name| name.ends_with(".mp4"))
+        }
+        _ => false,
+    };
+    if !owned {
+        return Err(CommandError::ReleaseFailed(format!(
+            "{} is not a recording created by this app",
+            path
+        )));
+    }
+    let is_current = current.as_deref() == Some(path.as_str());
+    if is_current && (RECORDING.load(Ordering::SeqCst) || STARTING.load(Ordering::SeqCst)) {
+        return Err(CommandError::AlreadyRecording);
+    }
+
+    info!("Releasing recording: {}", path);
+    release_file(&path, is_current)
 }
 
-// Modify start_video_server to track ports
-fn start_video_server(video_path: String) -> Result<u16, Box<dyn std::error::Error>> {
-    println!("Starting video server for: {}", video_path);
+// Tear down everything holding `path` open, then delete it, in this order:
+// 1. stop its video servers and the custom protocol, so no new reads start;
+// 2. if it's the current recording (`is_current`), drop the chunk reader's memory map and forget
+//    the path, since Windows can't delete a mapped file;
+// 3. delete the file and its session entry. A file that's already gone is NoVideoFile, but its
+//    entry is still removed.
+// Other recordings' servers and the current recording's map are left alone.
+fn release_file(path: &str, is_current: bool) -> Result<(), CommandError> {
+    stop_video_servers(path);
+    if let Ok(mut protocol_path) = PROTOCOL_VIDEO_PATH.lock() {
+        if protocol_path.as_deref() == Some(path) {
+            *protocol_path = None;
+        }
+    }
+    if is_current {
+        // The chunk reader's memory map is always of the last recording
+        *VIDEO_MMAP.lock() = None;
+        set_video_path(None);
+    }
 
-    // Verify file exists and is readable first
-    let file_size = match std::fs::metadata(&video_path) {
-        Ok(metadata) => {
-            let size = metadata.len();
-            println!("Video file verified: {} bytes ({:.2} MB)", 
-                size, 
-                size as f64 / (1024.0 * 1024.0)
-            );
-            
-            if size == 0 {
-                return Err("Video file exists but is empty".into());
-            }
-            size
+    match std::fs::remove_file(path) {
+        Ok(()) => {
+            session::remove(path);
+            Ok(())
         }
-        Err(e) => {
-            return Err(format!("Cannot access video file: {}", e).into());
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            session::remove(path);
+            Err(CommandError::NoVideoFile)
         }
+        Err(e) => Err(CommandError::ReleaseFailed(e.to_string())),
+    }
+}
+
+// The whole stored mouse track: raw samples while recording, the processed track after stop_recording
+#[tauri::command]
+async fn get_mouse_positions() -> Result<Vec<MousePosition>, String> {
+    debug!("Retrieving mouse positions...");
+    if let Ok(positions) = MOUSE_POSITIONS.lock() {
+        let positions_vec: Vec<MousePosition> = positions.iter().cloned().collect();
+        debug!("Retrieved {} mouse positions", positions_vec.len());
+        Ok(positions_vec)
+    } else {
+        Err("Failed to get mouse positions".to_string())
+    }
+}
+
+// Return the stored mouse track delta/run-length encoded, 5-10x smaller than the full structs.
+// Decode with EncodedMouseTrack::decode or decodeMouseTrack on the frontend.
+#[tauri::command]
+async fn get_mouse_positions_encoded(rle: Option<bool>) -> Result<EncodedMouseTrack, String> {
+    if let Ok(positions) = MOUSE_POSITIONS.lock() {
+        let positions: Vec<MousePosition> = positions.iter().cloned().collect();
+        let encoded = EncodedMouseTrack::encode(&positions, rle.unwrap_or(true));
+        debug!(
+            "Encoded {} mouse positions into {} x runs, {} timestamp runs",
+            encoded.count,
+            encoded.dx.len(),
+            encoded.dt_us.len()
+        );
+        Ok(encoded)
+    } else {
+        Err("Failed to get mouse positions".to_string())
+    }
+}
+
+// Report what the encoder actually produced (codec, profile, level, size, fps) from the MP4 boxes.
+// Defaults to the last recording.
+#[tauri::command]
+async fn probe_video(path: Option<String>) -> Result<mp4::VideoProbe, CommandError> {
+    let path = match path {
+        Some(path) => path,
+        None => current_video_path().ok_or(CommandError::NoVideoFile)?,
     };
+    let probe = mp4::probe_file(&path).map_err(CommandError::InvalidVideo)?;
+    info!("Probed {}: {:?}", path, probe);
+    Ok(probe)
+}
 
-    // Clean up old ports first
-    if let Ok(mut ports) = SERVER_PORTS.lock() {
-        ports.clear();
+// Join recordings back to back into `output`. They must share resolution, frame rate and codec;
+// the stream is copied when the encoder settings match too, otherwise it is re-encoded.
+#[tauri::command]
+async fn concat_recordings(
+    paths: Vec<String>,
+    output: String,
+) -> Result<concat::ConcatResult, CommandError> {
+    let current = current_video_path();
+    if RECORDING.load(Ordering::SeqCst) && current.is_some_and(|current| paths.contains(&current)) {
+        return Err(CommandError::AlreadyRecording);
+    }
+    if paths.contains(&output) {
+        return Err(CommandError::ConcatFailed(
+            "The output can't be one of the inputs".to_string(),
+        ));
     }
 
-    // Try ports starting from 8000
-    let mut port = 8000;
-    let server = loop {
-        println!("Trying to bind server to port {}", port);
-        match Server::http(format!("127.0.0.1:{}", port)) {
-            Ok(server) => {
-                println!("Server started on port {}", port);
-                if let Ok(mut ports) = SERVER_PORTS.lock() {
-                    ports.push(port);
-                }
-                break server;
-            }
-            Err(e) => {
-                println!("Failed to bind port {}: {}", port, e);
-                port += 1;
-                if port > 9000 {
-                    return Err("No available ports".into());
-                }
-            }
-        }
+    tauri::async_runtime::spawn_blocking(move || concat::concat(&paths, &output))
+        .await
+        .map_err(|e| CommandError::ConcatFailed(e.to_string()))?
+        .map_err(|e| {
+            warn!("Failed to join recordings: {}", e);
+            CommandError::ConcatFailed(e)
+        })
+}
+
+// Re-encode the trimmed range of a recording into `output`, fading from and to black. Defaults to
+// the last recording. A fade longer than the trimmed clip is rejected before anything is decoded.
+#[tauri::command]
+async fn export_recording(
+    path: Option<String>,
+    output: String,
+    options: Option<export::ExportOptions>,
+) -> Result<export::ExportResult, CommandError> {
+    let path = match path {
+        Some(path) => path,
+        None => current_video_path().ok_or(CommandError::NoVideoFile)?,
     };
+    if RECORDING.load(Ordering::SeqCst) && current_video_path().as_ref() == Some(&path) {
+        return Err(CommandError::AlreadyRecording);
+    }
+    if path == output {
+        return Err(CommandError::ExportFailed(
+            "The output can't be the recording itself".to_string(),
+        ));
+    }
+    let options = options.unwrap_or_default();
 
-    PORT.store(port, Ordering::SeqCst);
+    tauri::async_runtime::spawn_blocking(move || export::export(&path, &output, &options))
+        .await
+        .map_err(|e| CommandError::ExportFailed(e.to_string()))?
+        .map_err(|e| {
+            warn!("Failed to export recording: {}", e);
+            CommandError::ExportFailed(e)
+        })
+}
 
-    thread::spawn(move || {
-        println!("Opening video file for serving...");
-        match File::open(&video_path) {
-            Ok(file) => {
-                // Get the current file size again in case it changed
-                let file_size = match file.metadata() {
-                    Ok(metadata) => metadata.len(),
-                    Err(_) => file_size, // Fall back to the previously measured size
-                };
-                
-                println!("Video file opened successfully: {} bytes", file_size);
-            
-                for request in server.incoming_requests() {
-                    println!("Received request: {} {}", 
-                        request.method(), 
-                        request.url()
-                    );
-                    
-                    // Handle OPTIONS preflight request
-                    if request.method() == &tiny_http::Method::Options {
-                        println!("Handling OPTIONS request");
-                        let mut response = Response::empty(204);
-                        add_cors_headers(&mut response);
-                        let _ = request.respond(response);
-                        continue;
-                    }
-                    
-                    // Handle range request
-                    let mut start = 0;
-                    let mut end = file_size - 1;
-                    
-                    if let Some(range_header) = request
-                        .headers()
-                        .iter()
-                        .find(|h| h.field.as_str() == "Range")
-                    {
-                        if let Ok(range_str) = std::str::from_utf8(range_header.value.as_bytes()) {
-                            println!("Range request: {}", range_str);
-                            if let Some(range) = range_str.strip_prefix("bytes=") {
-                                let parts: Vec<&str> = range.split('-').collect();
-                                if parts.len() == 2 {
-                                    start = parts[0].parse::<u64>().unwrap_or(0);
-                                    end = parts[1].parse::<u64>().unwrap_or(file_size - 1);
-                                }
-                            }
-                        }
-                    }
-                    
-                    println!("Serving range: bytes {}-{}/{}", start, end, file_size);
-                    
-                    match file.try_clone() {
-                        Ok(mut file_clone) => {
-                            if let Err(e) = file_clone.seek(std::io::SeekFrom::Start(start)) {
-                                println!("Error seeking in file: {}", e);
-                                let _ = request.respond(Response::empty(500));
-                                continue;
-                            }
-                            
-                            let mut response = Response::new(
-                                if start == 0 {
-                                    StatusCode(200)
-                                } else {
-                                    StatusCode(206)
-                                },
-                                vec![],
-                                Box::new(file_clone.take(end - start + 1)),
-                                Some((end - start + 1) as usize),
-                                None,
-                            );
-                            
-                            add_cors_headers(&mut response);
-                            
-                            // Add content type header
-                            response.add_header(
-                                tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"video/mp4"[..]).unwrap(),
-                            );
-                            
-                            // Add headers for range requests
-                            if start != 0 {
-                                response.add_header(
-                                    tiny_http::Header::from_bytes(
-                                        &b"Content-Range"[..],
-                                        format!("bytes {}-{}/{}", start, end, file_size).as_bytes(),
-                                    )
-                                    .unwrap(),
-                                );
-                            }
-                            
-                            match request.respond(response) {
-                                Ok(_) => println!("Response sent successfully"),
-                                Err(e) => println!("Error sending response: {}", e),
-                            }
-                        }
-                        Err(e) => {
-                            println!("Error cloning file: {}", e);
-                            let _ = request.respond(Response::empty(500));
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                println!("Failed to open video file for serving: {}", e);
-                // Server will exit if we can't open the file
-            }
-        }
-        println!("Video server thread ended");
+// Copy the range of a recording between the keyframes nearest start_secs and end_secs into
+// `output` without re-encoding: fast and lossless, e.g. to cut a dead intro. Returns the range
+// actually kept so the editor can shift its overlays to match.
+#[tauri::command]
+async fn trim_lossless(
+    path: String,
+    start_secs: f64,
+    end_secs: f64,
+    output: String,
+) -> Result<export::LosslessTrim, CommandError> {
+    if RECORDING.load(Ordering::SeqCst) && current_video_path().as_ref() == Some(&path) {
+        return Err(CommandError::AlreadyRecording);
+    }
+    if path == output {
+        return Err(CommandError::ExportFailed(
+            "The output can't be the recording itself".to_string(),
+        ));
+    }
+
+    tauri::async_runtime::spawn_blocking(move || {
+        export::trim_lossless(&path, start_secs, end_secs, &output)
+    })
+    .await
+    .map_err(|e| CommandError::ExportFailed(e.to_string()))?
+    .map_err(|e| {
+        warn!("Failed to trim recording: {}", e);
+        CommandError::ExportFailed(e)
+    })
+}
+
+// Suggest where to trim dead time at the start and end of a recording.
+// Defaults to the last recording; the mouse track is used as an extra activity signal.
+#[tauri::command]
+async fn detect_trim_points(
+    path: Option<String>,
+    options: Option<analysis::TrimOptions>,
+) -> Result<analysis::TrimPoints, CommandError> {
+    let path = match path {
+        Some(path) => path,
+        None => current_video_path().ok_or(CommandError::NoVideoFile)?,
+    };
+    let options = options.unwrap_or_default();
+    let mouse_positions: Vec<MousePosition> = MOUSE_POSITIONS
+        .lock()
+        .map(|positions| positions.iter().cloned().collect())
+        .unwrap_or_default();
+
+    info!("Detecting trim points for {} with {:?}", path, options);
+    tauri::async_runtime::spawn_blocking(move || {
+        analysis::detect_trim_points(&path, &options, &mouse_positions)
+    })
+    .await
+    .map_err(|e| CommandError::AnalysisFailed(e.to_string()))?
+    .map_err(CommandError::AnalysisFailed)
+}
+
+// Suggest zoom-in points where the cursor rested or clicked. Coordinates are normalized to the
+// given recording size; positions default to the stored track of the last recording.
+#[tauri::command]
+async fn suggest_zoom_points(
+    positions: Option<Vec<MousePosition>>,
+    width: u32,
+    height: u32,
+    options: Option<analysis::ZoomOptions>,
+) -> Result<Vec<analysis::ZoomSuggestion>, CommandError> {
+    let positions = match positions {
+        Some(positions) => positions,
+        None => MOUSE_POSITIONS
+            .lock()
+            .map(|positions| positions.iter().cloned().collect())
+            .unwrap_or_default(),
+    };
+    let options = options.unwrap_or_default();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        analysis::suggest_zoom_points(&positions, width, height, &options)
+    })
+    .await
+    .map_err(|e| CommandError::AnalysisFailed(e.to_string()))
+}
+
+// Split a recording into chapters at long periods of cursor inactivity. Positions default to the
+// stored track of the last recording.
+#[tauri::command]
+async fn detect_chapters(
+    positions: Option<Vec<MousePosition>>,
+    options: Option<analysis::ChapterOptions>,
+) -> Result<Vec<analysis::Chapter>, CommandError> {
+    let positions = match positions {
+        Some(positions) => positions,
+        None => MOUSE_POSITIONS
+            .lock()
+            .map(|positions| positions.iter().cloned().collect())
+            .unwrap_or_default(),
+    };
+    let options = options.unwrap_or_default();
+
+    tauri::async_runtime::spawn_blocking(move || analysis::detect_chapters(&positions, &options))
+        .await
+        .map_err(|e| CommandError::AnalysisFailed(e.to_string()))
+}
+
+// Change how much the backend logs: "off", "error", "warn", "info" (default), "debug" or "trace"
+#[tauri::command]
+async fn set_log_level(level: String) -> Result<(), String> {
+    logging::set_level(&level)
+}
+
+// Also append log lines to a file, for attaching to bug reports; pass no path to stop
+#[tauri::command]
+async fn set_log_file(path: Option<String>) -> Result<(), String> {
+    logging::set_file(path.map(std::path::PathBuf::from))
+}
+
+// The log file currently written to, normally in the app's log directory
+#[tauri::command]
+async fn get_log_path() -> Result<String, String> {
+    logging::current_file()
+        .map(|path| path.to_string_lossy().to_string())
+        .ok_or_else(|| "File logging is not enabled".to_string())
+}
+
+// Zip the recent log with the last recording's metadata into the temp directory and return the
+// zip's path, for attaching to bug reports
+#[tauri::command]
+async fn export_diagnostics() -> Result<String, String> {
+    log::logger().flush();
+    let log_files = logging::current_file()
+        .map(|path| vec![logging::previous_file(&path), path])
+        .unwrap_or_default();
+
+    let mut metadata = RECORDING_METADATA
+        .lock()
+        .map(|metadata| metadata.clone())
+        .unwrap_or_default();
+    metadata.poster = None; // A base64 image, no use for debugging
+    let options = ACTIVE_RECORDING
+        .lock()
+        .ok()
+        .and_then(|active| active.as_ref().map(|(_, options)| options.clone()));
+    let environment = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "recording": RECORDING.load(Ordering::SeqCst),
+        "remote_session": is_remote_session(),
+        "video_path": current_video_path(),
+        "frame_timestamp_count": FRAME_TIMESTAMPS.lock().map(|timestamps| timestamps.len()).unwrap_or(0),
     });
+    let documents = [
+        (
+            "metadata",
+            serde_json::to_value(&metadata).map_err(|e| e.to_string())?,
+        ),
+        (
+            "options",
+            serde_json::to_value(&options).map_err(|e| e.to_string())?,
+        ),
+        ("environment", environment),
+    ];
 
-    Ok(port)
+    let millis = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let path = env::temp_dir().join(format!("screen_demo_diagnostics_{}.zip", millis));
+    tauri::async_runtime::spawn_blocking({
+        let path = path.clone();
+        move || diagnostics::write_bundle(&path, &log_files, &documents)
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    info!("Exported diagnostics to {}", path.display());
+    Ok(path.to_string_lossy().to_string())
 }
 
-// Add this function near the other utility functions
-fn process_cursor_changes(positions: &mut Vec<MousePosition>) {
-    const MIN_DURATION_MS: f64 = 100.0;
+// Presentation time (seconds) of every encoded frame, available once recording has stopped.
+// Lets the editor map a trim point to an exact frame for variable frame rate captures.
+#[tauri::command]
+async fn get_frame_timestamps() -> Result<Vec<f64>, String> {
+    if let Ok(timestamps) = FRAME_TIMESTAMPS.lock() {
+        debug!("Returning {} frame timestamps", timestamps.len());
+        Ok(timestamps.clone())
+    } else {
+        Err("Failed to get frame timestamps".to_string())
+    }
+}
 
-    let mut i = 0;
-    while i < positions.len() - 1 {
-        let current_type = &positions[i].cursor_type;
-        let mut j = i + 1;
+// Number of stored mouse positions, for paging with get_mouse_positions_range
+#[tauri::command]
+async fn get_mouse_positions_count() -> Result<usize, CommandError> {
+    MOUSE_POSITIONS
+        .lock()
+        .map(|positions| positions.len())
+        .map_err(|_| CommandError::MouseTrackUnavailable)
+}
 
-        while j < positions.len() && positions[j].cursor_type == *current_type {
-            j += 1;
-        }
+// Return up to `count` mouse positions starting at `start_index`, so long tracks can be paged
+#[tauri::command]
+async fn get_mouse_positions_range(
+    start_index: usize,
+    count: usize,
+) -> Result<Vec<MousePosition>, CommandError> {
+    let positions = MOUSE_POSITIONS
+        .lock()
+        .map_err(|_| CommandError::MouseTrackUnavailable)?;
+    let start = start_index.min(positions.len());
+    let end = start.saturating_add(count).min(positions.len());
+    let positions_vec: Vec<MousePosition> = positions.range(start..end).cloned().collect();
+    debug!(
+        "Retrieved {} mouse positions from index {} (total {})",
+        positions_vec.len(),
+        start_index,
+        positions.len()
+    );
+    Ok(positions_vec)
+}
 
-        // If this cursor type lasted less than MIN_DURATION_MS and we're not at the start
-        let duration = (positions[j - 1].timestamp - positions[i].timestamp) * 1000.0;
-        if duration < MIN_DURATION_MS && i > 0 {
-            // Replace the short duration with the previous type
-            let prev_type = positions[i - 1].cursor_type.clone();
-            for pos in positions.iter_mut().take(j).skip(i) {
-                pos.cursor_type = prev_type.clone();
-            }
-        }
-        i = j;
+// Smooth a mouse track (e.g. stop_recording's, or one saved with a project) at the editor's
+// "Movement Smoothing" level, 0 to 10. The editor draws both preview and export from the result,
+// so they can't disagree about where the cursor is.
+#[tauri::command]
+async fn smooth_mouse_track(
+    mut positions: Vec<MousePosition>,
+    smoothing: u32,
+) -> Result<Vec<MousePosition>, String> {
+    if smoothing > smoothing::MAX_SMOOTHING {
+        return Err(format!(
+            "Smoothing must be between 0 and {}, got {}",
+            smoothing::MAX_SMOOTHING,
+            smoothing
+        ));
     }
+    smoothing::smooth_positions(&mut positions, smoothing);
+    compute_motion(&mut positions);
+    Ok(positions)
 }
 
-// Modify the existing stop_recording command
+// Check that a mouse track's timestamps never decrease, e.g. after editing or merging tracks.
+// Without positions, checks the stored track. Returns the offending samples; empty when valid.
 #[tauri::command]
-async fn stop_recording(_: tauri::AppHandle) -> Result<(String, Vec<MousePosition>), String> {
-    println!("Starting recording stop process...");
+async fn check_mouse_timestamps(
+    positions: Option<Vec<MousePosition>>,
+) -> Result<Vec<TimestampRegression>, String> {
+    match positions {
+        Some(positions) => Ok(find_timestamp_regressions(&positions)),
+        None => MOUSE_POSITIONS
+            .lock()
+            .map(|mut positions| find_timestamp_regressions(positions.make_contiguous()))
+            .map_err(|_| "Failed to get mouse positions".to_string()),
+    }
+}
 
-    if !RECORDING.load(Ordering::SeqCst) {
-        println!("Not recording, cleaning up any stale resources...");
-        cleanup_resources();
-        return Err("Not recording".to_string());
+// Mouse positions inside the editor's trim range, rebased so 0 is the trim start like the exported
+// video. The range is clamped to the track; the last sample before the start is kept as the cursor
+// position at 0.
+#[tauri::command]
+async fn get_mouse_positions_trimmed(
+    start_secs: f64,
+    end_secs: f64,
+) -> Result<Vec<MousePosition>, CommandError> {
+    let positions = MOUSE_POSITIONS
+        .lock()
+        .map_err(|_| CommandError::MouseTrackUnavailable)?;
+    let last = positions.back().map_or(0.0, |p| p.timestamp);
+    let start = start_secs.max(0.0);
+    let end = end_secs.min(last);
+    if start > end {
+        return Err(CommandError::InvalidTrimRange(format!(
+            "{}s to {}s",
+            start_secs, end_secs
+        )));
     }
 
-    // Signal capture to stop 
-    SHOULD_STOP.store(true, Ordering::SeqCst);
-    
-    // Get the video path first, in case it gets cleared during cleanup
-    let video_path = unsafe {
-        if let Some(path) = &VIDEO_PATH {
-            path.clone()
-        } else {
-            cleanup_resources();
-            return Err("No video path available".to_string());
-        }
-    };
-    
-    println!("Expecting video at: {}", video_path);
-    
-    // Check if the file already exists before waiting for encoder
-    let pre_wait_file_exists = match std::fs::metadata(&video_path) {
-        Ok(metadata) => {
-            let size = metadata.len();
-            println!("Video file already exists with size: {} bytes ({:.2} MB)", 
-                size, size as f64 / (1024.0 * 1024.0));
-            size > 0
-        },
-        Err(_) => {
-            println!("Video file does not exist yet, will wait for encoder");
-            false
-        }
-    };
-    
-    // If file already exists with content, don't wait as long
-    let max_wait_time = if pre_wait_file_exists {
-        println!("Using shorter wait time since video file already exists");
-        std::time::Duration::from_secs(5)
+    let first_inside = positions.partition_point(|p| p.timestamp < start);
+    let trimmed: Vec<MousePosition> = positions
+        .range(first_inside.saturating_sub(1)..)
+        .take_while(|p| p.timestamp <= end)
+        .map(|p| MousePosition {
+            timestamp: (p.timestamp - start).max(0.0),
+            ..p.clone()
+        })
+        .collect();
+    debug!(
+        "Trimmed mouse track to {:.3}s-{:.3}s: {} of {} samples",
+        start,
+        end,
+        trimmed.len(),
+        positions.len()
+    );
+    Ok(trimmed)
+}
+
+// Write the last recording's mouse track and clicks to `path` as JSON or CSV, for analysis in other
+// tools. With iso_timestamps each entry also gets its wall-clock time.
+#[tauri::command]
+async fn export_mouse_track(
+    path: String,
+    format: track_export::TrackFormat,
+    iso_timestamps: Option<bool>,
+) -> Result<(), String> {
+    let positions: Vec<MousePosition> = MOUSE_POSITIONS
+        .lock()
+        .map(|positions| positions.iter().cloned().collect())
+        .map_err(|_| "Failed to get mouse positions".to_string())?;
+    let offset = RECORDING_METADATA
+        .lock()
+        .ok()
+        .and_then(|metadata| metadata.first_frame_latency)
+        .unwrap_or(0.0);
+    let clicks = aligned_click_events(offset);
+    // Track timestamps count from the first frame, which came `offset` after the capture start
+    let start = if iso_timestamps.unwrap_or(false) {
+        CLICK_EPOCH
+            .lock()
+            .ok()
+            .and_then(|epoch| *epoch)
+            .map(|epoch| epoch + std::time::Duration::from_secs_f64(offset))
     } else {
-        println!("Using standard wait time for encoder");
-        std::time::Duration::from_secs(15)
+        None
     };
-    
-    // Wait for encoder to finish or timeout
-    let start = Instant::now();
-    let mut last_status_time = start;
-    
-    while !ENCODING_FINISHED.load(Ordering::SeqCst) && start.elapsed() < max_wait_time {
-        // Check status and print progress every second
-        if last_status_time.elapsed().as_secs() >= 1 {
-            println!("Waiting for encoder to finish or timeout... ({}/{}s)", 
-                start.elapsed().as_secs(), max_wait_time.as_secs());
-            
-            // Check if the file is growing
-            if let Ok(metadata) = std::fs::metadata(&video_path) {
-                let size = metadata.len();
-                println!("Current video file size: {} bytes ({:.2} MB)", 
-                    size, size as f64 / (1024.0 * 1024.0));
-            }
-            
-            last_status_time = Instant::now();
-        }
-        
-        thread::sleep(std::time::Duration::from_millis(250));
+
+    info!(
+        "Exporting {} mouse positions and {} clicks to {} as {:?}",
+        positions.len(),
+        clicks.len(),
+        path,
+        format
+    );
+    tauri::async_runtime::spawn_blocking(move || {
+        track_export::write_track(
+            std::path::Path::new(&path),
+            format,
+            &positions,
+            &clicks,
+            start,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// List available webcams for picture-in-picture
+#[tauri::command]
+async fn get_cameras() -> Result<Vec<CameraInfo>, String> {
+    info!("Enumerating cameras...");
+    let cameras = media::list_cameras()?;
+    info!("Found {} cameras", cameras.len());
+    Ok(cameras)
+}
+
+// Grab a single frame of the monitor (optionally cropped to a region) and return it as base64 PNG
+#[tauri::command]
+async fn capture_screenshot(
+    monitor_id: Option<String>,
+    region: Option<screenshot::CaptureRegion>,
+) -> Result<String, CommandError> {
+    let (monitor, _) = resolve_monitor(monitor_id.as_deref())?;
+    let png =
+        tauri::async_runtime::spawn_blocking(move || screenshot::capture_png(monitor, region))
+            .await
+            .map_err(|e| CommandError::EncoderFailed(e.to_string()))?
+            .map_err(CommandError::EncoderFailed)?;
+    info!("Captured screenshot: {} bytes", png.len());
+    Ok(BASE64.encode(png))
+}
+
+// One uncompressed frame of the monitor as captured, for checking colors against known on-screen
+// values without the encoder's YUV conversion in between. The frame is width * height * 4 bytes and
+// goes over IPC as a JSON number array, several times that size, so only call it on demand.
+#[tauri::command]
+async fn get_raw_frame(monitor_id: Option<String>) -> Result<screenshot::RawFrame, CommandError> {
+    let (monitor, _) = resolve_monitor(monitor_id.as_deref())?;
+    let frame =
+        tauri::async_runtime::spawn_blocking(move || screenshot::capture_raw(monitor, None))
+            .await
+            .map_err(|e| CommandError::EncoderFailed(e.to_string()))?
+            .map_err(CommandError::EncoderFailed)?;
+    info!("Captured raw frame: {}x{}", frame.width, frame.height);
+    Ok(frame)
+}
+
+// Start instant replay: keep the last `seconds` of the monitor in memory until save_buffer is called.
+// Memory is capped at max_memory_mb; the oldest frames are dropped first when either limit is hit.
+#[tauri::command]
+async fn start_buffering(
+    seconds: u32,
+    monitor_id: Option<String>,
+    max_memory_mb: Option<u32>,
+) -> Result<(), CommandError> {
+    if seconds == 0 || seconds > replay::MAX_SECONDS {
+        return Err(CommandError::ReplayFailed(format!(
+            "Buffer length must be between 1 and {} seconds",
+            replay::MAX_SECONDS
+        )));
     }
-    
-    if !ENCODING_FINISHED.load(Ordering::SeqCst) {
-        println!("Encoder still running after {}s - proceeding with current file state", start.elapsed().as_secs());
-    } else {
-        println!("Encoder finished within timeout period ({}s)", start.elapsed().as_secs());
+    let (monitor, _) = resolve_monitor(monitor_id.as_deref())?;
+    let max_memory_mb = max_memory_mb
+        .unwrap_or(replay::DEFAULT_MAX_MEMORY_MB)
+        .max(16);
+    replay::start(monitor, seconds, max_memory_mb).map_err(CommandError::ReplayFailed)
+}
+
+#[tauri::command]
+async fn stop_buffering() -> Result<(), CommandError> {
+    if !replay::is_active() {
+        return Err(CommandError::ReplayFailed(
+            "Replay buffering is not running".to_string(),
+        ));
     }
-    
-    // Check if video file exists and is non-empty
-    let file_exists = match std::fs::metadata(&video_path) {
-        Ok(metadata) => {
-            let size = metadata.len();
-            println!("Final video file size: {} bytes ({:.2} MB)", 
-                size, size as f64 / (1024.0 * 1024.0));
-            size > 0
-        }
-        Err(e) => {
-            println!("Error checking video file: {}", e);
-            false
-        }
+    replay::stop();
+    Ok(())
+}
+
+// Write the replay buffer to a file and serve it like stop_recording. There is no mouse track for
+// buffered footage (the cursor is captured into the frames), so the count is 0 and the lists empty.
+#[tauri::command]
+async fn save_buffer(delivery: Option<VideoDelivery>) -> Result<RecordingOutput, CommandError> {
+    let millis = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let video_path = env::temp_dir()
+        .join(format!("screen_replay_{}.mp4", millis))
+        .to_string_lossy()
+        .to_string();
+
+    let save_path = video_path.clone();
+    let saved = tauri::async_runtime::spawn_blocking(move || replay::save(&save_path))
+        .await
+        .map_err(|e| CommandError::ReplayFailed(e.to_string()))?
+        .map_err(CommandError::ReplayFailed)?;
+    mp4::validate_file(&video_path).map_err(CommandError::InvalidVideo)?;
+    info!("Saved replay: {:?}", saved);
+
+    set_video_path(Some(video_path.clone()));
+    session::add(&video_path);
+    session::finish(&video_path, saved.duration);
+    let metadata = RecordingMetadata {
+        requested_fps: TARGET_FPS,
+        actual_fps: saved.frame_count as f64 / saved.duration.max(f64::EPSILON),
+        frame_count: saved.frame_count,
+        duration: saved.duration,
+        color_format: "bgra8".to_string(),
+        color_range: "full".to_string(),
+        color_primaries: "bt709".to_string(),
+        poster: poster_frame(&video_path)
+            .map_err(|e| warn!("Failed to extract poster frame: {}", e))
+            .ok(),
+        ..Default::default()
     };
-    
-    if !file_exists {
-        println!("No usable video file found, cleaning up");
-        cleanup_resources();
-        return Err("No usable video file was created. The recording may have failed.".to_string());
-    }
-    
-    // Stop mouse tracking 
-    SHOULD_LISTEN_CLICKS.store(false, Ordering::SeqCst);
-    IS_MOUSE_CLICKED.store(false, Ordering::SeqCst);
-    
-    // Regardless of encoder state, try to serve the file
-    println!("Attempting to serve video file from: {}", video_path);
-    
-    match start_video_server(video_path) {
-        Ok(port) => {
-            println!("Server started successfully on port {}", port);
-            let mouse_positions = if let Ok(mut positions) = MOUSE_POSITIONS.lock() {
-                let mut positions: Vec<MousePosition> = positions.drain(..).collect();
-                process_cursor_changes(&mut positions);
-                positions
-            } else {
-                Vec::new()
-            };
-            
-            // Don't clean up resources here, as we need the file to remain available
-            Ok((format!("http://localhost:{}", port), mouse_positions))
-        }
-        Err(e) => {
-            println!("Server failed to start: {}", e);
-            cleanup_resources();
-            Err(format!("Failed to start video server: {}", e))
-        }
-    }
+    let video_url = serve_video(video_path, delivery)?;
+    Ok((
+        video_url,
+        0,
+        metadata,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+    ))
 }
 
-// Add new command to get mouse positions
+// Video encoders Media Foundation offers on this machine, hardware ones first.
+// Probed once per run since it activates every encoder.
 #[tauri::command]
-async fn get_mouse_positions() -> Result<Vec<MousePosition>, String> {
-    println!("Retrieving mouse positions...");
-    if let Ok(positions) = MOUSE_POSITIONS.lock() {
-        let positions_vec: Vec<MousePosition> = positions.iter().cloned().collect();
-        println!("Retrieved {} mouse positions", positions_vec.len());
-        Ok(positions_vec)
-    } else {
-        Err("Failed to get mouse positions".to_string())
-    }
+async fn get_supported_encoders() -> Result<Vec<EncoderCapability>, String> {
+    tauri::async_runtime::spawn_blocking(encoders::supported_encoders)
+        .await
+        .map_err(|e| e.to_string())
 }
 
 // Add static variables for monitor position
-static mut MONITOR_X: i32 = 0;
-static mut MONITOR_Y: i32 = 0;
+static MONITOR_ORIGIN: Mutex<(i32, i32)> = Mutex::new((0, 0));
+static VIRTUAL_COORDINATES: AtomicBool = AtomicBool::new(false); // RecordingOptions.coordinate_space is Virtual
 
 // Entry point for the Tauri application
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    logging::init();
     tauri::Builder::default()
+        .setup(|app| {
+            // Keep a handle around so capture threads can emit events
+            if let Ok(mut handle) = APP_HANDLE.lock() {
+                *handle = Some(app.handle().clone());
+            }
+            // Release builds have no console, so keep a log file for bug reports
+            match app.path().app_log_dir() {
+                Ok(dir) => {
+                    if let Err(e) = logging::start_rolling_file(&dir) {
+                        warn!("{}", e);
+                    }
+                }
+                Err(e) => warn!("No log directory available: {}", e),
+            }
+            match app.path().app_config_dir() {
+                Ok(dir) => quality_presets::load(&dir),
+                Err(e) => warn!("No config directory available for quality presets: {}", e),
+            }
+            if let Err(e) = tray::create(app.handle()) {
+                warn!("Failed to create the tray icon: {}", e);
+            }
+            // Probe the encoders in the background so the first start_recording doesn't wait on it
+            thread::spawn(encoders::supported_encoders);
+            info!("Screen Demo {} starting", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        })
+        .register_asynchronous_uri_scheme_protocol(VIDEO_PROTOCOL, |_ctx, request, responder| {
+            // Reads can be large, keep them off the main thread
+            thread::spawn(move || responder.respond(serve_protocol_video(&request)));
+        })
         .plugin(tauri_plugin_updater::Builder::new().build())
         .plugin(tauri_plugin_shell::init())
         .invoke_handler(tauri::generate_handler![
@@ -1177,8 +5169,446 @@ pub fn run() {
             stop_recording,
             get_monitors,
             get_mouse_positions,
+            get_mouse_positions_count,
+            get_mouse_positions_range,
+            get_mouse_positions_encoded,
             get_video_chunk,
+            get_cameras,
+            switch_monitor,
+            get_frame_timestamps,
+            detect_trim_points,
+            probe_video,
+            suggest_zoom_points,
+            test_capture,
+            get_supported_encoders,
+            start_buffering,
+            stop_buffering,
+            save_buffer,
+            capture_screenshot,
+            run_capture_benchmark,
+            release_recording,
+            detect_chapters,
+            set_log_level,
+            set_log_file,
+            get_log_path,
+            export_diagnostics,
+            mark_segment_start,
+            mark_segment_end,
+            get_mouse_positions_trimmed,
+            export_mouse_track,
+            concat_recordings,
+            serve_recording,
+            check_capture_permissions,
+            get_current_recording_path,
+            get_memory_stats,
+            set_quality_presets,
+            get_quality_presets,
+            export_recording,
+            smooth_mouse_track,
+            get_raw_frame,
+            check_mouse_timestamps,
+            get_video_bytes,
+            list_session_recordings,
+            trim_lossless,
+            set_recording_bitrate,
+            set_click_tracking,
+            set_redaction_regions,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::ExitRequested { api, .. } = event {
+                // Only held back once; the exit finalize_on_exit requests goes through
+                if RECORDING.load(Ordering::SeqCst)
+                    && !FINALIZING_ON_EXIT.swap(true, Ordering::SeqCst)
+                {
+                    api.prevent_exit();
+                    finalize_on_exit(app.clone());
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Barrier;
+
+    #[test]
+    fn start_guard_admits_one_of_two_racing_starts() {
+        static FLAG: AtomicBool = AtomicBool::new(false);
+        for _ in 0..100 {
+            let barrier = Arc::new(Barrier::new(2));
+            let racers: Vec<_> = (0..2)
+                .map(|_| {
+                    let barrier = Arc::clone(&barrier);
+                    thread::spawn(move || {
+                        barrier.wait();
+                        let guard = StartGuard::acquire(&FLAG);
+                        let won = guard.is_some();
+                        // Hold the guard until both have tried
+                        barrier.wait();
+                        won
+                    })
+                })
+                .collect();
+            let winners = racers
+                .into_iter()
+                .map(|racer| racer.join().unwrap())
+                .filter(|won| *won)
+                .count();
+            assert_eq!(winners, 1);
+            assert!(!FLAG.load(Ordering::SeqCst));
+        }
+    }
+
+    #[test]
+    fn start_guard_released_on_drop_and_error() {
+        static FLAG: AtomicBool = AtomicBool::new(false);
+        let guard = StartGuard::acquire(&FLAG).unwrap();
+        assert!(StartGuard::acquire(&FLAG).is_none());
+        drop(guard);
+        assert!(!FLAG.load(Ordering::SeqCst));
+
+        // Like start_recording bailing out with `?` after taking the guard
+        fn failing_start() -> Result<(), CommandError> {
+            let _guard = StartGuard::acquire(&FLAG).ok_or(CommandError::AlreadyStarting)?;
+            Err(CommandError::NotRecording)
+        }
+        assert!(failing_start().is_err());
+        assert!(!FLAG.load(Ordering::SeqCst));
+        assert!(StartGuard::acquire(&FLAG).is_some());
+    }
+
+    fn serve_for_test(path: &str) {
+        let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+        VIDEO_SERVERS
+            .lock()
+            .unwrap()
+            .push((path.to_string(), server));
+    }
+
+    fn is_served(path: &str) -> bool {
+        VIDEO_SERVERS
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(served, _)| served == path)
+    }
+
+    // One test, since the steps share the recording statics
+    #[test]
+    fn release_file_lifecycle() {
+        let dir = env::temp_dir();
+        let current = dir.join("screen_recording_release_test_current.mp4");
+        let other = dir.join("screen_recording_release_test_other.mp4");
+        let (current, other) = (
+            current.to_string_lossy().to_string(),
+            other.to_string_lossy().to_string(),
+        );
+        std::fs::write(&current, b"current recording").unwrap();
+        std::fs::write(&other, b"older recording").unwrap();
+
+        // The current recording is mapped, served over HTTP and by the protocol
+        set_video_path(Some(current.clone()));
+        let map = unsafe { Mmap::map(&File::open(&current).unwrap()).unwrap() };
+        *VIDEO_MMAP.lock() = Some(Arc::new(map));
+        *PROTOCOL_VIDEO_PATH.lock().unwrap() = Some(current.clone());
+        serve_for_test(&current);
+        serve_for_test(&other);
+        session::add(&other);
+
+        // Releasing another path leaves the current recording alone
+        release_file(&other, false).unwrap();
+        assert!(!std::path::Path::new(&other).exists());
+        assert!(!is_served(&other));
+        assert!(is_served(&current));
+        assert!(VIDEO_MMAP.lock().is_some());
+        assert_eq!(current_video_path().as_deref(), Some(current.as_str()));
+        assert_eq!(
+            PROTOCOL_VIDEO_PATH.lock().unwrap().as_deref(),
+            Some(current.as_str())
+        );
+        assert!(session::list(None, |_| false)
+            .iter()
+            .all(|entry| entry.path != other));
+
+        // Releasing the served, mapped recording stops serving and unmaps before deleting
+        release_file(&current, true).unwrap();
+        assert!(!std::path::Path::new(&current).exists());
+        assert!(!is_served(&current));
+        assert!(VIDEO_MMAP.lock().is_none());
+        assert!(current_video_path().is_none());
+        assert!(PROTOCOL_VIDEO_PATH.lock().unwrap().is_none());
+
+        // Releasing it again finds nothing to delete
+        assert!(matches!(
+            release_file(&current, false),
+            Err(CommandError::NoVideoFile)
+        ));
+    }
+
+    #[test]
+    fn half_float_bit_patterns() {
+        assert_eq!(half_to_f32(0x3C00), 1.0);
+        assert_eq!(half_to_f32(0xC000), -2.0);
+        assert_eq!(half_to_f32(0x7BFF), 65504.0); // Largest finite half
+        assert_eq!(half_to_f32(0x0000), 0.0);
+        assert_eq!(half_to_f32(0x0001), 2f32.powi(-24)); // Smallest subnormal
+        assert_eq!(half_to_f32(0x03FF), 1023.0 * 2f32.powi(-24)); // Largest subnormal
+        assert_eq!(half_to_f32(0x7C00), f32::INFINITY);
+        assert_eq!(half_to_f32(0xFC00), f32::NEG_INFINITY);
+        assert!(half_to_f32(0x7E00).is_nan());
+        assert!(half_to_f32(0xFE00).is_nan());
+    }
+
+    #[test]
+    fn hdr_lut_clamps_and_rolls_off() {
+        let lut = build_hdr_lut();
+        assert_eq!(lut.len(), 1 << 16);
+        assert_eq!(lut[0x0000], 0);
+        assert_eq!(lut[0xC000], 0); // Negative
+        assert_eq!(lut[0x7E00], 0); // NaN
+        assert_eq!(lut[0x7C00], 255); // +inf rolls off to white
+
+        // Past the knee values roll off: SDR white (2.5) is just below white, and the brightest
+        // values reach it without wrapping
+        assert_eq!(lut[0x4100], 247);
+        assert_eq!(lut[0x7BFF], 255);
+        assert!(lut[..=0x7C00].windows(2).all(|pair| pair[0] <= pair[1]));
+        // Below the knee values pass through: 1.0 is 0.4 of SDR white, sRGB-encoded
+        assert_eq!(lut[0x3C00], 170);
+    }
+
+    #[test]
+    fn tone_map_swaps_to_bgra() {
+        let lut = build_hdr_lut();
+        // One RGBA pixel: red SDR white, green 0, blue 1.0, alpha 1.0
+        let pixel: Vec<u8> = [0x4100u16, 0x0000, 0x3C00, 0x3C00]
+            .iter()
+            .flat_map(|half| half.to_le_bytes())
+            .collect();
+        let mut out = Vec::new();
+        tone_map_rgba16f(&pixel, &lut, &mut out);
+        assert_eq!(out, vec![170, 0, 247, 255]);
+    }
+
+    #[test]
+    fn server_token_checks() {
+        let token = "0123456789abcdef";
+        assert!(request_has_token("/?token=0123456789abcdef", None, token));
+        assert!(request_has_token(
+            "/video?x=1&token=0123456789abcdef",
+            None,
+            token
+        ));
+        assert!(request_has_token("/", Some(token), token));
+        // Missing
+        assert!(!request_has_token("/", None, token));
+        assert!(!request_has_token("/?other=1", None, token));
+        assert!(!request_has_token("/?token=", Some(""), token));
+        // Wrong
+        assert!(!request_has_token("/?token=0123456789abcdee", None, token));
+        assert!(!request_has_token("/", Some("fedcba9876543210"), token));
+        // A prefix of the token, or the token with more after it
+        assert!(!request_has_token("/?token=01234567", None, token));
+        assert!(!request_has_token("/", Some("0123456789abcdef0"), token));
+        // A wrong query token doesn't hide a right header
+        assert!(request_has_token("/?token=nope", Some(token), token));
+    }
+
+    fn mouse_sample(x: i32, y: i32, timestamp: f64, cursor_type: &str) -> MousePosition {
+        MousePosition {
+            x,
+            y,
+            timestamp,
+            isClicked: false,
+            cursor_type: cursor_type.to_string(),
+            click_type: None,
+            velocity: None,
+            acceleration: None,
+            on_screen: true,
+            monitor: Some(0),
+        }
+    }
+
+    fn assert_same_track(decoded: &[MousePosition], original: &[MousePosition]) {
+        assert_eq!(decoded.len(), original.len());
+        for (d, o) in decoded.iter().zip(original) {
+            assert_eq!((d.x, d.y), (o.x, o.y));
+            assert!((d.timestamp - o.timestamp).abs() < 1e-6);
+            assert_eq!(d.isClicked, o.isClicked);
+            assert_eq!(d.cursor_type, o.cursor_type);
+            assert_eq!(d.click_type, o.click_type);
+            assert_eq!(d.on_screen, o.on_screen);
+            assert_eq!(d.monitor, o.monitor);
+        }
+    }
+
+    #[test]
+    fn run_length_round_trip() {
+        let values = [1, 1, 2, 3, 3, 3, 1];
+        let merged = run_length_encode(values, true);
+        assert_eq!(merged, vec![(2, 1), (1, 2), (3, 3), (1, 1)]);
+        assert_eq!(run_length_decode(&merged).collect::<Vec<_>>(), values);
+
+        let unmerged = run_length_encode(values, false);
+        assert_eq!(unmerged.len(), values.len());
+        assert!(unmerged.iter().all(|&(count, _)| count == 1));
+        assert_eq!(run_length_decode(&unmerged).collect::<Vec<_>>(), values);
+
+        assert!(run_length_encode(Vec::<i32>::new(), true).is_empty());
+        assert_eq!(run_length_decode::<i32>(&[]).count(), 0);
+        assert_eq!(run_length_encode([7], true), vec![(1, 7)]);
+    }
+
+    #[test]
+    fn mouse_track_round_trip() {
+        for rle in [true, false] {
+            let empty = EncodedMouseTrack::encode(&[], rle);
+            assert_eq!(empty.count, 0);
+            assert!(empty.decode().is_empty());
+
+            let single = [mouse_sample(-5, 12, 3.25, "arrow")];
+            assert_same_track(&EncodedMouseTrack::encode(&single, rle).decode(), &single);
+        }
+
+        // A steady stretch (long runs), then a sample that changes everything at once (single
+        // sample runs), then the cursor leaving the recorded monitor
+        let mut track: Vec<MousePosition> = (0..6)
+            .map(|i| mouse_sample(100 + 4 * i, 200, 1.0 + i as f64 * 0.016, "arrow"))
+            .collect();
+        let mut press = mouse_sample(130, 180, 1.1, "hand");
+        press.isClicked = true;
+        press.click_type = Some("single".to_string());
+        track.push(press);
+        let mut away = mouse_sample(-40, 180, 1.116, "arrow");
+        away.on_screen = false;
+        away.monitor = None;
+        track.push(away);
+
+        let merged = EncodedMouseTrack::encode(&track, true);
+        let unmerged = EncodedMouseTrack::encode(&track, false);
+        assert!(merged.dx.len() < track.len());
+        assert_eq!(unmerged.dx.len(), track.len());
+        assert_eq!(unmerged.dt_us.len(), track.len());
+        for encoded in [merged, unmerged] {
+            let decoded = encoded.decode();
+            assert_same_track(&decoded, &track);
+            assert!(decoded[0].velocity.is_none());
+            assert!(decoded[1].velocity.is_some());
+        }
+    }
+
+    #[test]
+    fn identity_encoding_refusals() {
+        assert!(!accepts_identity_encoding(Some("identity;q=0")));
+        assert!(!accepts_identity_encoding(Some("gzip, identity; q=0")));
+        // A wildcard refusal covers identity unless identity is listed itself
+        assert!(!accepts_identity_encoding(Some("gzip, *;q=0")));
+        assert!(accepts_identity_encoding(Some("*;q=0, identity")));
+        assert!(!accepts_identity_encoding(Some("identity;q=0, *")));
+    }
+
+    #[test]
+    fn identity_encoding_accepted() {
+        assert!(accepts_identity_encoding(None));
+        assert!(accepts_identity_encoding(Some("")));
+        assert!(accepts_identity_encoding(Some("gzip, deflate, br")));
+        assert!(accepts_identity_encoding(Some("identity;q=0.5")));
+        // An unparseable q-value counts as the default of 1
+        assert!(accepts_identity_encoding(Some("identity;q=abc")));
+        assert!(accepts_identity_encoding(Some("*;q=")));
+    }
+
+    #[test]
+    fn byte_range_forms() {
+        let size = 1000;
+        assert_eq!(parse_byte_range(None, size, 100), ByteRange::Whole);
+        assert_eq!(
+            parse_byte_range(Some("bytes=0-99"), size, 100),
+            ByteRange::Part(0, 99)
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=900-"), size, 50),
+            ByteRange::Part(900, 949)
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=980-"), size, 50),
+            ByteRange::Part(980, 999)
+        );
+        // End past the file is clamped
+        assert_eq!(
+            parse_byte_range(Some("bytes=10-5000"), size, 100),
+            ByteRange::Part(10, 999)
+        );
+        // Suffix: the last N bytes, not the first
+        assert_eq!(
+            parse_byte_range(Some("bytes=-100"), size, 10),
+            ByteRange::Part(900, 999)
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=-5000"), size, 10),
+            ByteRange::Part(0, 999)
+        );
+    }
+
+    #[test]
+    fn byte_range_unsatisfiable() {
+        let size = 1000;
+        assert_eq!(
+            parse_byte_range(Some("bytes=1000-"), size, 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=2000-2100"), size, 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=50-10"), size, 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=-0"), size, 100),
+            ByteRange::Unsatisfiable
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=-10"), 0, 100),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn byte_range_huge_start_does_not_overflow() {
+        let size = u64::MAX;
+        assert_eq!(
+            parse_byte_range(
+                Some("bytes=18446744073709551000-"),
+                size,
+                PROTOCOL_MAX_RANGE
+            ),
+            ByteRange::Part(18446744073709551000, u64::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn byte_range_ignores_malformed() {
+        let size = 1000;
+        assert_eq!(
+            parse_byte_range(Some("items=0-10"), size, 100),
+            ByteRange::Whole
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=abc-10"), size, 100),
+            ByteRange::Whole
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=0-10,20-30"), size, 100),
+            ByteRange::Whole
+        );
+        assert_eq!(
+            parse_byte_range(Some("bytes=5"), size, 100),
+            ByteRange::Whole
+        );
+    }
 }
@@ -0,0 +1,143 @@
+// Leveled logging for the backend: everything goes to the console, and optionally to a file so
+// logs can be attached to bug reports (release builds have no visible console)
+use log::{LevelFilter, Log, Metadata, Record};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Info;
+const LOG_FILE_NAME: &str = "screen-demo.log";
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024; // The file is rolled over to a .old.log past this
+
+static LOGGER: Logger = Logger {
+    file: Mutex::new(None),
+};
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+struct Logger {
+    file: Mutex<Option<LogFile>>,
+}
+
+// Where a log file's previous generation is kept after rolling over
+pub fn previous_file(path: &Path) -> PathBuf {
+    path.with_extension("old.log")
+}
+
+fn open_append(path: &Path) -> Result<LogFile, String> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Failed to open log file {}: {}", path.display(), e))?;
+    let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+    Ok(LogFile {
+        path: path.to_path_buf(),
+        file,
+        size,
+    })
+}
+
+// Move the full log aside (replacing the previous generation) and start an empty one
+fn roll_over(path: &Path) -> Result<LogFile, String> {
+    std::fs::rename(path, previous_file(path)).map_err(|e| e.to_string())?;
+    open_append(path)
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or(0);
+        let line = format!(
+            "{} {:<5} [{}] {}",
+            millis,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        println!("{}", line);
+        if let Ok(mut current) = self.file.lock() {
+            if let Some(log_file) = current.as_mut() {
+                if writeln!(log_file.file, "{}", line).is_ok() {
+                    log_file.size += line.len() as u64 + 1;
+                }
+                if log_file.size > MAX_LOG_BYTES {
+                    let path = log_file.path.clone();
+                    // Close the full file before renaming it; on failure keep appending to it
+                    *current = None;
+                    *current = roll_over(&path).or_else(|_| open_append(&path)).ok();
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut current) = self.file.lock() {
+            if let Some(log_file) = current.as_mut() {
+                let _ = log_file.file.flush();
+            }
+        }
+    }
+}
+
+// Install the logger; called once at startup
+pub fn init() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(DEFAULT_LEVEL);
+    }
+}
+
+// "off", "error", "warn", "info", "debug" or "trace", case-insensitive
+pub fn set_level(level: &str) -> Result<(), String> {
+    let filter: LevelFilter = level
+        .parse()
+        .map_err(|_| format!("Unknown log level: {}", level))?;
+    log::set_max_level(filter);
+    log::info!("Log level set to {}", filter);
+    Ok(())
+}
+
+// Append log lines to `path` as well as the console; None stops file logging
+pub fn set_file(path: Option<PathBuf>) -> Result<(), String> {
+    let log_file = path.as_deref().map(open_append).transpose()?;
+
+    // Release the lock before logging, the logger takes it too
+    *LOGGER.file.lock().map_err(|e| e.to_string())? = log_file;
+    match path {
+        Some(path) => log::info!("Logging to {}", path.display()),
+        None => log::info!("File logging stopped"),
+    }
+    Ok(())
+}
+
+// Default file logging in `dir`, rolled over at MAX_LOG_BYTES
+pub fn start_rolling_file(dir: &Path) -> Result<(), String> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create log directory {}: {}", dir.display(), e))?;
+    set_file(Some(dir.join(LOG_FILE_NAME)))
+}
+
+pub fn current_file() -> Option<PathBuf> {
+    LOGGER
+        .file
+        .lock()
+        .ok()?
+        .as_ref()
+        .map(|log_file| log_file.path.clone())
+}
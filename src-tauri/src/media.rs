@@ -0,0 +1,426 @@
+// Media Foundation helpers (webcam enumeration and capture)
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use windows::core::{GUID, HSTRING, PWSTR};
+use windows::Win32::Foundation::BOOL;
+use windows::Win32::Media::MediaFoundation::{
+    IMFActivate, IMFAttributes, IMFMediaSource, IMFSourceReader, MFCreateAttributes,
+    MFCreateMediaType, MFCreateSourceReaderFromMediaSource, MFCreateSourceReaderFromURL,
+    MFEnumDeviceSources, MFMediaType_Video, MFStartup, MFVideoFormat_RGB32, MFSTARTUP_FULL,
+    MF_DEVSOURCE_ATTRIBUTE_FRIENDLY_NAME, MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE,
+    MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID,
+    MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_SYMBOLIC_LINK, MF_MT_DEFAULT_STRIDE,
+    MF_MT_FRAME_SIZE, MF_MT_MAJOR_TYPE, MF_MT_SUBTYPE, MF_SOURCE_READERF_ENDOFSTREAM,
+    MF_SOURCE_READER_ALL_STREAMS, MF_SOURCE_READER_ENABLE_VIDEO_PROCESSING,
+    MF_SOURCE_READER_FIRST_VIDEO_STREAM, MF_VERSION,
+};
+use windows::Win32::System::Com::{CoInitializeEx, CoTaskMemFree, COINIT_MULTITHREADED};
+
+static WEBCAM_GENERATION: AtomicU64 = AtomicU64::new(0); // Bumped on every start/stop so stale threads exit
+static WEBCAM_FRAME: Mutex<Option<WebcamFrame>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CameraInfo {
+    id: String, // Symbolic link, stable for the lifetime of the device
+    name: String,
+}
+
+// Latest webcam frame, top-down BGRA
+pub struct WebcamFrame {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+// Corner of the screen frame the webcam is drawn into
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PipPosition {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+pub fn init_media_foundation() -> Result<(), String> {
+    unsafe {
+        // COM may already be initialized on this thread with another model, which is fine
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+        MFStartup(MF_VERSION, MFSTARTUP_FULL).map_err(|e| e.to_string())
+    }
+}
+
+pub unsafe fn get_string_attribute(activate: &IMFActivate, key: &GUID) -> Option<String> {
+    let mut value = PWSTR::null();
+    let mut length = 0u32;
+    activate
+        .GetAllocatedString(key, &mut value, &mut length)
+        .ok()?;
+    let result = value.to_string().ok();
+    CoTaskMemFree(Some(value.0 as *const _));
+    result
+}
+
+unsafe fn enumerate_video_devices() -> Result<Vec<IMFActivate>, String> {
+    let mut attributes: Option<IMFAttributes> = None;
+    MFCreateAttributes(&mut attributes, 1).map_err(|e| e.to_string())?;
+    let attributes = attributes.ok_or("Failed to create device attributes")?;
+    attributes
+        .SetGUID(
+            &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE,
+            &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_GUID,
+        )
+        .map_err(|e| e.to_string())?;
+
+    let mut devices: *mut Option<IMFActivate> = std::ptr::null_mut();
+    let mut count = 0u32;
+    MFEnumDeviceSources(&attributes, &mut devices, &mut count).map_err(|e| e.to_string())?;
+
+    let mut result = Vec::new();
+    if !devices.is_null() {
+        for i in 0..count as usize {
+            if let Some(device) = (*devices.add(i)).take() {
+                result.push(device);
+            }
+        }
+        CoTaskMemFree(Some(devices as *const _));
+    }
+    Ok(result)
+}
+
+pub fn list_cameras() -> Result<Vec<CameraInfo>, String> {
+    init_media_foundation()?;
+
+    unsafe {
+        let devices = enumerate_video_devices()?;
+        let cameras = devices
+            .iter()
+            .filter_map(|device| {
+                let id = get_string_attribute(
+                    device,
+                    &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_SYMBOLIC_LINK,
+                )?;
+                let name = get_string_attribute(device, &MF_DEVSOURCE_ATTRIBUTE_FRIENDLY_NAME)
+                    .unwrap_or_else(|| "Unknown camera".to_string());
+                Some(CameraInfo { id, name })
+            })
+            .collect();
+        Ok(cameras)
+    }
+}
+
+// A decoded video frame, top-down BGRA
+pub struct DecodedFrame<'a> {
+    pub timestamp: f64, // Seconds
+    pub width: u32,
+    pub height: u32,
+    pub data: &'a [u8],
+}
+
+// Attributes that let the source reader convert whatever the source produces (usually NV12/MJPG/H.264) to RGB32
+unsafe fn video_processing_attributes() -> Result<IMFAttributes, String> {
+    let mut attributes: Option<IMFAttributes> = None;
+    MFCreateAttributes(&mut attributes, 1).map_err(|e| e.to_string())?;
+    let attributes = attributes.ok_or("Failed to create reader attributes")?;
+    attributes
+        .SetUINT32(&MF_SOURCE_READER_ENABLE_VIDEO_PROCESSING, 1)
+        .map_err(|e| e.to_string())?;
+    Ok(attributes)
+}
+
+// Ask the reader for RGB32 on the first video stream; returns width, height and row stride
+// (negative stride means the rows are bottom-up)
+unsafe fn configure_rgb32_output(reader: &IMFSourceReader) -> Result<(u32, u32, i32), String> {
+    let media_type = MFCreateMediaType().map_err(|e| e.to_string())?;
+    media_type
+        .SetGUID(&MF_MT_MAJOR_TYPE, &MFMediaType_Video)
+        .map_err(|e| e.to_string())?;
+    media_type
+        .SetGUID(&MF_MT_SUBTYPE, &MFVideoFormat_RGB32)
+        .map_err(|e| e.to_string())?;
+    reader
+        .SetCurrentMediaType(
+            MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32,
+            None,
+            &media_type,
+        )
+        .map_err(|e| e.to_string())?;
+
+    let current = reader
+        .GetCurrentMediaType(MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32)
+        .map_err(|e| e.to_string())?;
+    let frame_size = current
+        .GetUINT64(&MF_MT_FRAME_SIZE)
+        .map_err(|e| e.to_string())?;
+    let width = (frame_size >> 32) as u32;
+    let height = (frame_size & 0xFFFF_FFFF) as u32;
+    let stride = current
+        .GetUINT32(&MF_MT_DEFAULT_STRIDE)
+        .map(|stride| stride as i32)
+        .unwrap_or((width * 4) as i32);
+
+    Ok((width, height, stride))
+}
+
+// Decode a video file frame by frame, in order. Stops early when `on_frame` returns false.
+pub fn for_each_video_frame(
+    path: &str,
+    mut on_frame: impl FnMut(DecodedFrame) -> bool,
+) -> Result<(), String> {
+    init_media_foundation()?;
+
+    unsafe {
+        let attributes = video_processing_attributes()?;
+        let reader = MFCreateSourceReaderFromURL(&HSTRING::from(path), &attributes)
+            .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+
+        // Only decode video
+        reader
+            .SetStreamSelection(MF_SOURCE_READER_ALL_STREAMS.0 as u32, BOOL::from(false))
+            .map_err(|e| e.to_string())?;
+        reader
+            .SetStreamSelection(
+                MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32,
+                BOOL::from(true),
+            )
+            .map_err(|e| e.to_string())?;
+
+        let (width, height, stride) = configure_rgb32_output(&reader)?;
+        let row_bytes = (width * 4) as usize;
+        let mut frame = vec![0u8; row_bytes * height as usize];
+
+        loop {
+            let mut flags = 0u32;
+            let mut timestamp = 0i64;
+            let mut sample = None;
+            reader
+                .ReadSample(
+                    MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32,
+                    0,
+                    None,
+                    Some(&mut flags as *mut u32),
+                    Some(&mut timestamp as *mut i64),
+                    Some(&mut sample as *mut _),
+                )
+                .map_err(|e| e.to_string())?;
+
+            if flags & MF_SOURCE_READERF_ENDOFSTREAM.0 as u32 != 0 {
+                break;
+            }
+            let Some(sample) = sample else {
+                continue;
+            };
+
+            let buffer = sample
+                .ConvertToContiguousBuffer()
+                .map_err(|e| e.to_string())?;
+            let mut data: *mut u8 = std::ptr::null_mut();
+            let mut length = 0u32;
+            buffer
+                .Lock(&mut data, None, Some(&mut length as *mut u32))
+                .map_err(|e| e.to_string())?;
+
+            let src_stride = stride.unsigned_abs() as usize;
+            let copied = !data.is_null() && length as usize >= src_stride * height as usize;
+            if copied {
+                let src = std::slice::from_raw_parts(data, length as usize);
+                for row in 0..height as usize {
+                    let src_row = if stride < 0 {
+                        height as usize - 1 - row
+                    } else {
+                        row
+                    };
+                    frame[row * row_bytes..(row + 1) * row_bytes].copy_from_slice(
+                        &src[src_row * src_stride..src_row * src_stride + row_bytes],
+                    );
+                }
+            }
+            let _ = buffer.Unlock();
+
+            if copied
+                && !on_frame(DecodedFrame {
+                    timestamp: timestamp as f64 / 10_000_000.0,
+                    width,
+                    height,
+                    data: &frame,
+                })
+            {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Create a source reader for the given camera that outputs RGB32 (BGRA) frames
+unsafe fn create_webcam_reader(device_id: &str) -> Result<(IMFSourceReader, u32, u32), String> {
+    let device = enumerate_video_devices()?
+        .into_iter()
+        .find(|device| {
+            get_string_attribute(
+                device,
+                &MF_DEVSOURCE_ATTRIBUTE_SOURCE_TYPE_VIDCAP_SYMBOLIC_LINK,
+            )
+            .as_deref()
+                == Some(device_id)
+        })
+        .ok_or_else(|| format!("Camera not found: {}", device_id))?;
+
+    let source: IMFMediaSource = device.ActivateObject().map_err(|e| e.to_string())?;
+
+    let attributes = video_processing_attributes()?;
+    let reader =
+        MFCreateSourceReaderFromMediaSource(&source, &attributes).map_err(|e| e.to_string())?;
+    let (width, height, _) = configure_rgb32_output(&reader)?;
+
+    Ok((reader, width, height))
+}
+
+// Start reading frames from the camera on a background thread.
+// Only the most recent frame is kept; the capture handler composites it into each screen frame.
+pub fn start_webcam(device_id: String) -> Result<(), String> {
+    let generation = WEBCAM_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+    thread::spawn(move || {
+        if let Err(e) = init_media_foundation() {
+            let _ = ready_tx.send(Err(e));
+            return;
+        }
+
+        // COM objects stay on this thread
+        let (reader, width, height) = match unsafe { create_webcam_reader(&device_id) } {
+            Ok(reader) => reader,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+
+        info!("Webcam capture started: {}x{}", width, height);
+        let _ = ready_tx.send(Ok(()));
+
+        while WEBCAM_GENERATION.load(Ordering::SeqCst) == generation {
+            let mut flags = 0u32;
+            let mut sample = None;
+            let result = unsafe {
+                reader.ReadSample(
+                    MF_SOURCE_READER_FIRST_VIDEO_STREAM.0 as u32,
+                    0,
+                    None,
+                    Some(&mut flags as *mut u32),
+                    None,
+                    Some(&mut sample as *mut _),
+                )
+            };
+
+            if let Err(e) = result {
+                warn!("Webcam read failed: {}", e);
+                break;
+            }
+
+            let Some(sample) = sample else {
+                continue;
+            };
+
+            unsafe {
+                let Ok(buffer) = sample.ConvertToContiguousBuffer() else {
+                    continue;
+                };
+                let mut data: *mut u8 = std::ptr::null_mut();
+                let mut length = 0u32;
+                if buffer
+                    .Lock(&mut data, None, Some(&mut length as *mut u32))
+                    .is_ok()
+                {
+                    let expected = (width * height * 4) as usize;
+                    if !data.is_null() && length as usize >= expected {
+                        let pixels = std::slice::from_raw_parts(data, expected).to_vec();
+                        if let Ok(mut frame) = WEBCAM_FRAME.lock() {
+                            // Don't resurrect a frame after stop_webcam cleared it
+                            if WEBCAM_GENERATION.load(Ordering::SeqCst) == generation {
+                                *frame = Some(WebcamFrame {
+                                    width,
+                                    height,
+                                    data: pixels,
+                                });
+                            }
+                        }
+                    }
+                    let _ = buffer.Unlock();
+                }
+            }
+        }
+
+        if WEBCAM_GENERATION.load(Ordering::SeqCst) == generation {
+            if let Ok(mut frame) = WEBCAM_FRAME.lock() {
+                *frame = None;
+            }
+        }
+        info!("Webcam capture stopped");
+    });
+
+    ready_rx
+        .recv()
+        .unwrap_or_else(|_| Err("Webcam thread exited unexpectedly".to_string()))
+}
+
+pub fn stop_webcam() {
+    WEBCAM_GENERATION.fetch_add(1, Ordering::SeqCst);
+    if let Ok(mut frame) = WEBCAM_FRAME.lock() {
+        *frame = None;
+    }
+}
+
+// Draw the latest webcam frame into a top-down BGRA screen buffer.
+// `size` is the PiP width as a fraction of the screen width.
+pub fn composite_webcam(
+    pixels: &mut [u8],
+    width: u32,
+    height: u32,
+    position: PipPosition,
+    size: f32,
+) {
+    let Ok(frame) = WEBCAM_FRAME.lock() else {
+        return;
+    };
+    let Some(webcam) = frame.as_ref() else {
+        return;
+    };
+
+    let pip_width = ((width as f32 * size.clamp(0.05, 1.0)) as u32).max(1);
+    let pip_height =
+        ((pip_width as u64 * webcam.height as u64) / webcam.width.max(1) as u64) as u32;
+    let margin = width / 50;
+
+    let x = match position {
+        PipPosition::TopLeft | PipPosition::BottomLeft => margin,
+        PipPosition::TopRight | PipPosition::BottomRight => {
+            width.saturating_sub(pip_width + margin)
+        }
+    };
+    let y = match position {
+        PipPosition::TopLeft | PipPosition::TopRight => margin,
+        PipPosition::BottomLeft | PipPosition::BottomRight => {
+            height.saturating_sub(pip_height + margin)
+        }
+    };
+
+    crate::blit_scaled_bgra(
+        pixels,
+        width,
+        height,
+        &webcam.data,
+        webcam.width,
+        webcam.height,
+        x,
+        y,
+        pip_width,
+        pip_height,
+    );
+}
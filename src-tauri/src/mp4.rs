@@ -0,0 +1,397 @@
+// Minimal MP4 (ISO BMFF) box parsing: enough to tell a finalized file from a broken one and to
+// report what the encoder produced
+use memmap2::Mmap;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+
+// A box: four-character type, offset of its header and total size including the header
+#[derive(Debug, Clone, Copy)]
+pub struct BoxHeader {
+    pub kind: [u8; 4],
+    pub offset: u64,
+    pub size: u64,
+    pub header_size: u64,
+}
+
+// Read the box header at `offset`. Size 1 means a 64-bit size follows, size 0 means "to the end"
+// of the enclosing range.
+pub fn read_box_header(data: &[u8], offset: u64, end: u64) -> Result<BoxHeader, String> {
+    let start = offset as usize;
+    let header = data
+        .get(start..start + 8)
+        .ok_or_else(|| format!("Truncated box header at offset {}", offset))?;
+    let size32 = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    let kind = [header[4], header[5], header[6], header[7]];
+
+    let (size, header_size) = match size32 {
+        0 => (end - offset, 8),
+        1 => {
+            let large = data
+                .get(start + 8..start + 16)
+                .ok_or_else(|| format!("Truncated 64-bit box size at offset {}", offset))?;
+            (u64::from_be_bytes(large.try_into().unwrap()), 16)
+        }
+        size => (size, 8),
+    };
+
+    if size < header_size {
+        return Err(format!(
+            "Invalid size {} for '{}' box at offset {}",
+            size,
+            String::from_utf8_lossy(&kind),
+            offset
+        ));
+    }
+    Ok(BoxHeader {
+        kind,
+        offset,
+        size,
+        header_size,
+    })
+}
+
+// Walk the boxes in [start, end), failing if one runs past the end
+pub fn boxes_in(data: &[u8], start: u64, end: u64) -> Result<Vec<BoxHeader>, String> {
+    let mut boxes = Vec::new();
+    let mut offset = start;
+    while offset < end {
+        let header = read_box_header(data, offset, end)?;
+        // Compared against what's left rather than offset + size, which a 64-bit size can overflow
+        if header.size > end - offset {
+            return Err(format!(
+                "'{}' box at offset {} is truncated ({} bytes declared, {} available)",
+                String::from_utf8_lossy(&header.kind),
+                offset,
+                header.size,
+                end - offset
+            ));
+        }
+        boxes.push(header);
+        offset += header.size;
+    }
+    Ok(boxes)
+}
+
+pub fn top_level_boxes(data: &[u8]) -> Result<Vec<BoxHeader>, String> {
+    boxes_in(data, 0, data.len() as u64)
+}
+
+impl BoxHeader {
+    pub fn end(&self) -> u64 {
+        self.offset + self.size
+    }
+
+    pub fn payload<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[(self.offset + self.header_size) as usize..self.end() as usize]
+    }
+
+    pub fn children(&self, data: &[u8]) -> Result<Vec<BoxHeader>, String> {
+        boxes_in(data, self.offset + self.header_size, self.end())
+    }
+
+    pub fn child(&self, data: &[u8], kind: &[u8; 4]) -> Option<BoxHeader> {
+        self.children(data)
+            .ok()?
+            .into_iter()
+            .find(|b| &b.kind == kind)
+    }
+
+    // Follow a path of nested box types, e.g. [b"mdia", b"minf", b"stbl"]
+    pub fn descend(&self, data: &[u8], path: &[&[u8; 4]]) -> Option<BoxHeader> {
+        path.iter()
+            .try_fold(*self, |parent, kind| parent.child(data, kind))
+    }
+}
+
+// A playable MP4 needs an ftyp up front and a moov with the sample tables. The moov is written
+// last by the encoder, so it's missing when finalization timed out.
+pub fn validate(data: &[u8]) -> Result<(), String> {
+    let boxes = top_level_boxes(data)?;
+    if boxes.first().map(|b| &b.kind) != Some(b"ftyp") {
+        return Err("File does not start with an 'ftyp' box".to_string());
+    }
+    if !boxes.iter().any(|b| &b.kind == b"moov") {
+        return Err("No 'moov' box, the recording was not finalized".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_file(path: &str) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mmap = unsafe { Mmap::map(&file) }.map_err(|e| e.to_string())?;
+    validate(&mmap)
+}
+
+fn be_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+// (timescale, duration) from an mvhd or mdhd payload, which share the same prefix
+fn timescale_and_duration(payload: &[u8]) -> Option<(u32, u64)> {
+    if payload.first()? == &1 {
+        Some((be_u32(payload, 20)?, be_u64(payload, 24)?))
+    } else {
+        Some((be_u32(payload, 12)?, be_u32(payload, 16)? as u64))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VideoProbe {
+    pub codec: String,        // Sample entry type, e.g. avc1 or hvc1
+    pub codec_string: String, // RFC 6381 string usable with MediaSource.isTypeSupported
+    pub profile: Option<String>,
+    pub level: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub fps: f64,
+    pub bitrate: u64, // Average over the whole file, bits per second
+    pub has_audio: bool,
+    pub duration: f64,
+}
+
+fn avc_profile_name(profile: u8) -> &'static str {
+    match profile {
+        66 => "Baseline",
+        77 => "Main",
+        88 => "Extended",
+        100 => "High",
+        110 => "High 10",
+        122 => "High 4:2:2",
+        244 => "High 4:4:4",
+        _ => "Unknown",
+    }
+}
+
+fn hevc_profile_name(profile: u8) -> &'static str {
+    match profile {
+        1 => "Main",
+        2 => "Main 10",
+        3 => "Main Still Picture",
+        4 => "Range Extensions",
+        _ => "Unknown",
+    }
+}
+
+// Fill codec details from the first stsd sample entry of a video track
+fn probe_sample_entry(data: &[u8], stsd: &BoxHeader, probe: &mut VideoProbe) -> Option<()> {
+    // Full box header (4) + entry count (4), then the sample entries, which must fit in the stsd
+    let entries_start = stsd.offset + stsd.header_size + 8;
+    let entry = *boxes_in(data, entries_start, stsd.end()).ok()?.first()?;
+    let payload = entry.payload(data);
+    probe.codec = String::from_utf8_lossy(&entry.kind).to_string();
+    probe.codec_string = probe.codec.clone();
+
+    // VisualSampleEntry: width/height at 24/26, child boxes after the 78-byte fixed part
+    probe.width = be_u16(payload, 24)? as u32;
+    probe.height = be_u16(payload, 26)? as u32;
+    let children_start = entry.offset + entry.header_size + 78;
+    let children = boxes_in(data, children_start, entry.end()).ok()?;
+
+    if let Some(avcc) = children.iter().find(|b| &b.kind == b"avcC") {
+        let config = avcc.payload(data);
+        let (profile, compat, level) = (*config.get(1)?, *config.get(2)?, *config.get(3)?);
+        probe.profile = Some(avc_profile_name(profile).to_string());
+        probe.level = Some(format!("{}.{}", level / 10, level % 10));
+        probe.codec_string = format!("{}.{:02x}{:02x}{:02x}", probe.codec, profile, compat, level);
+    } else if let Some(hvcc) = children.iter().find(|b| &b.kind == b"hvcC") {
+        let config = hvcc.payload(data);
+        let profile = config.get(1)? & 0x1f;
+        let level = *config.get(12)?;
+        probe.profile = Some(hevc_profile_name(profile).to_string());
+        // HEVC level is 30x the level number
+        probe.level = Some(format!("{}", level as f64 / 30.0));
+        probe.codec_string = format!("{}.{}.L{}", probe.codec, profile, level);
+    }
+    Some(())
+}
+
+pub fn probe(data: &[u8]) -> Result<VideoProbe, String> {
+    let boxes = top_level_boxes(data)?;
+    let moov = boxes
+        .iter()
+        .find(|b| &b.kind == b"moov")
+        .ok_or("No 'moov' box, the recording was not finalized")?;
+
+    let mut probe = VideoProbe::default();
+    if let Some((timescale, duration)) = moov
+        .child(data, b"mvhd")
+        .and_then(|mvhd| timescale_and_duration(mvhd.payload(data)))
+    {
+        if timescale > 0 {
+            probe.duration = duration as f64 / timescale as f64;
+        }
+    }
+
+    let mut found_video = false;
+    for trak in moov.children(data)?.iter().filter(|b| &b.kind == b"trak") {
+        let Some(mdia) = trak.child(data, b"mdia") else {
+            continue;
+        };
+        // Handler type follows version/flags and pre_defined
+        let handler = mdia
+            .child(data, b"hdlr")
+            .and_then(|hdlr| hdlr.payload(data).get(8..12).map(|h| h.to_vec()));
+        match handler.as_deref() {
+            Some(b"soun") => probe.has_audio = true,
+            Some(b"vide") if !found_video => {
+                found_video = true;
+                if let Some(stsd) = mdia.descend(data, &[b"minf", b"stbl", b"stsd"]) {
+                    probe_sample_entry(data, &stsd, &mut probe);
+                }
+
+                // Average frame rate from the sample count over the media duration
+                let media_time = mdia
+                    .child(data, b"mdhd")
+                    .and_then(|mdhd| timescale_and_duration(mdhd.payload(data)));
+                let sample_count: u64 = mdia
+                    .descend(data, &[b"minf", b"stbl", b"stts"])
+                    .map(|stts| {
+                        let payload = stts.payload(data);
+                        let entries = be_u32(payload, 4).unwrap_or(0) as usize;
+                        (0..entries)
+                            .filter_map(|i| be_u32(payload, 8 + i * 8))
+                            .map(|count| count as u64)
+                            .sum()
+                    })
+                    .unwrap_or(0);
+                if let Some((timescale, duration)) = media_time {
+                    if timescale > 0 && duration > 0 {
+                        probe.fps = sample_count as f64 * timescale as f64 / duration as f64;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if !found_video {
+        return Err("No video track found".to_string());
+    }
+    if probe.duration > 0.0 {
+        probe.bitrate = (data.len() as f64 * 8.0 / probe.duration) as u64;
+    }
+    Ok(probe)
+}
+
+pub fn probe_file(path: &str) -> Result<VideoProbe, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mmap = unsafe { Mmap::map(&file) }.map_err(|e| e.to_string())?;
+    probe(&mmap)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut bytes = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn large_size_near_u64_max_is_rejected() {
+        let mut data = mp4_box(b"ftyp", b"isom");
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"mdat");
+        data.extend_from_slice(&(u64::MAX - 4).to_be_bytes());
+        assert!(top_level_boxes(&data).is_err());
+    }
+
+    // Full boxes start with version and flags
+    fn full_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        mp4_box(kind, &[&[0u8; 4], payload].concat())
+    }
+
+    // Version 0 mvhd/mdhd prefix: creation and modification times, then timescale and duration
+    fn header_box(kind: &[u8; 4], timescale: u32, duration: u32) -> Vec<u8> {
+        let mut payload = vec![0u8; 8];
+        payload.extend_from_slice(&timescale.to_be_bytes());
+        payload.extend_from_slice(&duration.to_be_bytes());
+        full_box(kind, &payload)
+    }
+
+    // ftyp + moov with one 1920x1080 H.264 High 4.0 track of 60 frames over one second. The
+    // sample entry comes from `entry` so tests can break it.
+    fn movie(entry: Vec<u8>) -> Vec<u8> {
+        let mut stsd = 1u32.to_be_bytes().to_vec();
+        stsd.extend(entry);
+        let mut stts = 1u32.to_be_bytes().to_vec();
+        stts.extend_from_slice(&60u32.to_be_bytes());
+        stts.extend_from_slice(&10u32.to_be_bytes());
+        let stbl = mp4_box(
+            b"stbl",
+            &[full_box(b"stsd", &stsd), full_box(b"stts", &stts)].concat(),
+        );
+        let hdlr = full_box(b"hdlr", &[&[0u8; 4], b"vide".as_slice()].concat());
+        let mdia = mp4_box(
+            b"mdia",
+            &[header_box(b"mdhd", 600, 600), hdlr, mp4_box(b"minf", &stbl)].concat(),
+        );
+        let moov = mp4_box(
+            b"moov",
+            &[header_box(b"mvhd", 1000, 1000), mp4_box(b"trak", &mdia)].concat(),
+        );
+        [mp4_box(b"ftyp", b"isom"), moov].concat()
+    }
+
+    fn avc1_entry() -> Vec<u8> {
+        let mut visual = vec![0u8; 78];
+        visual[24..26].copy_from_slice(&1920u16.to_be_bytes());
+        visual[26..28].copy_from_slice(&1080u16.to_be_bytes());
+        visual.extend(mp4_box(b"avcC", &[1, 100, 0, 40]));
+        mp4_box(b"avc1", &visual)
+    }
+
+    #[test]
+    fn validate_needs_ftyp_and_moov() {
+        let ftyp = mp4_box(b"ftyp", b"isom");
+        let moov = mp4_box(b"moov", &[]);
+        assert!(validate(&[ftyp.clone(), moov.clone()].concat()).is_ok());
+        assert!(validate(&[ftyp.clone(), mp4_box(b"mdat", &[0; 16])].concat()).is_err());
+        assert!(validate(&[moov.clone(), ftyp.clone()].concat()).is_err());
+        assert!(validate(&[]).is_err());
+
+        // A box declaring more than the file holds, as when finalization was cut short
+        let mut truncated = [ftyp, moov].concat();
+        truncated[15] = 0xff;
+        assert!(validate(&truncated).is_err());
+        assert!(validate(&truncated[..6]).is_err());
+    }
+
+    #[test]
+    fn probe_reads_the_video_track() {
+        let data = movie(avc1_entry());
+        let probe = probe(&data).unwrap();
+        assert_eq!(probe.codec, "avc1");
+        assert_eq!(probe.codec_string, "avc1.640028");
+        assert_eq!(probe.profile.as_deref(), Some("High"));
+        assert_eq!(probe.level.as_deref(), Some("4.0"));
+        assert_eq!((probe.width, probe.height), (1920, 1080));
+        assert_eq!(probe.fps, 60.0);
+        assert_eq!(probe.duration, 1.0);
+        assert_eq!(probe.bitrate, data.len() as u64 * 8);
+        assert!(!probe.has_audio);
+    }
+
+    #[test]
+    fn probe_survives_an_oversized_sample_entry() {
+        let mut entry = avc1_entry();
+        entry[..4].copy_from_slice(&0x7fff_ffffu32.to_be_bytes());
+        let probe = probe(&movie(entry)).unwrap();
+        assert_eq!(probe.codec, "");
+        assert_eq!((probe.width, probe.height), (0, 0));
+        assert_eq!(probe.fps, 60.0);
+
+        assert!(super::probe(&mp4_box(b"ftyp", b"isom")).is_err());
+    }
+}
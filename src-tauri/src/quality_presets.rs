@@ -0,0 +1,134 @@
+// User-defined recording presets (e.g. "YouTube 1080p60"), picked by name through start_recording's
+// quality argument and kept in the app config directory between runs
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const PRESETS_FILE_NAME: &str = "quality_presets.json";
+const BUILT_IN_NAMES: [&str; 3] = ["high", "medium", "low"];
+const MAX_DIMENSION: u32 = 8192; // Past what the hardware encoders accept in either direction
+
+static PRESETS: Mutex<Vec<QualityPreset>> = Mutex::new(Vec::new());
+static PRESETS_FILE: Mutex<Option<PathBuf>> = Mutex::new(None); // Set by load at startup
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QualityPreset {
+    pub name: String,
+    pub bitrate: u32, // bps
+    pub fps: u32,
+    // Output size, scaled (letterboxed) from the monitor's. Both unset records at monitor size.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl QualityPreset {
+    pub fn output_size(&self) -> Option<(u32, u32)> {
+        self.width.zip(self.height)
+    }
+}
+
+fn validate(presets: &[QualityPreset], max_fps: u32) -> Result<(), String> {
+    let mut names = HashSet::new();
+    for preset in presets {
+        let name = preset.name.trim();
+        if name.is_empty() {
+            return Err("Preset names can't be empty".to_string());
+        }
+        if BUILT_IN_NAMES.contains(&name) {
+            return Err(format!("\"{}\" is a built-in quality", name));
+        }
+        if !names.insert(name) {
+            return Err(format!("\"{}\" is listed twice", name));
+        }
+        if preset.bitrate == 0 {
+            return Err(format!("\"{}\": bitrate must be above 0", name));
+        }
+        if preset.fps == 0 || preset.fps > max_fps {
+            return Err(format!(
+                "\"{}\": frame rate must be between 1 and {} fps, got {}",
+                name, max_fps, preset.fps
+            ));
+        }
+        if preset.width.is_some() != preset.height.is_some() {
+            return Err(format!(
+                "\"{}\": set both width and height, or neither",
+                name
+            ));
+        }
+        if let Some((width, height)) = preset.output_size() {
+            // The encoders need even dimensions for 4:2:0 chroma
+            let valid = |size: u32| size > 0 && size % 2 == 0 && size <= MAX_DIMENSION;
+            if !valid(width) || !valid(height) {
+                return Err(format!(
+                    "\"{}\": {}x{} must be even and between 2 and {}",
+                    name, width, height, MAX_DIMENSION
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Read the saved presets from `dir`. A missing file means none are defined; an unreadable one is
+// logged and ignored so it can't block startup.
+pub fn load(dir: &Path) {
+    let path = dir.join(PRESETS_FILE_NAME);
+    if let Ok(mut file) = PRESETS_FILE.lock() {
+        *file = Some(path.clone());
+    }
+    let Ok(json) = fs::read_to_string(&path) else {
+        return;
+    };
+    match serde_json::from_str::<Vec<QualityPreset>>(&json) {
+        Ok(presets) => {
+            info!("Loaded {} quality presets", presets.len());
+            if let Ok(mut stored) = PRESETS.lock() {
+                *stored = presets;
+            }
+        }
+        Err(e) => warn!("Ignoring invalid {}: {}", path.display(), e),
+    }
+}
+
+// Validate, save and replace all custom presets
+pub fn set(mut presets: Vec<QualityPreset>, max_fps: u32) -> Result<(), String> {
+    validate(&presets, max_fps)?;
+    for preset in &mut presets {
+        preset.name = preset.name.trim().to_string();
+    }
+
+    let path = PRESETS_FILE
+        .lock()
+        .map_err(|e| e.to_string())?
+        .clone()
+        .ok_or("No config directory available to save presets in")?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let json = serde_json::to_string_pretty(&presets).map_err(|e| e.to_string())?;
+    fs::write(&path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+
+    info!("Saved {} quality presets", presets.len());
+    *PRESETS.lock().map_err(|e| e.to_string())? = presets;
+    Ok(())
+}
+
+pub fn all() -> Vec<QualityPreset> {
+    PRESETS
+        .lock()
+        .map(|presets| presets.clone())
+        .unwrap_or_default()
+}
+
+pub fn find(name: &str) -> Option<QualityPreset> {
+    PRESETS
+        .lock()
+        .ok()?
+        .iter()
+        .find(|preset| preset.name == name)
+        .cloned()
+}
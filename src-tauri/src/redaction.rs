@@ -0,0 +1,119 @@
+// Regions hidden in every recorded frame (RecordingOptions.redaction_regions), e.g. where an API
+// token or an inbox sits. They're burned in before encoding, so unlike an overlay in the editor
+// nothing underneath can be recovered from the file. set_redaction_regions changes them live.
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+const BLOCK: u32 = 16; // Blur block size in pixels
+const FILL: [u8; 4] = [0, 0, 0, 255]; // BGRA
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RedactionMode {
+    #[default]
+    Fill, // Solid black
+    // Each BLOCK x BLOCK block replaced by its average: a box blur that leaves layout and colour
+    // visible but nothing as fine as text
+    Blur,
+}
+
+// In pixels of the recorded frame, i.e. after any taskbar crop, from its top-left corner
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+static REGIONS: Mutex<(Vec<Rect>, RedactionMode)> = Mutex::new((Vec::new(), RedactionMode::Fill));
+static ACTIVE: AtomicBool = AtomicBool::new(false); // REGIONS is non-empty; checked on every frame
+
+// Replace the regions. Empty ones are dropped up front so frames don't have to skip them.
+pub fn set(regions: &[Rect], mode: RedactionMode) {
+    let regions: Vec<Rect> = regions
+        .iter()
+        .copied()
+        .filter(|region| region.width > 0 && region.height > 0)
+        .collect();
+    if let Ok(mut current) = REGIONS.lock() {
+        ACTIVE.store(!regions.is_empty(), Ordering::SeqCst);
+        *current = (regions, mode);
+    }
+}
+
+pub fn clear() {
+    set(&[], RedactionMode::Fill);
+}
+
+// Whether frames need to come to the CPU to be redacted
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+// Redact a top-down BGRA frame in place. Regions reaching past the frame are clipped to it.
+pub fn apply(pixels: &mut [u8], width: u32, height: u32) {
+    let Ok(current) = REGIONS.lock() else {
+        return;
+    };
+    let (regions, mode) = &*current;
+    for region in regions {
+        let left = region.x.min(width);
+        let top = region.y.min(height);
+        let right = region.x.saturating_add(region.width).min(width);
+        let bottom = region.y.saturating_add(region.height).min(height);
+        if left >= right || top >= bottom {
+            continue;
+        }
+        match mode {
+            RedactionMode::Fill => {
+                for row in top..bottom {
+                    let start = ((row * width + left) * 4) as usize;
+                    let end = ((row * width + right) * 4) as usize;
+                    for pixel in pixels[start..end].chunks_exact_mut(4) {
+                        pixel.copy_from_slice(&FILL);
+                    }
+                }
+            }
+            RedactionMode::Blur => {
+                for block_top in (top..bottom).step_by(BLOCK as usize) {
+                    let block_bottom = (block_top + BLOCK).min(bottom);
+                    for block_left in (left..right).step_by(BLOCK as usize) {
+                        let block_right = (block_left + BLOCK).min(right);
+                        average_block(
+                            pixels,
+                            width,
+                            block_left,
+                            block_top,
+                            block_right,
+                            block_bottom,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn average_block(pixels: &mut [u8], width: u32, left: u32, top: u32, right: u32, bottom: u32) {
+    let mut sum = [0u32; 3];
+    for row in top..bottom {
+        let start = ((row * width + left) * 4) as usize;
+        let end = ((row * width + right) * 4) as usize;
+        for pixel in pixels[start..end].chunks_exact(4) {
+            for (total, channel) in sum.iter_mut().zip(pixel) {
+                *total += *channel as u32;
+            }
+        }
+    }
+    let count = (right - left) * (bottom - top);
+    let average = sum.map(|total| (total / count) as u8);
+    for row in top..bottom {
+        let start = ((row * width + left) * 4) as usize;
+        let end = ((row * width + right) * 4) as usize;
+        for pixel in pixels[start..end].chunks_exact_mut(4) {
+            pixel[..3].copy_from_slice(&average);
+        }
+    }
+}
@@ -0,0 +1,251 @@
+// Instant replay: keep the last N seconds of the screen in memory and write them out on demand.
+// Frames are held as JPEGs so a 30s buffer of a 1080p screen stays in the low hundreds of MB.
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use windows::Win32::Graphics::Imaging::{
+    CLSID_WICImagingFactory, GUID_WICPixelFormat32bppBGRA, IWICImagingFactory,
+    WICConvertBitmapSource, WICDecodeMetadataCacheOnDemand,
+};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED,
+};
+use windows_capture::{
+    capture::{CaptureControl, Context, GraphicsCaptureApiHandler},
+    encoder::{
+        AudioSettingsBuilder, ContainerSettingsBuilder, ImageEncoder, VideoEncoder,
+        VideoSettingsBuilder,
+    },
+    frame::{Frame, ImageFormat},
+    graphics_capture_api::InternalCaptureControl,
+    monitor::Monitor,
+    settings::{ColorFormat, CursorCaptureSettings, DrawBorderSettings, Settings},
+};
+
+pub const MAX_SECONDS: u32 = 300;
+pub const DEFAULT_MAX_MEMORY_MB: u32 = 512;
+
+type CaptureError = Box<dyn std::error::Error + Send + Sync>;
+
+static REPLAY: Mutex<
+    Option<(
+        CaptureControl<ReplayHandler, CaptureError>,
+        Arc<Mutex<ReplayBuffer>>,
+    )>,
+> = Mutex::new(None);
+
+struct ReplayFrame {
+    pts: i64, // Capture timespan, 100ns units
+    jpeg: Arc<Vec<u8>>,
+}
+
+// Bounded by both duration and memory; the oldest frames go first
+struct ReplayBuffer {
+    frames: VecDeque<ReplayFrame>,
+    bytes: usize,
+    width: u32,
+    height: u32,
+    max_hns: i64,
+    max_bytes: usize,
+}
+
+impl ReplayBuffer {
+    fn push(&mut self, frame: ReplayFrame) {
+        self.bytes += frame.jpeg.len();
+        self.frames.push_back(frame);
+
+        let newest = self.frames.back().map_or(0, |f| f.pts);
+        while self.frames.len() > 1 {
+            let oldest = &self.frames[0];
+            if newest - oldest.pts <= self.max_hns && self.bytes <= self.max_bytes {
+                break;
+            }
+            self.bytes -= oldest.jpeg.len();
+            self.frames.pop_front();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedReplay {
+    pub frame_count: u32,
+    pub duration: f64,
+}
+
+struct ReplayHandler {
+    buffer: Arc<Mutex<ReplayBuffer>>,
+    last_pts: Option<i64>,
+}
+
+impl GraphicsCaptureApiHandler for ReplayHandler {
+    type Flags = Arc<Mutex<ReplayBuffer>>;
+    type Error = CaptureError;
+
+    fn new(ctx: Context<Self::Flags>) -> Result<Self, Self::Error> {
+        Ok(Self {
+            buffer: ctx.flags,
+            last_pts: None,
+        })
+    }
+
+    fn on_frame_arrived(
+        &mut self,
+        frame: &mut Frame,
+        _capture_control: InternalCaptureControl,
+    ) -> Result<(), Self::Error> {
+        // Keep at most TARGET_FPS frames per second
+        let pts = frame.timespan().Duration;
+        let step = crate::HNS_PER_SEC / crate::TARGET_FPS as i64;
+        if self.last_pts.is_some_and(|last| pts - last < step) {
+            return Ok(());
+        }
+        self.last_pts = Some(pts);
+
+        let mut buffer = frame.buffer()?;
+        let (width, height) = (buffer.width(), buffer.height());
+        let pixels = buffer.as_nopadding_buffer()?;
+        let jpeg = ImageEncoder::new(ImageFormat::Jpeg, ColorFormat::Bgra8)
+            .encode(pixels, width, height)?;
+
+        if let Ok(mut replay) = self.buffer.lock() {
+            replay.width = width;
+            replay.height = height;
+            replay.push(ReplayFrame {
+                pts,
+                jpeg: Arc::new(jpeg),
+            });
+        }
+        Ok(())
+    }
+}
+
+pub fn is_active() -> bool {
+    REPLAY
+        .lock()
+        .map(|replay| replay.is_some())
+        .unwrap_or(false)
+}
+
+pub fn start(monitor: Monitor, seconds: u32, max_memory_mb: u32) -> Result<(), String> {
+    stop();
+
+    let buffer = Arc::new(Mutex::new(ReplayBuffer {
+        frames: VecDeque::new(),
+        bytes: 0,
+        width: 0,
+        height: 0,
+        max_hns: seconds as i64 * crate::HNS_PER_SEC,
+        max_bytes: max_memory_mb as usize * 1024 * 1024,
+    }));
+    let settings = Settings::new(
+        monitor,
+        CursorCaptureSettings::WithCursor, // There is no mouse track to draw the cursor from later
+        DrawBorderSettings::WithoutBorder,
+        ColorFormat::Bgra8,
+        buffer.clone(),
+    );
+    let control = ReplayHandler::start_free_threaded(settings).map_err(|e| e.to_string())?;
+    info!(
+        "Replay buffering started: last {}s, up to {} MB",
+        seconds, max_memory_mb
+    );
+
+    if let Ok(mut replay) = REPLAY.lock() {
+        *replay = Some((control, buffer));
+    }
+    Ok(())
+}
+
+pub fn stop() {
+    let replay = REPLAY.lock().ok().and_then(|mut replay| replay.take());
+    if let Some((control, _)) = replay {
+        if let Err(e) = control.stop() {
+            warn!("Failed to stop replay capture: {}", e);
+        }
+        info!("Replay buffering stopped");
+    }
+}
+
+unsafe fn decode_jpeg(factory: &IWICImagingFactory, jpeg: &[u8]) -> Result<Vec<u8>, String> {
+    let stream = factory.CreateStream().map_err(|e| e.to_string())?;
+    stream
+        .InitializeFromMemory(jpeg)
+        .map_err(|e| e.to_string())?;
+    let decoder = factory
+        .CreateDecoderFromStream(&stream, std::ptr::null(), WICDecodeMetadataCacheOnDemand)
+        .map_err(|e| e.to_string())?;
+    let frame = decoder.GetFrame(0).map_err(|e| e.to_string())?;
+    let source =
+        WICConvertBitmapSource(&GUID_WICPixelFormat32bppBGRA, &frame).map_err(|e| e.to_string())?;
+
+    let mut width = 0u32;
+    let mut height = 0u32;
+    source
+        .GetSize(&mut width, &mut height)
+        .map_err(|e| e.to_string())?;
+    let mut data = vec![0u8; (width * height * 4) as usize];
+    source
+        .CopyPixels(std::ptr::null(), width * 4, &mut data)
+        .map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+// Encode the current buffer contents to an MP4 at `path`. Buffering keeps running.
+pub fn save(path: &str) -> Result<SavedReplay, String> {
+    // Snapshot under the lock; the JPEGs are shared, so this doesn't copy frame data
+    let (frames, width, height) = {
+        let replay = REPLAY.lock().map_err(|e| e.to_string())?;
+        let (_, buffer) = replay.as_ref().ok_or("Replay buffering is not running")?;
+        let buffer = buffer.lock().map_err(|e| e.to_string())?;
+        let frames: Vec<(i64, Arc<Vec<u8>>)> = buffer
+            .frames
+            .iter()
+            .map(|frame| (frame.pts, frame.jpeg.clone()))
+            .collect();
+        (frames, buffer.width, buffer.height)
+    };
+    if frames.is_empty() {
+        return Err("The replay buffer is empty".to_string());
+    }
+    info!("Saving {} buffered frames to {}", frames.len(), path);
+
+    let mut encoder = VideoEncoder::new(
+        VideoSettingsBuilder::new(width, height)
+            .frame_rate(crate::TARGET_FPS)
+            .bitrate(10_000_000),
+        AudioSettingsBuilder::default().disabled(true),
+        ContainerSettingsBuilder::default(),
+        path,
+    )
+    .map_err(|e| e.to_string())?;
+
+    unsafe {
+        // COM may already be initialized on this thread with another model, which is fine
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+        let factory: IWICImagingFactory =
+            CoCreateInstance(&CLSID_WICImagingFactory, None, CLSCTX_INPROC_SERVER)
+                .map_err(|e| e.to_string())?;
+
+        let mut upload = Vec::new();
+        for (pts, jpeg) in &frames {
+            let pixels = decode_jpeg(&factory, jpeg)?;
+            if pixels.len() != (width * height * 4) as usize {
+                continue; // Captured before a resolution change
+            }
+            crate::flip_rows_into(&pixels, width, height, &mut upload);
+            encoder
+                .send_frame_buffer(&upload, *pts)
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    encoder.finish().map_err(|e| e.to_string())?;
+
+    let first = frames.first().map_or(0, |(pts, _)| *pts);
+    let last = frames.last().map_or(0, |(pts, _)| *pts);
+    Ok(SavedReplay {
+        frame_count: frames.len() as u32,
+        duration: (last - first) as f64 / crate::HNS_PER_SEC as f64
+            + 1.0 / crate::TARGET_FPS as f64,
+    })
+}
@@ -0,0 +1,124 @@
+// Single-frame capture of a monitor (or part of it), as PNG or raw pixels
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc;
+use std::time::Duration;
+use windows_capture::{
+    capture::{Context, GraphicsCaptureApiHandler},
+    encoder::ImageEncoder,
+    frame::{Frame, ImageFormat},
+    graphics_capture_api::InternalCaptureControl,
+    monitor::Monitor,
+    settings::{ColorFormat, CursorCaptureSettings, DrawBorderSettings, Settings},
+};
+
+const FRAME_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Rectangle in pixels, relative to the monitor's top-left corner
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct CaptureRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// One captured frame exactly as Windows Graphics Capture delivered it: top-down 8-bit BGRA, full
+// range sRGB, before any scaling or encoder color conversion. width * height * 4 bytes, so about
+// 8 MB at 1080p and 33 MB at 4K.
+#[derive(Debug, Clone, Serialize)]
+pub struct RawFrame {
+    pub width: u32,
+    pub height: u32,
+    pub bgra: Vec<u8>,
+}
+
+struct ScreenshotFlags {
+    region: Option<CaptureRegion>,
+    result: mpsc::Sender<Result<RawFrame, String>>,
+}
+
+struct ScreenshotHandler {
+    flags: ScreenshotFlags,
+}
+
+impl ScreenshotHandler {
+    fn copy_frame(&self, frame: &mut Frame) -> Result<RawFrame, String> {
+        let (frame_width, frame_height) = (frame.width(), frame.height());
+        let mut buffer = match self.flags.region {
+            Some(region) => {
+                // Clamp to the frame so a region hanging off the edge still captures what's visible
+                let end_x = region.x.saturating_add(region.width).min(frame_width);
+                let end_y = region.y.saturating_add(region.height).min(frame_height);
+                if region.x >= end_x || region.y >= end_y {
+                    return Err(format!(
+                        "Region {:?} is outside the {}x{} monitor",
+                        region, frame_width, frame_height
+                    ));
+                }
+                frame.buffer_crop(region.x, region.y, end_x, end_y)
+            }
+            None => frame.buffer(),
+        }
+        .map_err(|e| e.to_string())?;
+
+        let (width, height) = (buffer.width(), buffer.height());
+        let pixels = buffer.as_nopadding_buffer().map_err(|e| e.to_string())?;
+        Ok(RawFrame {
+            width,
+            height,
+            bgra: pixels.to_vec(),
+        })
+    }
+}
+
+impl GraphicsCaptureApiHandler for ScreenshotHandler {
+    type Flags = ScreenshotFlags;
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn new(ctx: Context<Self::Flags>) -> Result<Self, Self::Error> {
+        Ok(Self { flags: ctx.flags })
+    }
+
+    fn on_frame_arrived(
+        &mut self,
+        frame: &mut Frame,
+        capture_control: InternalCaptureControl,
+    ) -> Result<(), Self::Error> {
+        let _ = self.flags.result.send(self.copy_frame(frame));
+        capture_control.stop();
+        Ok(())
+    }
+}
+
+// Grab one frame like a recording would (same monitor, no cursor)
+pub fn capture_raw(monitor: Monitor, region: Option<CaptureRegion>) -> Result<RawFrame, String> {
+    let (sender, receiver) = mpsc::channel();
+    let settings = Settings::new(
+        monitor,
+        CursorCaptureSettings::WithoutCursor,
+        DrawBorderSettings::WithoutBorder,
+        ColorFormat::Bgra8,
+        ScreenshotFlags {
+            region,
+            result: sender,
+        },
+    );
+    let control = ScreenshotHandler::start_free_threaded(settings).map_err(|e| e.to_string())?;
+
+    let result = receiver
+        .recv_timeout(FRAME_TIMEOUT)
+        .map_err(|_| "No frame arrived from the monitor".to_string());
+    if let Err(e) = control.stop() {
+        warn!("Failed to stop screenshot capture: {}", e);
+    }
+    result?
+}
+
+// capture_raw as PNG bytes
+pub fn capture_png(monitor: Monitor, region: Option<CaptureRegion>) -> Result<Vec<u8>, String> {
+    let frame = capture_raw(monitor, region)?;
+    ImageEncoder::new(ImageFormat::Png, ColorFormat::Bgra8)
+        .encode(&frame.bgra, frame.width, frame.height)
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,78 @@
+// Recordings made since the app started, so the UI can show a history and offer to release the
+// files it no longer needs instead of leaving them to pile up in the temp directory
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+struct Recorded {
+    path: String,
+    started_at: u64,
+    duration: Option<f64>, // Set once stop_recording finalized the file
+}
+
+static RECORDINGS: Mutex<Vec<Recorded>> = Mutex::new(Vec::new()); // Oldest first
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordingEntry {
+    pub path: String,
+    pub size: u64, // Current file size; still growing while in progress, 0 if the file is gone
+    pub duration: Option<f64>, // None while in progress, or if the recording failed
+    pub in_progress: bool,
+    pub served: bool,    // A video server or the custom protocol is serving the file
+    pub started_at: u64, // Unix time in milliseconds
+}
+
+// A capture session started writing `path`
+pub fn add(path: &str) {
+    let started_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default();
+    if let Ok(mut recordings) = RECORDINGS.lock() {
+        recordings.retain(|recording| recording.path != path);
+        recordings.push(Recorded {
+            path: path.to_string(),
+            started_at,
+            duration: None,
+        });
+    }
+}
+
+// stop_recording finalized `path`
+pub fn finish(path: &str, duration: f64) {
+    if let Ok(mut recordings) = RECORDINGS.lock() {
+        if let Some(recording) = recordings
+            .iter_mut()
+            .find(|recording| recording.path == path)
+        {
+            recording.duration = Some(duration);
+        }
+    }
+}
+
+// The file was released (deleted)
+pub fn remove(path: &str) {
+    if let Ok(mut recordings) = RECORDINGS.lock() {
+        recordings.retain(|recording| recording.path != path);
+    }
+}
+
+// `recording` is the path being recorded right now, if any
+pub fn list(recording: Option<&str>, is_served: impl Fn(&str) -> bool) -> Vec<RecordingEntry> {
+    let Ok(recordings) = RECORDINGS.lock() else {
+        return Vec::new();
+    };
+    recordings
+        .iter()
+        .map(|entry| RecordingEntry {
+            path: entry.path.clone(),
+            size: std::fs::metadata(&entry.path)
+                .map(|metadata| metadata.len())
+                .unwrap_or(0),
+            duration: entry.duration,
+            in_progress: recording == Some(entry.path.as_str()),
+            served: is_served(&entry.path),
+            started_at: entry.started_at,
+        })
+        .collect()
+}
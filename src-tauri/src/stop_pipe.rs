@@ -0,0 +1,113 @@
+// Named pipe that lets another process stop the recording, e.g. a CI script ending the recording of
+// a UI test run once the test finishes. A client writes "stop <token>" to PIPE_NAME and reads back
+// one line: "ok <output path>" or "error <reason>". Only local clients are accepted, and the pipe
+// exists only while a recording that enabled it is running.
+use crate::tokens_match;
+use log::{info, warn};
+use std::fs::OpenOptions;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use windows::core::HSTRING;
+use windows::Win32::Foundation::{CloseHandle, GetLastError, ERROR_PIPE_CONNECTED, HANDLE};
+use windows::Win32::Storage::FileSystem::{
+    FlushFileBuffers, ReadFile, WriteFile, FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_DUPLEX,
+};
+use windows::Win32::System::Pipes::{
+    ConnectNamedPipe, CreateNamedPipeW, DisconnectNamedPipe, PIPE_READMODE_BYTE,
+    PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+};
+
+pub const PIPE_NAME: &str = r"\\.\pipe\screen_demo_stop";
+const BUFFER_SIZE: u32 = 512;
+
+static GENERATION: AtomicU64 = AtomicU64::new(0); // Bumped by stop; the listener exits on a change
+
+// One exchange with a connected client. Returns true once the recording was stopped.
+unsafe fn serve_client(
+    pipe: HANDLE,
+    token: &str,
+    on_stop: &impl Fn() -> Result<String, String>,
+) -> bool {
+    let mut buffer = [0u8; BUFFER_SIZE as usize];
+    let mut read = 0u32;
+    if !ReadFile(
+        pipe,
+        Some(buffer.as_mut_ptr() as *mut _),
+        BUFFER_SIZE,
+        Some(&mut read as *mut u32),
+        None,
+    )
+    .as_bool()
+    {
+        return false;
+    }
+    let request = String::from_utf8_lossy(&buffer[..read as usize]);
+
+    let (reply, stopped) = match request.trim().split_once(' ') {
+        Some(("stop", client_token)) if tokens_match(client_token, token) => match on_stop() {
+            Ok(path) => (format!("ok {}\n", path), true),
+            Err(e) => (format!("error {}\n", e), false),
+        },
+        Some(("stop", _)) => {
+            warn!("Stop pipe: rejected a request with the wrong token");
+            ("error invalid token\n".to_string(), false)
+        }
+        _ => ("error expected \"stop <token>\"\n".to_string(), false),
+    };
+    let _ = WriteFile(pipe, Some(reply.as_bytes()), None, None);
+    let _ = FlushFileBuffers(pipe);
+    stopped
+}
+
+// Listen on PIPE_NAME until stop is called or a client stopped the recording. `on_stop` stops it
+// and returns the output path.
+pub fn start(
+    token: String,
+    on_stop: impl Fn() -> Result<String, String> + Send + 'static,
+) -> Result<(), String> {
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    // Fails if another instance of the app already owns the name
+    let pipe = unsafe {
+        CreateNamedPipeW(
+            &HSTRING::from(PIPE_NAME),
+            PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE,
+            PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+            1,
+            BUFFER_SIZE,
+            BUFFER_SIZE,
+            0,
+            None,
+        )
+    };
+    if pipe.is_invalid() {
+        let error = unsafe { GetLastError() };
+        return Err(format!("Failed to create {}: {:?}", PIPE_NAME, error));
+    }
+    info!("Listening for stop requests on {}", PIPE_NAME);
+
+    thread::spawn(move || unsafe {
+        loop {
+            let connected =
+                ConnectNamedPipe(pipe, None).as_bool() || GetLastError() == ERROR_PIPE_CONNECTED;
+            if GENERATION.load(Ordering::SeqCst) != generation {
+                break;
+            }
+            if connected && serve_client(pipe, &token, &on_stop) {
+                break;
+            }
+            let _ = DisconnectNamedPipe(pipe);
+        }
+        let _ = DisconnectNamedPipe(pipe);
+        let _ = CloseHandle(pipe);
+        info!("Stopped listening on {}", PIPE_NAME);
+    });
+    Ok(())
+}
+
+// Close the pipe, if it's listening
+pub fn stop() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+    // Wake a listener blocked in ConnectNamedPipe so it sees the new generation. This fails
+    // harmlessly when nothing is listening or a client is being served.
+    let _ = OpenOptions::new().read(true).write(true).open(PIPE_NAME);
+}
@@ -0,0 +1,77 @@
+// Cropping the Windows taskbar out of a recording (RecordingOptions.exclude_taskbar), for demos
+// without the clock and pinned apps along one edge
+use log::info;
+use windows::Win32::Foundation::RECT;
+use windows::Win32::UI::Shell::{
+    SHAppBarMessage, ABE_BOTTOM, ABE_LEFT, ABE_RIGHT, ABE_TOP, ABM_GETSTATE, ABM_GETTASKBARPOS,
+    ABS_AUTOHIDE, APPBARDATA,
+};
+
+// Part of a monitor's frame to record, in its pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// The part of `monitor` (desktop coordinates) not covered by the taskbar. None when the taskbar
+// hides itself or isn't on that monitor; only the primary taskbar is reported by Windows, so
+// taskbars shown on secondary monitors are left in.
+pub fn crop_for_monitor(monitor: RECT) -> Option<Crop> {
+    let mut data = APPBARDATA {
+        cbSize: std::mem::size_of::<APPBARDATA>() as u32,
+        ..Default::default()
+    };
+    unsafe {
+        if SHAppBarMessage(ABM_GETSTATE, &mut data) as u32 & ABS_AUTOHIDE != 0 {
+            return None;
+        }
+        if SHAppBarMessage(ABM_GETTASKBARPOS, &mut data) == 0 {
+            return None;
+        }
+    }
+    let taskbar = data.rc;
+    let overlaps = taskbar.left < monitor.right
+        && taskbar.right > monitor.left
+        && taskbar.top < monitor.bottom
+        && taskbar.bottom > monitor.top;
+    if !overlaps {
+        return None;
+    }
+
+    let (mut left, mut top, mut right, mut bottom) =
+        (monitor.left, monitor.top, monitor.right, monitor.bottom);
+    match data.uEdge {
+        ABE_BOTTOM => bottom = taskbar.top,
+        ABE_TOP => top = taskbar.bottom,
+        ABE_LEFT => left = taskbar.right,
+        ABE_RIGHT => right = taskbar.left,
+        _ => return None,
+    }
+    // Encoders need even dimensions
+    let width = ((right - left).max(0) as u32) & !1;
+    let height = ((bottom - top).max(0) as u32) & !1;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let crop = Crop {
+        x: (left - monitor.left) as u32,
+        y: (top - monitor.top) as u32,
+        width,
+        height,
+    };
+    info!("Cropping the taskbar out: {:?}", crop);
+    Some(crop)
+}
+
+// Copy the cropped part of a top-down BGRA frame `width` pixels wide into `out`
+pub fn crop_into(pixels: &[u8], width: u32, crop: Crop, out: &mut Vec<u8>) {
+    out.clear();
+    let row_bytes = crop.width as usize * 4;
+    for row in crop.y..crop.y + crop.height {
+        let start = ((row * width + crop.x) * 4) as usize;
+        out.extend_from_slice(&pixels[start..start + row_bytes]);
+    }
+}
@@ -0,0 +1,132 @@
+// Elapsed or wall-clock time burned into a corner of every frame (RecordingOptions.show_timestamp),
+// drawn with a built-in 5x7 bitmap font so there is no per-frame text layout
+use crate::media::PipPosition;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use windows::Win32::System::SystemInformation::GetLocalTime;
+
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+const ADVANCE: u32 = GLYPH_WIDTH + 1; // One blank column between characters
+const PADDING: u32 = 2; // Around the text, in font pixels
+const MAX_SCALE: u32 = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampFormat {
+    #[default]
+    Elapsed, // Since the capture started, HH:MM:SS.mmm
+    WallClock, // Local time of day, HH:MM:SS.mmm
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimestampStyle {
+    pub position: PipPosition,
+    pub format: TimestampFormat,
+    pub scale: u32, // Screen pixels per font pixel; 3 makes digits 21px tall
+}
+
+impl Default for TimestampStyle {
+    fn default() -> Self {
+        Self {
+            position: PipPosition::TopRight,
+            format: TimestampFormat::Elapsed,
+            scale: 3,
+        }
+    }
+}
+
+// Rows of each glyph, top first, the leftmost pixel in bit 4
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT as usize] {
+    match c {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        _ => [0; GLYPH_HEIGHT as usize],
+    }
+}
+
+// The text to draw for a frame `elapsed` after the capture started
+pub fn text(format: TimestampFormat, elapsed: Duration) -> String {
+    match format {
+        TimestampFormat::Elapsed => {
+            let secs = elapsed.as_secs();
+            format!(
+                "{:02}:{:02}:{:02}.{:03}",
+                secs / 3_600,
+                secs % 3_600 / 60,
+                secs % 60,
+                elapsed.subsec_millis()
+            )
+        }
+        TimestampFormat::WallClock => {
+            let now = unsafe { GetLocalTime() };
+            format!(
+                "{:02}:{:02}:{:02}.{:03}",
+                now.wHour, now.wMinute, now.wSecond, now.wMilliseconds
+            )
+        }
+    }
+}
+
+// Draw `text` in white on a darkened box into a top-down BGRA frame. Skipped if the frame is too
+// small to fit it.
+pub fn draw(pixels: &mut [u8], width: u32, height: u32, text: &str, style: &TimestampStyle) {
+    let scale = style.scale.clamp(1, MAX_SCALE);
+    let chars = text.chars().count() as u32;
+    let box_width = ((chars * ADVANCE).saturating_sub(1) + PADDING * 2) * scale;
+    let box_height = (GLYPH_HEIGHT + PADDING * 2) * scale;
+    let margin = width / 50;
+    if box_width + margin * 2 > width || box_height + margin * 2 > height {
+        return;
+    }
+
+    let x = match style.position {
+        PipPosition::TopLeft | PipPosition::BottomLeft => margin,
+        PipPosition::TopRight | PipPosition::BottomRight => width - margin - box_width,
+    };
+    let y = match style.position {
+        PipPosition::TopLeft | PipPosition::TopRight => margin,
+        PipPosition::BottomLeft | PipPosition::BottomRight => height - margin - box_height,
+    };
+
+    // Halve the background so the digits read on light and dark content alike
+    for row in y..y + box_height {
+        let start = ((row * width + x) * 4) as usize;
+        for pixel in pixels[start..start + box_width as usize * 4].chunks_exact_mut(4) {
+            for channel in &mut pixel[..3] {
+                *channel /= 2;
+            }
+        }
+    }
+
+    let top = y + PADDING * scale;
+    for (index, c) in text.chars().enumerate() {
+        let left = x + (PADDING + index as u32 * ADVANCE) * scale;
+        for (glyph_row, bits) in glyph(c).iter().enumerate() {
+            for glyph_col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - glyph_col)) == 0 {
+                    continue;
+                }
+                let block_x = left + glyph_col * scale;
+                let block_y = top + glyph_row as u32 * scale;
+                for row in block_y..block_y + scale {
+                    let start = ((row * width + block_x) * 4) as usize;
+                    for pixel in pixels[start..start + scale as usize * 4].chunks_exact_mut(4) {
+                        pixel[..3].fill(255);
+                    }
+                }
+            }
+        }
+    }
+}
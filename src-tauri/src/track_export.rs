@@ -0,0 +1,167 @@
+// Mouse track export for analysis outside the app: every sample and click, as JSON or CSV
+use crate::{ClickEvent, MousePosition};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackFormat {
+    Json,
+    Csv,
+}
+
+const CSV_HEADER: &str = "kind,timestamp,time,x,y,is_clicked,cursor_type,click_type,velocity,acceleration,on_screen,monitor,button,pressed";
+
+// UTC ISO 8601 with milliseconds, e.g. 2024-03-01T12:30:05.250Z
+fn iso_8601(time: SystemTime) -> String {
+    let elapsed = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = elapsed.as_secs();
+    let (days, day_secs) = ((secs / 86_400) as i64, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's civil_from_days)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        day_secs / 3_600,
+        day_secs % 3_600 / 60,
+        day_secs % 60,
+        elapsed.subsec_millis()
+    )
+}
+
+// Wall-clock time of a track timestamp, when the recording's start time is known
+fn wall_time(start: Option<SystemTime>, timestamp: f64) -> Option<String> {
+    let start = start?;
+    Some(iso_8601(
+        start + Duration::from_secs_f64(timestamp.max(0.0)),
+    ))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn csv_optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+// Rows for both samples and clicks, ordered by timestamp
+fn csv_rows(
+    positions: &[MousePosition],
+    clicks: &[ClickEvent],
+    start: Option<SystemTime>,
+) -> Vec<String> {
+    let mut rows: Vec<(f64, String)> = positions
+        .iter()
+        .map(|p| {
+            let row = [
+                "position".to_string(),
+                p.timestamp.to_string(),
+                csv_optional(wall_time(start, p.timestamp)),
+                p.x.to_string(),
+                p.y.to_string(),
+                p.isClicked.to_string(),
+                csv_field(&p.cursor_type),
+                csv_optional(p.click_type.as_deref()),
+                csv_optional(p.velocity),
+                csv_optional(p.acceleration),
+                p.on_screen.to_string(),
+                csv_optional(p.monitor),
+                String::new(),
+                String::new(),
+            ];
+            (p.timestamp, row.join(","))
+        })
+        .collect();
+    rows.extend(clicks.iter().map(|click| {
+        let row = [
+            "click".to_string(),
+            click.timestamp.to_string(),
+            csv_optional(wall_time(start, click.timestamp)),
+            click.x.to_string(),
+            click.y.to_string(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            String::new(),
+            csv_field(&click.button),
+            click.pressed.to_string(),
+        ];
+        (click.timestamp, row.join(","))
+    }));
+    rows.sort_by(|a, b| a.0.total_cmp(&b.0));
+    rows.into_iter().map(|(_, row)| row).collect()
+}
+
+// Serialize each entry, adding its wall-clock "time" when the start is known
+fn json_entries<T: Serialize>(
+    entries: &[T],
+    timestamp: impl Fn(&T) -> f64,
+    start: Option<SystemTime>,
+) -> Result<Vec<serde_json::Value>, String> {
+    entries
+        .iter()
+        .map(|entry| {
+            let mut value = serde_json::to_value(entry).map_err(|e| e.to_string())?;
+            if let (Some(time), Some(object)) =
+                (wall_time(start, timestamp(entry)), value.as_object_mut())
+            {
+                object.insert("time".to_string(), serde_json::Value::String(time));
+            }
+            Ok(value)
+        })
+        .collect()
+}
+
+// Write the track to `path`. Timestamps are seconds on the video's timeline; with `start` (the
+// wall-clock time of the first frame) each entry also gets an ISO 8601 time.
+pub fn write_track(
+    path: &Path,
+    format: TrackFormat,
+    positions: &[MousePosition],
+    clicks: &[ClickEvent],
+    start: Option<SystemTime>,
+) -> Result<(), String> {
+    let file =
+        File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    let mut writer = BufWriter::new(file);
+
+    match format {
+        TrackFormat::Json => {
+            let document = serde_json::json!({
+                "positions": json_entries(positions, |p| p.timestamp, start)?,
+                "clicks": json_entries(clicks, |click| click.timestamp, start)?,
+            });
+            serde_json::to_writer_pretty(&mut writer, &document).map_err(|e| e.to_string())?;
+        }
+        TrackFormat::Csv => {
+            writeln!(writer, "{}", CSV_HEADER).map_err(|e| e.to_string())?;
+            for row in csv_rows(positions, clicks, start) {
+                writeln!(writer, "{}", row).map_err(|e| e.to_string())?;
+            }
+        }
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
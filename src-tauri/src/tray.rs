@@ -0,0 +1,107 @@
+// Tray icon showing whether a recording is running, so a take doesn't run on unnoticed while the app
+// window is minimized or excluded from the capture. Its menu stops the recording the same way the
+// stop pipe does, or brings the window back. There is no paused state to show: recordings can't be
+// paused yet.
+use log::warn;
+use std::sync::Mutex;
+use std::thread;
+use tauri::image::Image;
+use tauri::menu::{Menu, MenuItem};
+use tauri::tray::TrayIconBuilder;
+use tauri::{AppHandle, Wry};
+
+const TRAY_ID: &str = "main";
+const STOP_ID: &str = "stop";
+const SHOW_ID: &str = "show";
+const IDLE_TOOLTIP: &str = "Screen Demo";
+const RECORDING_TOOLTIP: &str = "Screen Demo - recording";
+const ICON_SIZE: u32 = 32;
+
+static STOP_ITEM: Mutex<Option<MenuItem<Wry>>> = Mutex::new(None); // Enabled only while recording
+
+// A red dot, shown instead of the app icon while recording
+fn recording_icon() -> Image<'static> {
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 * 0.4;
+    let mut rgba = Vec::with_capacity((ICON_SIZE * ICON_SIZE * 4) as usize);
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let distance = (x as f32 - center).hypot(y as f32 - center);
+            // One pixel of falloff so the edge isn't jagged
+            let alpha = (radius - distance + 0.5).clamp(0.0, 1.0);
+            rgba.extend_from_slice(&[220, 38, 38, (alpha * 255.0) as u8]);
+        }
+    }
+    Image::new_owned(rgba, ICON_SIZE, ICON_SIZE)
+}
+
+pub fn create(app: &AppHandle) -> tauri::Result<()> {
+    let stop = MenuItem::with_id(app, STOP_ID, "Stop recording", false, None::<&str>)?;
+    let show = MenuItem::with_id(app, SHOW_ID, "Show Screen Demo", true, None::<&str>)?;
+    let menu = Menu::with_items(app, &[&stop, &show])?;
+
+    let mut builder = TrayIconBuilder::with_id(TRAY_ID)
+        .tooltip(IDLE_TOOLTIP)
+        .menu(&menu)
+        .on_menu_event(|_app, event| match event.id().as_ref() {
+            // Stopping blocks until the file is finalized; keep it off the main thread
+            STOP_ID => {
+                thread::spawn(|| {
+                    if let Err(e) = crate::stop_externally("the tray menu") {
+                        warn!("Failed to stop the recording from the tray: {}", e);
+                    }
+                });
+            }
+            SHOW_ID => {
+                if let Some(window) = crate::main_window() {
+                    let result = window
+                        .show()
+                        .and_then(|_| window.unminimize())
+                        .and_then(|_| window.set_focus());
+                    if let Err(e) = result {
+                        warn!("Failed to show the app window: {}", e);
+                    }
+                }
+            }
+            _ => {}
+        });
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder.build(app)?;
+
+    if let Ok(mut item) = STOP_ITEM.lock() {
+        *item = Some(stop);
+    }
+    Ok(())
+}
+
+// Follow RECORDING: the red dot and an enabled stop item while it's set, the app icon otherwise
+pub fn set_recording(recording: bool) {
+    let Some(app) = crate::APP_HANDLE
+        .lock()
+        .ok()
+        .and_then(|handle| handle.clone())
+    else {
+        return;
+    };
+    let Some(tray) = app.tray_by_id(TRAY_ID) else {
+        return;
+    };
+    let (icon, tooltip) = if recording {
+        (Some(recording_icon()), RECORDING_TOOLTIP)
+    } else {
+        (app.default_window_icon().cloned(), IDLE_TOOLTIP)
+    };
+    if let Err(e) = tray
+        .set_icon(icon)
+        .and_then(|()| tray.set_tooltip(Some(tooltip)))
+    {
+        warn!("Failed to update the tray icon: {}", e);
+    }
+    if let Some(stop) = STOP_ITEM.lock().ok().and_then(|item| item.clone()) {
+        if let Err(e) = stop.set_enabled(recording) {
+            warn!("Failed to update the tray menu: {}", e);
+        }
+    }
+}
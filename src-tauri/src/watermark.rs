@@ -0,0 +1,238 @@
+// Watermark overlay: rendered once to a BGRA bitmap, then blended into every frame
+use crate::media::PipPosition;
+use serde::{Deserialize, Serialize};
+use windows::core::HSTRING;
+use windows::Win32::Foundation::{COLORREF, GENERIC_READ, HANDLE, SIZE};
+use windows::Win32::Graphics::Gdi::{
+    CreateCompatibleDC, CreateDIBSection, CreateFontW, DeleteDC, DeleteObject, GdiFlush,
+    GetTextExtentPoint32W, SelectObject, SetBkMode, SetTextColor, TextOutW, ANTIALIASED_QUALITY,
+    BITMAPINFO, BITMAPINFOHEADER, BI_RGB, CLIP_DEFAULT_PRECIS, DEFAULT_CHARSET, DEFAULT_PITCH,
+    DIB_RGB_COLORS, FW_BOLD, HDC, OUT_DEFAULT_PRECIS, TRANSPARENT,
+};
+use windows::Win32::Graphics::Imaging::{
+    CLSID_WICImagingFactory, GUID_WICPixelFormat32bppBGRA, IWICImagingFactory,
+    WICConvertBitmapSource, WICDecodeMetadataCacheOnDemand,
+};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED,
+};
+
+const SHADOW_OFFSET: i32 = 2;
+const SHADOW_STRENGTH: f32 = 0.6;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatermarkContent {
+    Text(String),
+    Image(String), // Path to a PNG (anything WIC can decode works)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Watermark {
+    pub content: WatermarkContent,
+    #[serde(default)]
+    pub position: PipPosition,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default = "default_text_size")]
+    pub text_size: u32, // Pixel height of text watermarks
+}
+
+fn default_opacity() -> f32 {
+    0.8
+}
+
+fn default_text_size() -> u32 {
+    28
+}
+
+// Top-down BGRA with straight alpha
+pub struct WatermarkBitmap {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+pub fn render_watermark(watermark: &Watermark) -> Result<WatermarkBitmap, String> {
+    match &watermark.content {
+        WatermarkContent::Text(text) => unsafe { render_text(text, watermark.text_size) },
+        WatermarkContent::Image(path) => unsafe { load_image(path) },
+    }
+}
+
+// Draw white text with a drop shadow. GDI doesn't write alpha, so the text is drawn white on
+// black and the red channel is used as coverage.
+unsafe fn render_text(text: &str, size: u32) -> Result<WatermarkBitmap, String> {
+    let wide: Vec<u16> = text.encode_utf16().collect();
+    if wide.is_empty() {
+        return Err("Watermark text is empty".to_string());
+    }
+
+    let dc = CreateCompatibleDC(HDC::default());
+    let font = CreateFontW(
+        -(size.max(1) as i32),
+        0,
+        0,
+        0,
+        FW_BOLD.0 as i32,
+        0,
+        0,
+        0,
+        DEFAULT_CHARSET.0 as u32,
+        OUT_DEFAULT_PRECIS.0 as u32,
+        CLIP_DEFAULT_PRECIS.0 as u32,
+        ANTIALIASED_QUALITY.0 as u32,
+        DEFAULT_PITCH.0 as u32,
+        &HSTRING::from("Segoe UI"),
+    );
+    let old_font = SelectObject(dc, font);
+
+    let mut extent = SIZE::default();
+    GetTextExtentPoint32W(dc, &wide, &mut extent);
+    let padding = (size / 4) as i32 + SHADOW_OFFSET;
+    let width = (extent.cx + padding * 2).max(1);
+    let height = (extent.cy + padding * 2).max(1);
+
+    let info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+            biWidth: width,
+            biHeight: -height, // Top-down
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: BI_RGB.0 as u32,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut bits: *mut std::ffi::c_void = std::ptr::null_mut();
+    let bitmap = match CreateDIBSection(dc, &info, DIB_RGB_COLORS, &mut bits, HANDLE::default(), 0)
+    {
+        Ok(bitmap) if !bits.is_null() => bitmap,
+        _ => {
+            SelectObject(dc, old_font);
+            DeleteObject(font);
+            DeleteDC(dc);
+            return Err("Failed to create watermark bitmap".to_string());
+        }
+    };
+    let old_bitmap = SelectObject(dc, bitmap);
+
+    SetBkMode(dc, TRANSPARENT);
+    SetTextColor(dc, COLORREF(0x00FF_FFFF));
+    TextOutW(dc, padding - SHADOW_OFFSET, padding - SHADOW_OFFSET, &wide);
+    GdiFlush();
+
+    let (w, h) = (width as usize, height as usize);
+    let pixels = std::slice::from_raw_parts(bits as *const u8, w * h * 4);
+    let coverage = |x: i32, y: i32| -> f32 {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return 0.0;
+        }
+        pixels[(y as usize * w + x as usize) * 4 + 2] as f32 / 255.0
+    };
+
+    let mut data = vec![0u8; w * h * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let text = coverage(x, y);
+            let shadow = coverage(x - SHADOW_OFFSET, y - SHADOW_OFFSET) * SHADOW_STRENGTH;
+            let alpha = text + shadow * (1.0 - text);
+            let value = if alpha > 0.0 { text / alpha } else { 0.0 };
+            let i = (y as usize * w + x as usize) * 4;
+            let channel = (value * 255.0) as u8;
+            data[i] = channel;
+            data[i + 1] = channel;
+            data[i + 2] = channel;
+            data[i + 3] = (alpha * 255.0) as u8;
+        }
+    }
+
+    SelectObject(dc, old_bitmap);
+    SelectObject(dc, old_font);
+    DeleteObject(bitmap);
+    DeleteObject(font);
+    DeleteDC(dc);
+
+    Ok(WatermarkBitmap {
+        width: width as u32,
+        height: height as u32,
+        data,
+    })
+}
+
+unsafe fn load_image(path: &str) -> Result<WatermarkBitmap, String> {
+    // COM may already be initialized on this thread with another model, which is fine
+    let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+
+    let factory: IWICImagingFactory =
+        CoCreateInstance(&CLSID_WICImagingFactory, None, CLSCTX_INPROC_SERVER)
+            .map_err(|e| e.to_string())?;
+    let decoder = factory
+        .CreateDecoderFromFilename(
+            &HSTRING::from(path),
+            None,
+            GENERIC_READ,
+            WICDecodeMetadataCacheOnDemand,
+        )
+        .map_err(|e| format!("Failed to open watermark image {}: {}", path, e))?;
+    let frame = decoder.GetFrame(0).map_err(|e| e.to_string())?;
+    let source =
+        WICConvertBitmapSource(&GUID_WICPixelFormat32bppBGRA, &frame).map_err(|e| e.to_string())?;
+
+    let mut width = 0u32;
+    let mut height = 0u32;
+    source
+        .GetSize(&mut width, &mut height)
+        .map_err(|e| e.to_string())?;
+    let mut data = vec![0u8; (width * height * 4) as usize];
+    source
+        .CopyPixels(std::ptr::null(), width * 4, &mut data)
+        .map_err(|e| e.to_string())?;
+
+    Ok(WatermarkBitmap {
+        width,
+        height,
+        data,
+    })
+}
+
+// Alpha-blend the watermark into a corner of a top-down BGRA frame
+pub fn composite_watermark(
+    pixels: &mut [u8],
+    width: u32,
+    height: u32,
+    bitmap: &WatermarkBitmap,
+    position: PipPosition,
+    opacity: f32,
+) {
+    let margin = width / 50;
+    let draw_width = bitmap.width.min(width.saturating_sub(margin * 2));
+    let draw_height = bitmap.height.min(height.saturating_sub(margin * 2));
+
+    let x = match position {
+        PipPosition::TopLeft | PipPosition::BottomLeft => margin,
+        PipPosition::TopRight | PipPosition::BottomRight => width - margin - draw_width,
+    };
+    let y = match position {
+        PipPosition::TopLeft | PipPosition::TopRight => margin,
+        PipPosition::BottomLeft | PipPosition::BottomRight => height - margin - draw_height,
+    };
+
+    let opacity = opacity.clamp(0.0, 1.0);
+    for row in 0..draw_height as usize {
+        for col in 0..draw_width as usize {
+            let s = (row * bitmap.width as usize + col) * 4;
+            let alpha = bitmap.data[s + 3] as f32 / 255.0 * opacity;
+            if alpha <= 0.0 {
+                continue;
+            }
+            let d = ((y as usize + row) * width as usize + x as usize + col) * 4;
+            for c in 0..3 {
+                let blended =
+                    bitmap.data[s + c] as f32 * alpha + pixels[d + c] as f32 * (1.0 - alpha);
+                pixels[d + c] = blended as u8;
+            }
+        }
+    }
+}
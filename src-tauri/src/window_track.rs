@@ -0,0 +1,130 @@
+// Which window had focus while recording (RecordingOptions.track_windows), so the editor can show
+// e.g. "now in: Chrome". Opt-in, since titles can reveal email subjects, file names and the like.
+use crate::clock;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use windows::core::PWSTR;
+use windows::Win32::Foundation::{CloseHandle, BOOL, MAX_PATH};
+use windows::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetForegroundWindow, GetWindowTextLengthW, GetWindowTextW, GetWindowThreadProcessId,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500); // Focus changes are rarely shorter
+
+static EVENTS: Mutex<Vec<WindowEvent>> = Mutex::new(Vec::new()); // Timestamps in clock::elapsed seconds
+static GENERATION: AtomicU64 = AtomicU64::new(0); // Bumped by start/stop so stale pollers exit
+
+// The foreground window from `timestamp` until the next event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowEvent {
+    pub title: String,
+    pub process: String, // Executable name, e.g. "chrome.exe"; empty if it couldn't be read
+    pub timestamp: f64,
+}
+
+unsafe fn process_name(process_id: u32) -> Option<String> {
+    let process = OpenProcess(
+        PROCESS_QUERY_LIMITED_INFORMATION,
+        BOOL::from(false),
+        process_id,
+    )
+    .ok()?;
+    let mut path = [0u16; MAX_PATH as usize];
+    let mut length = path.len() as u32;
+    let found = QueryFullProcessImageNameW(
+        process,
+        PROCESS_NAME_WIN32,
+        PWSTR(path.as_mut_ptr()),
+        &mut length,
+    )
+    .as_bool();
+    let _ = CloseHandle(process);
+    if !found {
+        return None;
+    }
+    let path = String::from_utf16_lossy(&path[..length as usize]);
+    Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+}
+
+// Title and process of the foreground window. None when no window has focus, e.g. on the lock
+// screen or while focus is changing.
+fn foreground_window() -> Option<(String, String)> {
+    unsafe {
+        let window = GetForegroundWindow();
+        if window.0 == 0 {
+            return None;
+        }
+        let mut title = vec![0u16; GetWindowTextLengthW(window).max(0) as usize + 1];
+        let length = GetWindowTextW(window, &mut title).max(0) as usize;
+        let title = String::from_utf16_lossy(&title[..length]);
+
+        let mut process_id = 0u32;
+        GetWindowThreadProcessId(window, Some(&mut process_id as *mut u32));
+        let process = process_name(process_id).unwrap_or_default();
+        Some((title, process))
+    }
+}
+
+// Poll the foreground window until stop (or the next start), recording each change. Nothing is
+// recorded until the capture has started.
+pub fn start() {
+    let generation = GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    if let Ok(mut events) = EVENTS.lock() {
+        events.clear();
+    }
+    info!("Tracking the foreground window");
+
+    thread::spawn(move || {
+        while GENERATION.load(Ordering::SeqCst) == generation {
+            let timestamp = clock::elapsed();
+            if let (Some((title, process)), Some(timestamp)) = (foreground_window(), timestamp) {
+                if let Ok(mut events) = EVENTS.lock() {
+                    // Only changes are kept; polling the same window adds nothing
+                    let unchanged = events
+                        .last()
+                        .is_some_and(|last| last.title == title && last.process == process);
+                    if !unchanged {
+                        events.push(WindowEvent {
+                            title,
+                            process,
+                            timestamp,
+                        });
+                    }
+                }
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    });
+}
+
+pub fn stop() {
+    GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+// Take the track, rebased onto the video's timeline by `offset` seconds. The window focused when
+// the video starts is kept at 0; earlier changes are dropped.
+pub fn take(offset: f64) -> Vec<WindowEvent> {
+    let events = EVENTS
+        .lock()
+        .map(|mut events| std::mem::take(&mut *events))
+        .unwrap_or_default();
+    let first_inside = events.partition_point(|event| event.timestamp <= offset);
+    events
+        .into_iter()
+        .skip(first_inside.saturating_sub(1))
+        .map(|event| WindowEvent {
+            timestamp: (event.timestamp - offset).max(0.0),
+            ..event
+        })
+        .collect()
+}
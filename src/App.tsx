@@ -4,7 +4,7 @@ import { Play, Pause, Video, StopCircle, Plus, Trash2, Search, Download, Loader2
 import "./App.css";
 import { Button } from "@/components/ui/button";
 import { videoRenderer } from '@/lib/videoRenderer';
-import { BackgroundConfig, VideoSegment, ZoomKeyframe, MousePosition, ExportOptions, Project, TextSegment } from '@/types/video';
+import { BackgroundConfig, VideoSegment, ZoomKeyframe, MousePosition, ExportOptions, Project, TextSegment, RecordingMetadata, CommandError, ClickEvent, TrailSegment, PermissionCheck, MonitorRecording, WindowEvent } from '@/types/video';
 import { videoExporter, EXPORT_PRESETS, DIMENSION_PRESETS } from '@/lib/videoExporter';
 import { createVideoController } from '@/lib/videoController';
 import logo from '@/assets/logo.svg';
@@ -12,6 +12,7 @@ import { projectManager } from '@/lib/projectManager';
 import { autoZoomGenerator } from '@/lib/autoZoom';
 import { Timeline } from '@/components/Timeline';
 import { thumbnailGenerator } from '@/lib/thumbnailGenerator';
+import { fetchMousePositions } from '@/lib/mouseTrack';
 
 // Replace the debounce utility with throttle
 const useThrottle = (callback: Function, limit: number) => {
@@ -35,8 +36,22 @@ interface MonitorInfo {
   x: number;
   y: number;
   is_primary: boolean;
+  device_id: string | null; // Stable id, can be passed as monitorId instead of the index
+  is_mirror: boolean; // Duplicates another display, hidden with excludeMirrors
+  orientation: 'landscape' | 'portrait';
+  scale_factor: number; // Display scaling, e.g. 1.5 at 150%; divide pixel values by it for CSS pixels
 }
 
+// Backend commands reject with { kind, message? }; older paths still reject with strings
+const formatError = (err: unknown): string => {
+  if (typeof err === 'string') return err;
+  if (err && typeof err === 'object' && 'kind' in err) {
+    const { kind, message } = err as CommandError;
+    return message ? `${kind}: ${message}` : kind;
+  }
+  return String(err);
+};
+
 // Add this helper function near the top of the file
 const sortMonitorsByPosition = (monitors: MonitorInfo[]) => {
   return [...monitors]
@@ -67,6 +82,7 @@ function App() {
   const [error, setError] = useState<string | null>(null);
   const [currentTime, setCurrentTime] = useState(0);
   const [duration, setDuration] = useState(0);
+  const [warmupSecs, setWarmupSecs] = useState(0); // Initial trim start, from RecordingMetadata
   const [isPlaying, setIsPlaying] = useState(false);
   const [segment, setSegment] = useState<VideoSegment | null>(null);
   const [editingKeyframeId, setEditingKeyframeId] = useState<number | null>(null);
@@ -107,6 +123,31 @@ function App() {
 
   // Add to your App component state
   const [mousePositions, setMousePositions] = useState<MousePosition[]>([]);
+  // RecordingOptions.cursor_smoothing the track was recorded with; non-zero means it's already smoothed
+  const [trackSmoothing, setTrackSmoothing] = useState(0);
+  // mousePositions smoothed by the backend at the current "Movement Smoothing", unless the recording
+  // already smoothed them; preview and export both draw this
+  const [smoothedPositions, setSmoothedPositions] = useState<MousePosition[]>([]);
+
+  useEffect(() => {
+    if (trackSmoothing > 0) {
+      setSmoothedPositions(mousePositions);
+      return;
+    }
+    let cancelled = false;
+    const smoothing = backgroundConfig.cursorSmoothness ?? 5;
+    invoke<MousePosition[]>("smooth_mouse_track", { positions: mousePositions, smoothing })
+      .then((smoothed) => {
+        if (!cancelled) setSmoothedPositions(smoothed);
+      })
+      .catch((err) => {
+        console.error("Failed to smooth mouse track:", err);
+        if (!cancelled) setSmoothedPositions(mousePositions);
+      });
+    return () => {
+      cancelled = true;
+    };
+  }, [mousePositions, trackSmoothing, backgroundConfig.cursorSmoothness]);
 
   // Add new state at the top of App component
   const [isVideoReady, setIsVideoReady] = useState(false);
@@ -141,9 +182,9 @@ function App() {
     videoControllerRef.current?.updateRenderOptions({
       segment,
       backgroundConfig,
-      mousePositions
+      mousePositions: smoothedPositions
     });
-  }, [segment, backgroundConfig, mousePositions]);
+  }, [segment, backgroundConfig, smoothedPositions]);
 
   // Remove frameCallback and simplify the animation effect
   useEffect(() => {
@@ -160,7 +201,7 @@ function App() {
         tempCanvas: tempCanvasRef.current,
         segment: segment!,
         backgroundConfig,
-        mousePositions,
+        mousePositions: smoothedPositions,
         currentTime: video.currentTime
       };
       videoRenderer.startAnimation(renderContext);
@@ -169,7 +210,7 @@ function App() {
     return () => {
       videoRenderer.stopAnimation();
     };
-  }, [segment, backgroundConfig, mousePositions]);
+  }, [segment, backgroundConfig, smoothedPositions]);
 
   // Update other places where drawFrame was used to use renderFrame instead
   useEffect(() => {
@@ -182,6 +223,17 @@ function App() {
   const [showMonitorSelect, setShowMonitorSelect] = useState(false);
   const [selectedMonitor, setSelectedMonitor] = useState<string | null>(null);
 
+  // Warn up front when capture is blocked, rather than when Record silently does nothing
+  useEffect(() => {
+    invoke<PermissionCheck>("check_capture_permissions")
+      .then((check) => {
+        if (check.status !== 'allowed') {
+          setError(`Screen capture unavailable (${check.status}): ${check.detail ?? 'unknown reason'}`);
+        }
+      })
+      .catch((err) => console.error("Failed to check capture permissions:", err));
+  }, []);
+
   // Add this function to fetch monitors
   const getMonitors = async () => {
     try {
@@ -192,7 +244,7 @@ function App() {
       return sortedMonitors;
     } catch (err) {
       console.error("Failed to get monitors:", err);
-      setError(err as string);
+      setError(formatError(err));
       return [];
     }
   };
@@ -217,7 +269,7 @@ function App() {
       }
     } catch (err) {
       console.error("Failed to handle start recording:", err);
-      setError(err as string);
+      setError(formatError(err));
     }
   }
 
@@ -227,6 +279,7 @@ function App() {
       console.log('Starting new recording, clearing states');
       // Clear all states first
       setMousePositions([]);
+      setTrackSmoothing(0);
       setIsVideoReady(false);
       setCurrentTime(0);
       setDuration(0);
@@ -265,7 +318,7 @@ function App() {
       console.log('Recording started, mouse positions cleared');
     } catch (err) {
       console.error("Failed to start recording:", err);
-      setError(err as string);
+      setError(formatError(err));
     }
   }
 
@@ -280,8 +333,10 @@ function App() {
       setLoadingProgress(0);
       setThumbnails([]);
 
-      const [videoUrl, mouseData] = await invoke<[string, MousePosition[]]>("stop_recording");
-      setMousePositions(mouseData);
+      const [videoUrl, mouseCount, metadata] = await invoke<[string, number, RecordingMetadata, ClickEvent[], TrailSegment[], MonitorRecording[], WindowEvent[]]>("stop_recording");
+      setMousePositions(await fetchMousePositions(mouseCount));
+      setTrackSmoothing(metadata.cursor_smoothing ?? 0);
+      setWarmupSecs(metadata.warmup_secs ?? 0);
 
       // Use the new centralized video loading
       const objectUrl = await videoControllerRef.current?.loadVideo({
@@ -297,7 +352,7 @@ function App() {
 
     } catch (err) {
       console.error("❌ Failed to stop recording:", err);
-      setError(err as string);
+      setError(formatError(err));
     } finally {
       setIsLoadingVideo(false);
       setLoadingProgress(0);
@@ -383,7 +438,7 @@ function App() {
         tempCanvas: tempCanvasRef.current,
         segment,
         backgroundConfig,
-        mousePositions,
+        mousePositions: smoothedPositions,
         onProgress: (progress: number) => {
           setExportProgress(progress);
         }
@@ -634,7 +689,8 @@ function App() {
         videoBlob,
         segment,
         backgroundConfig,
-        mousePositions
+        mousePositions,
+        trackSmoothing
       });
     } else {
       // Create new project
@@ -643,7 +699,8 @@ function App() {
         videoBlob,
         segment,
         backgroundConfig,
-        mousePositions
+        mousePositions,
+        trackSmoothing
       });
       setCurrentProjectId(project.id);
     }
@@ -671,6 +728,7 @@ function App() {
     setSegment(project.segment);
     setBackgroundConfig(project.backgroundConfig);
     setMousePositions(project.mousePositions);
+    setTrackSmoothing(project.trackSmoothing ?? 0);
     setShowProjectsDialog(false);
     setCurrentProjectId(projectId);
   };
@@ -728,14 +786,14 @@ function App() {
   useEffect(() => {
     if (duration > 0 && !segment) {
       const initialSegment: VideoSegment = {
-        trimStart: 0,
+        trimStart: Math.min(warmupSecs, duration),
         trimEnd: duration,
         zoomKeyframes: [],
         textSegments: []
       };
       setSegment(initialSegment);
     }
-  }, [duration, segment]);
+  }, [duration, segment, warmupSecs]);
 
   // Add this state for text segments
   const [editingTextId, setEditingTextId] = useState<string | null>(null);
@@ -1126,14 +1184,14 @@ function App() {
                     <div>
                       <label className="text-sm font-medium text-[#d7dadc] mb-2 flex justify-between">
                         <span>Movement Smoothing</span>
-                        <span className="text-[#818384]">{backgroundConfig.cursorSmoothness || 5}</span>
+                        <span className="text-[#818384]">{backgroundConfig.cursorSmoothness ?? 5}</span>
                       </label>
                       <input 
                         type="range" 
                         min="0" 
                         max="10" 
                         step="1" 
-                        value={backgroundConfig.cursorSmoothness || 5} 
+                        value={backgroundConfig.cursorSmoothness ?? 5} 
                         onChange={(e) => setBackgroundConfig(prev => ({...prev, cursorSmoothness: Number(e.target.value)}))}
                         className="w-full accent-[#0079d3]" 
                       />
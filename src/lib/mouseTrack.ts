@@ -0,0 +1,61 @@
+import { invoke } from '@tauri-apps/api/core';
+import { EncodedMouseTrack, MousePosition } from '@/types/video';
+
+const PAGE_SIZE = 10_000;
+
+function expandRuns<T>(runs: [number, T][], count: number): T[] {
+  const values: T[] = [];
+  for (const [runLength, value] of runs) {
+    for (let i = 0; i < runLength && values.length < count; i++) {
+      values.push(value);
+    }
+  }
+  return values;
+}
+
+// Inverse of the backend encoder; timestamps are accumulated in integer microseconds to avoid drift
+export function decodeMouseTrack(track: EncodedMouseTrack): MousePosition[] {
+  const dx = expandRuns(track.dx, track.count);
+  const dy = expandRuns(track.dy, track.count);
+  const dt = expandRuns(track.dt_us, track.count);
+  const clicked = expandRuns(track.clicked, track.count);
+  const cursorTypes = expandRuns(track.cursor_type, track.count);
+  const clickTypes = expandRuns(track.click_type ?? [], track.count);
+  const onScreen = expandRuns(track.on_screen ?? [], track.count);
+  const monitors = expandRuns(track.monitor ?? [], track.count);
+
+  const positions: MousePosition[] = [];
+  let x = track.base_x;
+  let y = track.base_y;
+  let offsetUs = 0;
+
+  for (let i = 0; i < track.count; i++) {
+    x += dx[i];
+    y += dy[i];
+    offsetUs += dt[i];
+    positions.push({
+      x,
+      y,
+      timestamp: track.base_timestamp + offsetUs / 1_000_000,
+      isClicked: clicked[i],
+      cursor_type: cursorTypes[i],
+      click_type: clickTypes[i] ?? null,
+      on_screen: onScreen[i] ?? true,
+      monitor: monitors[i] ?? null,
+    });
+  }
+
+  return positions;
+}
+
+// Fetch the stored mouse track in pages, so a long recording doesn't go over IPC in one message.
+// `count` is the one stop_recording returned.
+export async function fetchMousePositions(count: number): Promise<MousePosition[]> {
+  const positions: MousePosition[] = [];
+  for (let start = 0; start < count; start += PAGE_SIZE) {
+    const page = await invoke<MousePosition[]>('get_mouse_positions_range', { startIndex: start, count: PAGE_SIZE });
+    if (page.length === 0) break;
+    positions.push(...page);
+  }
+  return positions;
+}
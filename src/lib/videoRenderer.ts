@@ -50,7 +50,8 @@ export class VideoRenderer {
     easingType: 'linear' as const
   };
 
-  private smoothedPositions: MousePosition[] | null = null;
+  private interpolatedPositions: MousePosition[] | null = null;
+  private interpolatedSource: MousePosition[] | null = null; // Track interpolatedPositions came from
   private hasLoggedPositions = false;
 
   private isDraggingText = false;
@@ -70,7 +71,7 @@ export class VideoRenderer {
     console.log('[VideoRenderer] Starting animation');
     this.stopAnimation();
     this.lastDrawTime = 0;
-    this.smoothedPositions = null;
+    this.interpolatedPositions = null;
 
     const animate = () => {
       // Only animate if video is playing
@@ -550,15 +551,16 @@ export class VideoRenderer {
     );
   }
 
-  private smoothMousePositions(
+  // Catmull-Rom interpolation between samples for fluid playback. It passes through every sample,
+  // so the path stays the one smoothed by the backend (smooth_mouse_track).
+  private interpolateMousePositions(
     positions: MousePosition[],
     targetFps: number = 120
   ): MousePosition[] {
     if (positions.length < 4) return positions;
 
-    const smoothed: MousePosition[] = [];
+    const interpolated: MousePosition[] = [];
     
-    // First pass: Catmull-Rom interpolation
     for (let i = 0; i < positions.length - 3; i++) {
       const p0 = positions[i];
       const p1 = positions[i + 1];
@@ -578,77 +580,11 @@ export class VideoRenderer {
         // Use the cursor type from the nearest position
         const cursor_type = t < 0.5 ? p1.cursor_type : p2.cursor_type;
 
-        smoothed.push({ x, y, timestamp, isClicked, cursor_type });
+        interpolated.push({ x, y, timestamp, isClicked, cursor_type });
       }
     }
 
-    // Get smoothness value from background config, default to 5 if not set
-    // Scale it up to make the effect more noticeable (1-10 becomes 2-20)
-    const windowSize = ((this.backgroundConfig?.cursorSmoothness || 5) * 2) + 1;
-    
-    // Multiple smoothing passes based on smoothness value
-    const passes = Math.ceil(windowSize / 2);
-    let currentSmoothed = smoothed;
-
-    // Apply multiple passes of smoothing based on the smoothness value
-    for (let pass = 0; pass < passes; pass++) {
-        const passSmoothed: MousePosition[] = [];
-        
-        for (let i = 0; i < currentSmoothed.length; i++) {
-            let sumX = 0;
-            let sumY = 0;
-            let totalWeight = 0;
-            
-            // Keep cursor type from original position
-            const cursor_type = currentSmoothed[i].cursor_type;
-            
-            // Only smooth position, not cursor type
-            for (let j = Math.max(0, i - windowSize); j <= Math.min(currentSmoothed.length - 1, i + windowSize); j++) {
-                const distance = Math.abs(i - j);
-                const weight = Math.exp(-distance * (0.5 / windowSize));
-                
-                sumX += currentSmoothed[j].x * weight;
-                sumY += currentSmoothed[j].y * weight;
-                totalWeight += weight;
-            }
-
-            passSmoothed.push({
-                x: sumX / totalWeight,
-                y: sumY / totalWeight,
-                timestamp: currentSmoothed[i].timestamp,
-                isClicked: currentSmoothed[i].isClicked,
-                cursor_type // Preserve the cursor type
-            });
-        }
-        
-        currentSmoothed = passSmoothed;
-    }
-
-    // Apply threshold to remove tiny movements
-    // Make threshold smaller for higher smoothness values
-    const threshold = 0.5 / (windowSize / 2); // Adjust threshold based on smoothness
-    let lastSignificantPos = currentSmoothed[0];
-    const finalSmoothed = [lastSignificantPos];
-
-    for (let i = 1; i < currentSmoothed.length; i++) {
-        const current = currentSmoothed[i];
-        const distance = Math.sqrt(
-            Math.pow(current.x - lastSignificantPos.x, 2) + 
-            Math.pow(current.y - lastSignificantPos.y, 2)
-        );
-
-        if (distance > threshold || current.isClicked !== lastSignificantPos.isClicked) {
-            finalSmoothed.push(current);
-            lastSignificantPos = current;
-        } else {
-            finalSmoothed.push({
-                ...lastSignificantPos,
-                timestamp: current.timestamp
-            });
-        }
-    }
-
-    return finalSmoothed;
+    return interpolated;
   }
 
   private interpolateCursorPosition(
@@ -673,12 +609,13 @@ export class VideoRenderer {
       this.hasLoggedPositions = true;
     }
 
-    // Cache smoothed positions
-    if (!this.smoothedPositions || this.smoothedPositions.length === 0) {
-      this.smoothedPositions = this.smoothMousePositions(mousePositions);
+    // Cache interpolated positions until the track changes (e.g. a new smoothing level)
+    if (!this.interpolatedPositions || this.interpolatedPositions.length === 0 || this.interpolatedSource !== mousePositions) {
+      this.interpolatedPositions = this.interpolateMousePositions(mousePositions);
+      this.interpolatedSource = mousePositions;
     }
 
-    const positions = this.smoothedPositions;
+    const positions = this.interpolatedPositions;
     
     // Find the exact position for the current time
     const exactMatch = positions.find(pos => Math.abs(pos.timestamp - currentTime) < 0.001);
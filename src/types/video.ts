@@ -43,9 +43,374 @@ export interface BackgroundConfig {
 export interface MousePosition {
   x: number;
   y: number;
+  // Seconds on the video's timeline, authoritative: samples are only roughly 16ms apart, so place
+  // them by this, never by index
   timestamp: number;
   isClicked?: boolean;
   cursor_type?: string;
+  click_type?: 'single' | 'double' | null;
+  velocity?: number | null; // Pixels/sec, computed by the backend when recording stops
+  acceleration?: number | null; // Pixels/sec²
+  on_screen?: boolean; // False while the cursor is on another monitor; hide it rather than draw it
+  monitor?: number | null; // Index of the recorded monitor under the cursor, see MonitorRecording
+}
+
+// Sample of a mouse track earlier than the one before it, from check_mouse_timestamps
+export interface TimestampRegression {
+  index: number;
+  timestamp: number;
+  previous: number;
+}
+
+// Mouse button press or release, returned from stop_recording
+export interface ClickEvent {
+  x: number;
+  y: number;
+  button: 'left' | 'right' | 'middle' | 'other';
+  timestamp: number;
+  pressed: boolean;
+}
+
+// Options for stop_recording's cursor trail segments
+export interface TrailOptions {
+  max_gap_secs?: number;
+  min_distance_px?: number;
+}
+
+// Line between two consecutive mouse samples; from/to are [x, y]
+export interface TrailSegment {
+  from: [number, number];
+  to: [number, number];
+  t_start: number;
+  t_end: number;
+}
+
+// Delta/run-length encoded mouse track from get_mouse_positions_encoded.
+// Runs are [count, value] pairs; decode with decodeMouseTrack.
+export interface EncodedMouseTrack {
+  count: number;
+  base_x: number;
+  base_y: number;
+  base_timestamp: number;
+  dx: [number, number][];
+  dy: [number, number][];
+  dt_us: [number, number][];
+  clicked: [number, boolean][];
+  cursor_type: [number, string][];
+  click_type: [number, 'single' | 'double' | null][];
+  on_screen?: [number, boolean][];
+  monitor?: [number, number | null][];
+}
+
+// How stop_recording serves the video: local HTTP server or the in-app custom protocol
+export type VideoDelivery = 'http' | 'protocol';
+
+// Payload of the `recording-stats` event
+export interface RecordingStats {
+  elapsed: number;
+  frame_count: number;
+  encoded_frames: number;
+  dropped_frames: number;
+  failed_frames: number;
+  queue_depth: number; // Frames waiting in the frame queue, 0 without one
+  queue_overflowed: number;
+  file_size: number;
+  bitrate_bps: number;
+}
+
+// From get_memory_stats, and the payload of the `recording-memory` event
+export interface MemoryStats {
+  working_set_bytes: number;
+  mouse_positions: number;
+  mouse_positions_bytes: number; // Approximate
+  click_events: number;
+  frame_queue_bytes: number;
+  over_cap: boolean; // Above RecordingOptions.memory_cap_mb; the mouse track was thinned
+}
+
+// Payload of the `recording-degraded` event: the encoder can't keep up and capture was slowed down
+export interface RecordingDegraded {
+  failed_frames: number;
+  capture_fps: number;
+  error: string;
+}
+
+// Payload of the `resolution-changed` event: the captured frames changed size mid-recording. They
+// are letterboxed into the unchanged encode size from then on.
+export interface ResolutionChanged {
+  width: number;
+  height: number;
+  previous_width: number;
+  previous_height: number;
+  encode_width: number;
+  encode_height: number;
+  elapsed: number; // Seconds since the capture started
+}
+
+// Options and result of detect_trim_points
+export interface TrimOptions {
+  threshold?: number;
+  padding_secs?: number;
+  use_mouse_track?: boolean;
+  mouse_threshold_px?: number;
+}
+
+export interface TrimPoints {
+  start_secs: number;
+  end_secs: number;
+  duration: number;
+}
+
+// Clustering parameters for suggest_zoom_points; omitted fields use the backend defaults
+export interface ZoomOptions {
+  radius_px?: number;
+  min_dwell_secs?: number;
+  min_clicks?: number;
+  merge_gap_secs?: number;
+  lead_in_secs?: number;
+  max_suggestions?: number;
+}
+
+// Suggested zoom from suggest_zoom_points; x/y are 0-1 across the recording
+export interface ZoomSuggestion {
+  x: number;
+  y: number;
+  timestamp: number;
+  duration: number;
+  clicks: number;
+  score: number;
+}
+
+// Inactivity detection for detect_chapters; omitted fields use the backend defaults
+export interface ChapterOptions {
+  min_gap_secs?: number;
+  movement_threshold_px?: number;
+  min_chapter_secs?: number;
+}
+
+export interface Chapter {
+  title_placeholder: string;
+  start_secs: number;
+}
+
+export type VideoCodec = 'h264' | 'hevc' | 'av1';
+
+// RecordingOptions.encoder_preset; defaults to the preset implied by start_recording's quality
+export type EncoderPreset = 'quality' | 'balanced' | 'speed';
+
+// Custom preset for set_quality_presets, passed by name as start_recording's quality. Width and
+// height are both set (output size) or both null (monitor size).
+export interface QualityPreset {
+  name: string;
+  bitrate: number;
+  fps: number;
+  width: number | null;
+  height: number | null;
+}
+
+// RecordingOptions.timestamp_style, used with show_timestamp. Elapsed counts from the start of the
+// capture; wall_clock is the local time of day. Scale is screen pixels per font pixel.
+export type TimestampFormat = 'elapsed' | 'wall_clock';
+
+export interface TimestampStyle {
+  position?: 'top_left' | 'top_right' | 'bottom_left' | 'bottom_right';
+  format?: TimestampFormat;
+  scale?: number;
+}
+
+// RecordingOptions.redaction_regions and set_redaction_regions: parts of the recorded frame (after
+// any taskbar crop) hidden in the video, in pixels from its top-left corner. 'fill' paints them
+// black; 'blur' averages them in 16px blocks.
+export interface RedactionRect {
+  x: number;
+  y: number;
+  width: number;
+  height: number;
+}
+
+export type RedactionMode = 'fill' | 'blur';
+
+// File format for export_mouse_track
+export type TrackFormat = 'json' | 'csv';
+
+// Entry of get_supported_encoders
+export interface EncoderCapability {
+  codec: VideoCodec;
+  name: string;
+  hardware: boolean;
+  max_width: number | null;
+  max_height: number | null;
+  max_bitrate: number;
+}
+
+// Monitor-relative pixel rectangle for capture_screenshot
+export interface CaptureRegion {
+  x: number;
+  y: number;
+  width: number;
+  height: number;
+}
+
+// Result of test_capture
+export interface CaptureTestResult {
+  success: boolean;
+  width: number;
+  height: number;
+  frames_received: number;
+  frames_encoded: number;
+  first_frame_ms: number | null;
+  encoder_error: string | null;
+  capture_error: string | null;
+}
+
+// Result of concat_recordings
+export interface ConcatResult {
+  duration: number;
+  reencoded: boolean; // The inputs' encoder settings differed, so the stream couldn't be copied
+}
+
+// Options of export_recording. Times are seconds on the source's timeline; the fades together
+// can't be longer than the trimmed clip.
+export interface ExportOptions {
+  start_secs?: number | null;
+  end_secs?: number | null;
+  fade_in_secs?: number;
+  fade_out_secs?: number;
+  constant_frame_rate?: boolean; // Defaults to true: frames on an exact grid at the source's fps
+}
+
+export interface ExportResult {
+  duration: number;
+  frame_count: number;
+}
+
+// Result of trim_lossless: the range kept, snapped to keyframes, on the source's timeline
+export interface LosslessTrim {
+  start_secs: number;
+  end_secs: number;
+  duration: number;
+}
+
+// Result of get_raw_frame: top-down 8-bit BGRA exactly as captured, before encoding.
+// bgra has width * height * 4 entries (about 33 million at 4K).
+export interface RawFrame {
+  width: number;
+  height: number;
+  bgra: number[];
+}
+
+// Result of check_capture_permissions
+export interface PermissionCheck {
+  status: 'allowed' | 'blocked_by_policy' | 'missing_feature' | 'failed';
+  detail: string | null;
+}
+
+// Result of run_capture_benchmark
+export interface BenchmarkResult {
+  width: number;
+  height: number;
+  duration: number;
+  frames_captured: number;
+  frames_encoded: number;
+  capture_fps: number;
+  encode_fps: number;
+  dropped_frames: number;
+  avg_frame_ms: number;
+  peak_memory_mb: number;
+}
+
+// Result of probe_video
+export interface VideoProbe {
+  codec: string;
+  codec_string: string;
+  profile: string | null;
+  level: string | null;
+  width: number;
+  height: number;
+  fps: number;
+  bitrate: number;
+  has_audio: boolean;
+  duration: number;
+}
+
+// Error returned by the backend commands
+export interface CommandError {
+  kind: string;
+  message?: string;
+}
+
+export interface RecordingMetadata {
+  requested_fps: number;
+  actual_fps: number;
+  frame_count: number;
+  duration: number;
+  constant_frame_rate: boolean;
+  duplicated_frames: number;
+  skipped_frames: number;
+  failed_frames: number; // Rejected by the encoder and missing from the video
+  refresh_rate: number | null;
+  color_format: 'bgra8' | 'rgba16f'; // rgba16f is HDR capture, tone-mapped to SDR before encoding
+  color_range: 'full';
+  color_primaries: 'bt709';
+  first_frame_latency: number | null; // Seconds; already applied to the mouse track by stop_recording
+  poster: string | null; // Base64 JPEG of the first frame
+  segments: Segment[]; // From mark_segment_start/mark_segment_end; empty if none were marked
+  frame_intervals: FrameIntervalHistogram;
+  coordinate_space: CoordinateSpace; // Of the mouse track and click events
+  cursor_smoothing: number; // Already applied to the returned mouse track; 0 means unsmoothed
+  monitor_origin: [number, number]; // Subtract from 'virtual' coordinates to get video pixels
+  warmup_secs: number; // Encoder warm-up at the start (RecordingOptions.warmup_secs); trim from here
+}
+
+// 'monitor' is relative to the recorded monitor (video pixels), 'virtual' is desktop coordinates
+export type CoordinateSpace = 'monitor' | 'virtual';
+
+// Counts of the time between captured frames, for diagnosing stutter
+export interface FrameIntervalHistogram {
+  under_16ms: number;
+  from_16_to_33ms: number;
+  from_33_to_50ms: number;
+  over_50ms: number;
+}
+
+// Part of the recording to keep, in seconds on the video's timeline
+export interface Segment {
+  start: number;
+  end: number;
+}
+
+// Another monitor recorded alongside the main one (start_recording's monitorIds)
+export interface MonitorRecording {
+  monitor: number; // Matches MousePosition.monitor
+  video_url: string;
+  path: string;
+  offset_x: number; // Relative to the main monitor; subtract from mouse x/y for this video
+  offset_y: number;
+  frame_count: number;
+  duration: number;
+}
+
+// What stop_recording returns; also the payload of `recording-stopped-externally`, sent when a
+// process stopped the recording through the stop pipe (RecordingOptions.stop_pipe_token) or the
+// tray menu did. The second entry is the mouse position count; page the track with fetchMousePositions.
+export type RecordingOutput = [string, number, RecordingMetadata, ClickEvent[], TrailSegment[], MonitorRecording[], WindowEvent[]];
+
+// Entry of list_session_recordings: a recording made since the app started
+export interface RecordingEntry {
+  path: string;
+  size: number; // Bytes; still growing while in progress, 0 if the file is gone
+  duration: number | null; // Seconds; null while in progress or if the recording failed
+  in_progress: boolean;
+  served: boolean; // Still being served to the editor
+  started_at: number; // Unix time in milliseconds
+}
+
+// Focus change while recording with RecordingOptions.track_windows: this window had focus from
+// timestamp (seconds on the video's timeline) until the next event
+export interface WindowEvent {
+  title: string;
+  process: string; // Executable name, e.g. "chrome.exe"; empty if it couldn't be read
+  timestamp: number;
 }
 
 export interface VideoMetadata {
@@ -84,4 +449,5 @@ export interface Project {
   segment: VideoSegment;
   backgroundConfig: BackgroundConfig;
   mousePositions: MousePosition[];
+  trackSmoothing?: number; // RecordingMetadata.cursor_smoothing already applied to mousePositions
 } 
\ No newline at end of file